@@ -57,6 +57,13 @@ export declare class OciClient {
    * This is useful for pre-authenticating before multiple operations.
    */
   storeAuth(registry: string, auth: RegistryAuth): Promise<void>
+  /**
+   * Remove the authentication credentials stored for a registry, or a registry
+   * and repository path prefix.
+   */
+  removeAuth(registry: string): Promise<void>
+  /** Remove all the stored authentication credentials. */
+  clearAuth(): Promise<void>
   /**
    * Pull a manifest (either image or index) from the registry.
    * Returns the manifest and its digest.
@@ -98,6 +105,9 @@ export declare function basicAuth(username: string, password: string): RegistryA
 /** Create a bearer token authentication object. */
 export declare function bearerAuth(token: string): RegistryAuth
 
+/** Create an OAuth2 identity token authentication object. */
+export declare function identityTokenAuth(token: string): RegistryAuth
+
 /**
  * A x509 certificate for TLS.
  * Mirrors the native Certificate struct.
@@ -127,7 +137,10 @@ export declare const enum CertificateEncoding {
 export interface ClientConfig {
   /** Which protocol the client should use (default: Https) */
   protocol?: ClientProtocol
-  /** List of registries to exclude from HTTPS (used with HttpsExcept protocol) */
+  /**
+   * List of registries to exclude from HTTPS (used with HttpsExcept and
+   * HttpsWithHttpFallback protocols)
+   */
   httpsExceptRegistries?: Array<string>
   /** Accept invalid certificates (default: false) */
   acceptInvalidCertificates?: boolean
@@ -163,7 +176,9 @@ export declare const enum ClientProtocol {
   /** Use HTTPS (secure, default) */
   Https = 'Https',
   /** Use HTTPS except for specified registries */
-  HttpsExcept = 'HttpsExcept'
+  HttpsExcept = 'HttpsExcept',
+  /** Use HTTPS, falling back to HTTP for specified registries and localhost */
+  HttpsWithHttpFallback = 'HttpsWithHttpFallback'
 }
 
 /**
@@ -420,7 +435,7 @@ export interface RegistryAuth {
   username?: string
   /** Password for Basic auth (required when auth_type is Basic) */
   password?: string
-  /** Token for Bearer auth (required when auth_type is Bearer) */
+  /** Token for Bearer and IdentityToken auth (required when auth_type is Bearer or IdentityToken) */
   token?: string
 }
 
@@ -434,7 +449,9 @@ export declare const enum RegistryAuthType {
   /** Access the registry using HTTP Basic authentication */
   Basic = 'Basic',
   /** Access the registry using Bearer token authentication */
-  Bearer = 'Bearer'
+  Bearer = 'Bearer',
+  /** Access the registry using an OAuth2 identity token */
+  IdentityToken = 'IdentityToken'
 }
 
 /** The mediatype for a WASM image config */
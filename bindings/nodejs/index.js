@@ -576,6 +576,7 @@ module.exports.OciClient = nativeBinding.OciClient
 module.exports.anonymousAuth = nativeBinding.anonymousAuth
 module.exports.basicAuth = nativeBinding.basicAuth
 module.exports.bearerAuth = nativeBinding.bearerAuth
+module.exports.identityTokenAuth = nativeBinding.identityTokenAuth
 module.exports.CertificateEncoding = nativeBinding.CertificateEncoding
 module.exports.ClientProtocol = nativeBinding.ClientProtocol
 module.exports.IMAGE_CONFIG_MEDIA_TYPE = nativeBinding.IMAGE_CONFIG_MEDIA_TYPE
@@ -36,6 +36,8 @@ pub enum RegistryAuthType {
     Basic,
     /// Access the registry using Bearer token authentication
     Bearer,
+    /// Access the registry using an OAuth2 identity token
+    IdentityToken,
 }
 
 /// Registry authentication configuration.
@@ -48,7 +50,7 @@ pub struct RegistryAuth {
     pub username: Option<String>,
     /// Password for Basic auth (required when auth_type is Basic)
     pub password: Option<String>,
-    /// Token for Bearer auth (required when auth_type is Bearer)
+    /// Token for Bearer and IdentityToken auth (required when auth_type is Bearer or IdentityToken)
     pub token: Option<String>,
 }
 
@@ -74,6 +76,13 @@ impl RegistryAuth {
                     .ok_or_else(|| Error::from_reason("token required for Bearer auth"))?;
                 Ok(NativeRegistryAuth::Bearer(token))
             }
+            RegistryAuthType::IdentityToken => {
+                let token = self
+                    .token
+                    .clone()
+                    .ok_or_else(|| Error::from_reason("token required for IdentityToken auth"))?;
+                Ok(NativeRegistryAuth::IdentityToken(token))
+            }
         }
     }
 }
@@ -92,6 +101,8 @@ pub enum ClientProtocol {
     Https,
     /// Use HTTPS except for specified registries
     HttpsExcept,
+    /// Use HTTPS, falling back to HTTP for specified registries and localhost
+    HttpsWithHttpFallback,
 }
 
 /// Certificate encoding format.
@@ -132,8 +143,12 @@ impl Certificate {
 pub struct ClientConfig {
     /// Which protocol the client should use (default: Https)
     pub protocol: Option<ClientProtocol>,
-    /// List of registries to exclude from HTTPS (used with HttpsExcept protocol)
+    /// List of registries to exclude from HTTPS (used with HttpsExcept and
+    /// HttpsWithHttpFallback protocols)
     pub https_except_registries: Option<Vec<String>>,
+    /// Registries to which credentials may be sent over plain HTTP, "*" for all
+    /// (default: none)
+    pub allow_credentials_over_http: Option<Vec<String>>,
     /// Accept invalid certificates (default: false)
     pub accept_invalid_certificates: Option<bool>,
     /// Use monolithic push for pushing blobs (default: false)
@@ -170,9 +185,17 @@ impl ClientConfig {
                     let registries = self.https_except_registries.clone().unwrap_or_default();
                     NativeClientProtocol::HttpsExcept(registries)
                 }
+                ClientProtocol::HttpsWithHttpFallback => {
+                    let registries = self.https_except_registries.clone().unwrap_or_default();
+                    NativeClientProtocol::HttpsWithHttpFallback(registries)
+                }
             };
         }
 
+        if let Some(registries) = &self.allow_credentials_over_http {
+            config.allow_credentials_over_http = registries.clone();
+        }
+
         if let Some(accept) = self.accept_invalid_certificates {
             config.accept_invalid_certificates = accept;
         }
@@ -811,6 +834,21 @@ impl OciClient {
         Ok(())
     }
 
+    /// Remove the authentication credentials stored for a registry, or a registry
+    /// and repository path prefix.
+    #[napi]
+    pub async fn remove_auth(&self, registry: String) -> Result<()> {
+        self.inner.remove_auth(&registry).await;
+        Ok(())
+    }
+
+    /// Remove all the stored authentication credentials.
+    #[napi]
+    pub async fn clear_auth(&self) -> Result<()> {
+        self.inner.clear_auth().await;
+        Ok(())
+    }
+
     /// Pull a manifest (either image or index) from the registry.
     /// Returns the manifest and its digest.
     #[napi]
@@ -1130,3 +1168,14 @@ pub fn bearer_auth(token: String) -> RegistryAuth {
         token: Some(token),
     }
 }
+
+/// Create an OAuth2 identity token authentication object.
+#[napi]
+pub fn identity_token_auth(token: String) -> RegistryAuth {
+    RegistryAuth {
+        auth_type: RegistryAuthType::IdentityToken,
+        username: None,
+        password: None,
+        token: Some(token),
+    }
+}
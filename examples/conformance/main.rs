@@ -0,0 +1,96 @@
+use oci_client::{conformance, secrets::RegistryAuth, Client, Reference};
+
+use clap::Parser;
+use docker_credential::{CredentialRetrievalError, DockerCredential};
+use tracing::debug;
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::{fmt, EnvFilter};
+
+/// Run the distribution-spec conformance workflows against a OCI container registry
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+pub(crate) struct Cli {
+    /// Enable verbose mode
+    #[clap(short, long)]
+    pub verbose: bool,
+
+    /// Perform anonymous operation, by default the tool tries to reuse the docker credentials read
+    /// from the default docker file
+    #[clap(short, long)]
+    pub anonymous: bool,
+
+    /// Talk to the registry using HTTP instead of HTTPS, sending the credentials in clear
+    /// text
+    #[clap(short, long)]
+    pub insecure: bool,
+
+    /// Repository to push the test content to, e.g. `localhost:5000/conformance`. The tool also
+    /// uses `<repository>-mount` to test cross-repository blob mounts
+    repository: String,
+}
+
+fn build_auth(reference: &Reference, cli: &Cli) -> RegistryAuth {
+    let server = reference
+        .resolve_registry()
+        .strip_suffix('/')
+        .unwrap_or_else(|| reference.resolve_registry());
+
+    if cli.anonymous {
+        return RegistryAuth::Anonymous;
+    }
+
+    match docker_credential::get_credential(server) {
+        Err(CredentialRetrievalError::ConfigNotFound) => RegistryAuth::Anonymous,
+        Err(CredentialRetrievalError::NoCredentialConfigured) => RegistryAuth::Anonymous,
+        Err(e) => panic!("Error handling docker configuration file: {e}"),
+        Ok(DockerCredential::UsernamePassword(username, password)) => {
+            debug!(username, "Found docker credentials");
+            RegistryAuth::Basic(username, password)
+        }
+        Ok(DockerCredential::IdentityToken(token)) => {
+            debug!("Found docker identity token");
+            RegistryAuth::IdentityToken(token)
+        }
+    }
+}
+
+#[tokio::main]
+pub async fn main() {
+    let cli = Cli::parse();
+
+    // setup logging
+    let level_filter = if cli.verbose { "debug" } else { "info" };
+    let filter_layer = EnvFilter::new(level_filter);
+    tracing_subscriber::registry()
+        .with(filter_layer)
+        .with(fmt::layer().with_writer(std::io::stderr))
+        .init();
+
+    let reference: Reference = cli
+        .repository
+        .parse()
+        .expect("Not a valid repository reference");
+    let auth = build_auth(&reference, &cli);
+
+    let protocol = if cli.insecure {
+        oci_client::client::ClientProtocol::Http
+    } else {
+        oci_client::client::ClientProtocol::Https
+    };
+    let client = Client::new(oci_client::client::ClientConfig {
+        protocol,
+        // The credentials are sent in clear text to any registry
+        allow_credentials_over_http: if cli.insecure {
+            vec!["*".to_string()]
+        } else {
+            vec![]
+        },
+        ..Default::default()
+    });
+
+    let report = conformance::run(&client, &reference, &auth).await;
+    print!("{report}");
+    if !report.all_passed() {
+        std::process::exit(1);
+    }
+}
@@ -19,7 +19,8 @@ pub(crate) struct Cli {
     #[clap(short, long)]
     pub anonymous: bool,
 
-    /// Pull image from registry using HTTP instead of HTTPS
+    /// Pull image from registry using HTTP instead of HTTPS, sending the credentials in
+    /// clear text
     #[clap(short, long)]
     pub insecure: bool,
 
@@ -65,6 +66,12 @@ fn build_client_config(cli: &Cli) -> oci_client::client::ClientConfig {
 
     oci_client::client::ClientConfig {
         protocol,
+        // The credentials are sent in clear text to any registry
+        allow_credentials_over_http: if cli.insecure {
+            vec!["*".to_string()]
+        } else {
+            vec![]
+        },
         ..Default::default()
     }
 }
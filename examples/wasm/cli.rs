@@ -13,7 +13,8 @@ pub(crate) struct Cli {
     #[clap(short, long)]
     pub anonymous: bool,
 
-    /// Pull image from registry using HTTP instead of HTTPS
+    /// Pull image from registry using HTTP instead of HTTPS, sending the credentials in
+    /// clear text
     #[clap(short, long)]
     pub insecure: bool,
 
@@ -50,6 +50,12 @@ fn build_client_config(cli: &Cli) -> oci_client::client::ClientConfig {
 
     oci_client::client::ClientConfig {
         protocol,
+        // The credentials are sent in clear text to any registry
+        allow_credentials_over_http: if cli.insecure {
+            vec!["*".to_string()]
+        } else {
+            vec![]
+        },
         ..Default::default()
     }
 }
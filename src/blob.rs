@@ -1,8 +1,10 @@
 //! Helpers for interacting with blobs and their verification
 use std::task::Poll;
 
+use chrono::{DateTime, NaiveDateTime, Utc};
 use futures_util::stream::{BoxStream, Stream};
 use futures_util::TryStreamExt;
+use reqwest::Url;
 
 use crate::digest::Digester;
 use crate::errors::DigestError;
@@ -38,6 +40,60 @@ pub enum BlobResponse {
     Partial(SizedStream),
 }
 
+/// Where to download a blob from, as resolved by
+/// [`Client::resolve_blob_url`](crate::Client::resolve_blob_url)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlobUrl {
+    /// The URL to download the blob from
+    pub url: String,
+    /// Whether the registry redirected the download to another host, usually to a
+    /// presigned URL of an object storage or of a CDN that needs no credentials.
+    /// Otherwise the URL is the one of the registry, which requires its credentials.
+    pub redirected: bool,
+    /// When the URL expires, if its query tells it
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl BlobUrl {
+    pub(crate) fn new(url: Url, redirected: bool) -> Self {
+        Self {
+            expires_at: url_expiry(&url),
+            url: url.into(),
+            redirected,
+        }
+    }
+}
+
+/// The expiry of a presigned URL, from the parameters of its query used by AWS S3 and
+/// Google Cloud Storage (`X-Amz-Date` and `X-Amz-Expires`, or `Expires`), CloudFront
+/// (`Expires`) and Azure Blob Storage (`se`)
+fn url_expiry(url: &Url) -> Option<DateTime<Utc>> {
+    let param = |name: &str| {
+        url.query_pairs()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.into_owned())
+    };
+    for prefix in ["X-Amz", "X-Goog"] {
+        if let (Some(date), Some(expires)) = (
+            param(&format!("{prefix}-Date")),
+            param(&format!("{prefix}-Expires")),
+        ) {
+            let date = NaiveDateTime::parse_from_str(&date, "%Y%m%dT%H%M%SZ").ok()?;
+            let expires = chrono::Duration::seconds(expires.parse().ok()?);
+            return Some(date.and_utc() + expires);
+        }
+    }
+    if let Some(expires) = param("Expires") {
+        return DateTime::from_timestamp(expires.parse().ok()?, 0);
+    }
+    if let (Some(expiry), Some(_)) = (param("se"), param("sig")) {
+        return DateTime::parse_from_rfc3339(&expiry)
+            .ok()
+            .map(|expiry| expiry.to_utc());
+    }
+    None
+}
+
 pub(crate) struct VerifyingStream {
     stream: BoxStream<'static, Result<bytes::Bytes, std::io::Error>>,
     layer_digester: Digester,
@@ -129,8 +185,36 @@ mod tests {
 
     use bytes::Bytes;
     use futures_util::TryStreamExt;
+    use rstest::rstest;
     use sha2::Digest as _;
 
+    #[rstest]
+    #[case::s3(
+        "https://bucket.s3.amazonaws.com/blob?X-Amz-Algorithm=AWS4-HMAC-SHA256&X-Amz-Date=20240102T030405Z&X-Amz-Expires=1200&X-Amz-Signature=abc",
+        Some("2024-01-02T03:24:05Z")
+    )]
+    #[case::gcs(
+        "https://storage.googleapis.com/bucket/blob?x-goog-date=20240102T030405Z&x-goog-expires=60&x-goog-signature=abc",
+        Some("2024-01-02T03:05:05Z")
+    )]
+    #[case::cloudfront(
+        "https://d1.cloudfront.net/blob?Expires=1704164645&Signature=abc&Key-Pair-Id=K1",
+        Some("2024-01-02T03:04:05Z")
+    )]
+    #[case::azure(
+        "https://account.blob.core.windows.net/container/blob?sv=2022-11-02&se=2024-01-02T03%3A04%3A05Z&sr=b&sp=r&sig=abc",
+        Some("2024-01-02T03:04:05Z")
+    )]
+    #[case::unsigned("https://registry.example.com/v2/hello/blobs/sha256:abc", None)]
+    #[case::invalid(
+        "https://bucket.s3.amazonaws.com/blob?X-Amz-Date=yesterday&X-Amz-Expires=60",
+        None
+    )]
+    fn test_url_expiry(#[case] url: &str, #[case] expected: Option<&str>) {
+        let expected = expected.map(|expected| expected.parse::<DateTime<Utc>>().unwrap());
+        assert_eq!(url_expiry(&url.parse().unwrap()), expected);
+    }
+
     #[tokio::test]
     async fn test_verifying_stream() {
         // Test with correct SHA
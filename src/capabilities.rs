@@ -0,0 +1,216 @@
+//! The capabilities of a registry, as found out by [`Client::probe`](crate::Client::probe)
+//! and [`Client::probe_repository`](crate::Client::probe_repository)
+//!
+//! The capabilities probed last are cached per registry by the client, which then
+//! applies the workarounds they call for without first trying what the registry
+//! doesn't support, see [`Client::registry_quirks`](crate::Client::registry_quirks).
+
+use http::HeaderValue;
+use http_auth::parser::ChallengeParser;
+use serde::Deserialize;
+
+use crate::quirks::RegistryQuirks;
+
+/// The API version announced by the registries implementing the distribution
+/// specification, in the `Docker-Distribution-API-Version` header
+pub const DISTRIBUTION_API_VERSION: &str = "registry/2.0";
+
+/// What a registry supports.
+///
+/// The capabilities that couldn't be checked, for instance because the credentials
+/// given don't allow it, are `None`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RegistryCapabilities {
+    /// The value of the `Docker-Distribution-API-Version` header, if sent
+    pub api_version: Option<String>,
+
+    /// How the registry asks its clients to authenticate
+    pub auth: AuthScheme,
+
+    /// Whether the registry implements the referrers API
+    pub referrers_api: Option<bool>,
+
+    /// Whether blob uploads can be started
+    pub uploads: Option<bool>,
+
+    /// The minimum size of the chunks of an upload but the last one, required by the
+    /// registry with the `OCI-Chunk-Min-Length` header
+    pub chunk_min_length: Option<usize>,
+
+    /// Whether blobs can be mounted from another repository. This is only known when
+    /// the probed repository has the empty JSON blob `{}`.
+    pub blob_mount: Option<bool>,
+
+    /// Whether tags can be deleted. This is only reported: deleting a tag is never
+    /// turned into deleting its manifest, see
+    /// [`RegistryQuirks::no_tag_delete`](crate::quirks::RegistryQuirks::no_tag_delete).
+    pub tag_delete: Option<bool>,
+
+    /// The extensions listed by the registry at `/v2/_oci/ext/discover`, when it lists
+    /// them without authentication
+    pub extensions: Vec<RegistryExtension>,
+}
+
+impl RegistryCapabilities {
+    /// Adds the workarounds called for by the capabilities to `quirks`
+    pub(crate) fn apply_to(&self, quirks: &mut RegistryQuirks) {
+        quirks.no_referrers_api |= self.referrers_api == Some(false);
+        quirks.min_chunk_size = quirks.min_chunk_size.max(self.chunk_min_length);
+    }
+
+    /// Keeps the capabilities of the registry checked by a previous probe that this one
+    /// didn't check
+    pub(crate) fn merge(&mut self, previous: &RegistryCapabilities) {
+        self.referrers_api = self.referrers_api.or(previous.referrers_api);
+        self.uploads = self.uploads.or(previous.uploads);
+        self.chunk_min_length = self.chunk_min_length.or(previous.chunk_min_length);
+        self.blob_mount = self.blob_mount.or(previous.blob_mount);
+        self.tag_delete = self.tag_delete.or(previous.tag_delete);
+    }
+}
+
+/// The authentication scheme of a registry, from the `WWW-Authenticate` header of its
+/// answer to an anonymous request
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum AuthScheme {
+    /// The registry doesn't require authentication
+    #[default]
+    None,
+    /// HTTP Basic authentication
+    Basic {
+        /// The protection space of the credentials
+        realm: Option<String>,
+    },
+    /// Token authentication, with tokens obtained from an authorization server
+    Bearer {
+        /// The URL of the authorization server
+        realm: String,
+        /// The name of the registry for the authorization server
+        service: Option<String>,
+    },
+    /// Another authentication scheme, which the client doesn't support
+    Other {
+        /// The name of the scheme
+        scheme: String,
+    },
+}
+
+impl AuthScheme {
+    /// The scheme of the first challenge of a `WWW-Authenticate` header
+    pub(crate) fn from_header(value: &HeaderValue) -> Self {
+        let Some(challenge) = value
+            .to_str()
+            .ok()
+            .and_then(|value| ChallengeParser::new(value).next()?.ok())
+        else {
+            return AuthScheme::Other {
+                scheme: String::new(),
+            };
+        };
+        let param = |name: &str| {
+            challenge
+                .params
+                .iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.to_unescaped())
+        };
+        if challenge.scheme.eq_ignore_ascii_case("Basic") {
+            AuthScheme::Basic {
+                realm: param("realm"),
+            }
+        } else if let (true, Some(realm)) = (
+            challenge.scheme.eq_ignore_ascii_case("Bearer"),
+            param("realm"),
+        ) {
+            AuthScheme::Bearer {
+                realm,
+                service: param("service"),
+            }
+        } else {
+            AuthScheme::Other {
+                scheme: challenge.scheme.to_string(),
+            }
+        }
+    }
+}
+
+/// An extension of the distribution specification supported by a registry
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct RegistryExtension {
+    /// The name of the extension, e.g. `_oci`
+    pub name: String,
+    /// The URL of the documentation of the extension
+    #[serde(default)]
+    pub url: Option<String>,
+    /// What the extension does
+    #[serde(default)]
+    pub description: Option<String>,
+    /// The endpoints of the extension, relative to `/v2/`
+    #[serde(default)]
+    pub endpoints: Vec<String>,
+}
+
+/// The answer of a registry to `/v2/_oci/ext/discover`
+#[derive(Deserialize)]
+pub(crate) struct ExtensionList {
+    #[serde(default)]
+    pub extensions: Vec<RegistryExtension>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_auth_scheme_from_header() {
+        let scheme =
+            |value: &'static str| AuthScheme::from_header(&HeaderValue::from_static(value));
+        assert_eq!(
+            scheme(r#"Bearer realm="https://auth.docker.io/token",service="registry.docker.io""#),
+            AuthScheme::Bearer {
+                realm: "https://auth.docker.io/token".to_string(),
+                service: Some("registry.docker.io".to_string()),
+            }
+        );
+        assert_eq!(
+            scheme(r#"Basic realm="Registry Realm""#),
+            AuthScheme::Basic {
+                realm: Some("Registry Realm".to_string()),
+            }
+        );
+        assert_eq!(
+            scheme("Negotiate"),
+            AuthScheme::Other {
+                scheme: "Negotiate".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_apply_to() {
+        let capabilities = RegistryCapabilities {
+            referrers_api: Some(false),
+            tag_delete: Some(false),
+            chunk_min_length: Some(1024),
+            ..Default::default()
+        };
+        let mut quirks = RegistryQuirks::default();
+        capabilities.apply_to(&mut quirks);
+        assert!(quirks.no_referrers_api);
+        assert!(!quirks.no_tag_delete);
+        assert_eq!(quirks.min_chunk_size, Some(1024));
+
+        let mut quirks = RegistryQuirks {
+            min_chunk_size: Some(4096),
+            ..Default::default()
+        };
+        RegistryCapabilities::default().apply_to(&mut quirks);
+        assert_eq!(
+            quirks,
+            RegistryQuirks {
+                min_chunk_size: Some(4096),
+                ..Default::default()
+            }
+        );
+    }
+}
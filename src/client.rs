@@ -1,26 +1,35 @@
 //! OCI distribution client for fetching oci images from an OCI compliant remote store
 use std::collections::{BTreeMap, HashMap};
 use std::convert::TryFrom;
+use std::fmt;
 use std::hash::Hash;
+use std::net::SocketAddr;
+use std::path::Path;
 use std::sync::Arc;
 use std::time::Duration;
 
+use futures_util::future::BoxFuture;
 use futures_util::stream::{self, BoxStream, StreamExt, TryStreamExt};
 use futures_util::{future, Stream};
 use http::header::RANGE;
-use http::{HeaderValue, StatusCode};
+use http::{HeaderValue, Method, StatusCode};
 use http_auth::{parser::ChallengeParser, ChallengeRef};
 use olpc_cjson::CanonicalFormatter;
 use reqwest::header::HeaderMap;
-use reqwest::{NoProxy, Proxy, RequestBuilder, Response, Url};
+use reqwest::{IntoUrl, NoProxy, Proxy, RequestBuilder, Response, Url};
 use serde::Deserialize;
 use serde::Serialize;
 use tokio::io::{AsyncWrite, AsyncWriteExt};
 use tokio::sync::RwLock;
 use tracing::{debug, trace, warn};
+use zeroize::Zeroizing;
 
 pub use crate::blob::*;
+use crate::capabilities::{
+    AuthScheme, ExtensionList, RegistryCapabilities, DISTRIBUTION_API_VERSION,
+};
 use crate::config::ConfigFile;
+use crate::diagnostics::ResponseDiagnostics;
 use crate::digest::{digest_header_value, validate_digest, Digest, Digester};
 use crate::errors::*;
 use crate::manifest::{
@@ -29,10 +38,15 @@ use crate::manifest::{
     IMAGE_MANIFEST_LIST_MEDIA_TYPE, IMAGE_MANIFEST_MEDIA_TYPE, OCI_IMAGE_INDEX_MEDIA_TYPE,
     OCI_IMAGE_MEDIA_TYPE,
 };
+use crate::quirks::{docker_media_type, referrers_tag, RegistryQuirks};
+use crate::rate_limit::{body_stream, RateLimiter};
 use crate::secrets::RegistryAuth;
 use crate::secrets::*;
 use crate::sha256_digest;
-use crate::token_cache::{RegistryOperation, RegistryToken, RegistryTokenType, TokenCache};
+use crate::throttle::{Bandwidths, Throttle};
+use crate::token_cache::{
+    RegistryOperation, RegistryToken, RegistryTokenType, TokenCache, TokenStore,
+};
 use crate::Reference;
 
 const MIME_TYPES_DISTRIBUTION_MANIFEST: &[&str] = &[
@@ -42,8 +56,26 @@ const MIME_TYPES_DISTRIBUTION_MANIFEST: &[&str] = &[
     OCI_IMAGE_INDEX_MEDIA_TYPE,
 ];
 
+/// The manifest media types accepted from the registries only knowing the Docker ones
+const DOCKER_MIME_TYPES_DISTRIBUTION_MANIFEST: &[&str] =
+    &[IMAGE_MANIFEST_MEDIA_TYPE, IMAGE_MANIFEST_LIST_MEDIA_TYPE];
+
 const PUSH_CHUNK_MAX_SIZE: usize = 4096 * 1024;
 
+/// The header giving the minimum size of the chunks of an upload
+const OCI_CHUNK_MIN_LENGTH_HEADER: &str = "OCI-Chunk-Min-Length";
+
+/// The header giving the version of the distribution API implemented by a registry
+const DISTRIBUTION_API_VERSION_HEADER: &str = "Docker-Distribution-API-Version";
+
+/// The tag deleted to find out whether a registry deletes tags, which shouldn't exist
+const PROBE_TAG: &str = concat!(env!("CARGO_PKG_NAME"), "-probe-missing-tag");
+
+/// The maximum number of redirects within a registry followed to resolve the URL of a
+/// blob
+#[cfg(not(target_arch = "wasm32"))]
+const MAX_BLOB_REDIRECTS: usize = 10;
+
 /// Default value for `ClientConfig::max_concurrent_upload`
 pub const DEFAULT_MAX_CONCURRENT_UPLOAD: usize = 16;
 
@@ -53,8 +85,14 @@ pub const DEFAULT_MAX_CONCURRENT_DOWNLOAD: usize = 16;
 /// Default value for `ClientConfig:default_token_expiration_secs`
 pub const DEFAULT_TOKEN_EXPIRATION_SECS: usize = 60;
 
+/// Default value for `ClientConfig::token_refresh_margin_secs`
+pub const DEFAULT_TOKEN_REFRESH_MARGIN_SECS: usize = 10;
+
 static DEFAULT_USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
 
+/// The `client_id` sent to authorization servers during the OAuth2 token flow
+const OAUTH2_CLIENT_ID: &str = env!("CARGO_PKG_NAME");
+
 /// The data for an image or module.
 #[derive(Clone)]
 pub struct ImageData {
@@ -66,6 +104,8 @@ pub struct ImageData {
     pub config: Config,
     /// The manifest of the image or module.
     pub manifest: Option<OciImageManifest>,
+    /// The metadata of the answers of the registry, as of the end of the pull.
+    pub diagnostics: ResponseDiagnostics,
 }
 
 /// The data returned by an OCI registry after a successful push
@@ -75,6 +115,8 @@ pub struct PushResponse {
     pub config_url: String,
     /// Pullable url for the manifest
     pub manifest_url: String,
+    /// The metadata of the answers of the registry, as of the end of the push
+    pub diagnostics: ResponseDiagnostics,
 }
 
 /// The data returned by a successful tags/list Request
@@ -84,6 +126,10 @@ pub struct TagResponse {
     pub name: String,
     /// List of existing Tags
     pub tags: Vec<String>,
+    /// The `last` value to list the next page of tags with, when the registry paginated
+    /// the response with a `Link` header
+    #[serde(skip)]
+    pub next: Option<String>,
 }
 
 /// Layer descriptor required to pull a layer
@@ -266,10 +312,26 @@ impl TryFrom<Config> for ConfigFile {
 #[derive(Clone)]
 pub struct Client {
     config: Arc<ClientConfig>,
-    // Registry -> RegistryAuth
-    auth_store: Arc<RwLock<HashMap<String, RegistryAuth>>>,
+    // Registry, or registry and repository path prefix -> RegistryAuth
+    auth_store: Arc<RwLock<HashMap<String, Zeroizing<RegistryAuth>>>>,
+    // Registry, or registry and repository -> authentication challenge of the registry,
+    // or the one sent by the registry when rejecting a request on the repository
+    challenges: Arc<RwLock<HashMap<String, AuthChallenge>>>,
+    // Registry -> scheme chosen for the registries that may fall back to HTTP
+    schemes: Arc<std::sync::RwLock<HashMap<String, &'static str>>>,
+    // Registry -> capabilities found out by the last probe of the registry
+    capabilities: Arc<std::sync::RwLock<HashMap<String, RegistryCapabilities>>>,
+    // Registry -> metadata of the answers of the registry
+    diagnostics: Arc<std::sync::Mutex<HashMap<String, ResponseDiagnostics>>>,
     tokens: TokenCache,
-    client: reqwest::Client,
+    // The provider of the credentials, from the configuration unless set for a view
+    credential_provider: Option<Arc<dyn CredentialProvider>>,
+    client: HttpClients,
+    // HTTP clients not following redirects, created when first needed
+    #[cfg(not(target_arch = "wasm32"))]
+    redirectless_client: Arc<std::sync::OnceLock<HttpClients>>,
+    limiter: RateLimiter,
+    bandwidths: Bandwidths,
     push_chunk_size: usize,
 }
 
@@ -278,8 +340,17 @@ impl Default for Client {
         Self {
             config: Arc::default(),
             auth_store: Arc::default(),
-            tokens: TokenCache::new(DEFAULT_TOKEN_EXPIRATION_SECS),
-            client: reqwest::Client::default(),
+            challenges: Arc::default(),
+            schemes: Arc::default(),
+            capabilities: Arc::default(),
+            diagnostics: Arc::default(),
+            tokens: token_cache_for(&ClientConfig::default(), None),
+            credential_provider: None,
+            client: HttpClients::default(),
+            #[cfg(not(target_arch = "wasm32"))]
+            redirectless_client: Arc::default(),
+            limiter: rate_limiter_for(&ClientConfig::default()),
+            bandwidths: Bandwidths::default(),
             push_chunk_size: PUSH_CHUNK_MAX_SIZE,
         }
     }
@@ -293,79 +364,239 @@ pub trait ClientConfigSource {
     fn client_config(&self) -> ClientConfig;
 }
 
+/// Creates the token cache described by the given configuration, backed by the given
+/// token store
+fn token_cache_for(config: &ClientConfig, store: Option<Arc<dyn TokenStore>>) -> TokenCache {
+    let tokens = TokenCache::new(config.default_token_expiration_secs)
+        .with_refresh_margin(config.token_refresh_margin_secs)
+        .with_max_entries(config.max_cached_tokens);
+    let tokens = match store {
+        Some(store) => tokens.with_store(store),
+        None => tokens,
+    };
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(interval) = config.token_prune_interval {
+        tokens.spawn_pruning(interval);
+    }
+    tokens
+}
+
+/// Creates the limiter of the requests to each registry described by the given
+/// configuration
+fn rate_limiter_for(config: &ClientConfig) -> RateLimiter {
+    RateLimiter::new(
+        config.max_concurrent_requests_per_registry,
+        config.max_requests_per_second_per_registry,
+        config.adaptive_rate_limit,
+    )
+}
+
 impl TryFrom<ClientConfig> for Client {
     type Error = OciDistributionError;
 
     fn try_from(config: ClientConfig) -> std::result::Result<Self, Self::Error> {
-        #[allow(unused_mut)]
-        let mut client_builder = reqwest::Client::builder();
-        #[cfg(not(target_arch = "wasm32"))]
-        let mut client_builder =
-            client_builder.danger_accept_invalid_certs(config.accept_invalid_certificates);
-
-        client_builder = match () {
-            #[cfg(all(feature = "native-tls", not(target_arch = "wasm32")))]
-            () => client_builder.danger_accept_invalid_hostnames(config.accept_invalid_hostnames),
-            #[cfg(any(not(feature = "native-tls"), target_arch = "wasm32"))]
-            () => client_builder,
+        let tokens = token_cache_for(&config, config.token_store.clone());
+        Client::with_token_cache(config, tokens)
+    }
+}
+
+impl Client {
+    /// Creates the client described by the given configuration, using the given token
+    /// cache
+    fn with_token_cache(config: ClientConfig, tokens: TokenCache) -> Result<Self> {
+        let client = http_clients_for(&config, true)?;
+        let limiter = rate_limiter_for(&config);
+        let bandwidths = Bandwidths::new(
+            config.max_bytes_per_second,
+            &config.max_bytes_per_second_by_registry,
+        );
+        Ok(Self {
+            credential_provider: config.credential_provider.clone(),
+            config: Arc::new(config),
+            tokens,
+            limiter,
+            bandwidths,
+            client,
+            push_chunk_size: PUSH_CHUNK_MAX_SIZE,
+            ..Default::default()
+        })
+    }
+}
+
+/// Creates the HTTP clients described by the given configuration, following redirects
+/// or not
+fn http_clients_for(config: &ClientConfig, follow_redirects: bool) -> Result<HttpClients> {
+    let default = http_client_for(config, None, follow_redirects)?;
+    let by_host = config
+        .registry_tls
+        .iter()
+        .map(|(host, tls)| {
+            let client = http_client_for(config, Some(tls), follow_redirects)?;
+            Ok((host.clone(), client))
+        })
+        .collect::<Result<HashMap<_, _>>>()?;
+    Ok(HttpClients {
+        default,
+        by_host: Arc::new(by_host),
+    })
+}
+
+/// Creates the HTTP client described by the given configuration, with the TLS settings
+/// of a registry if any
+#[cfg_attr(target_arch = "wasm32", allow(unused_variables))]
+fn http_client_for(
+    config: &ClientConfig,
+    tls: Option<&RegistryTlsConfig>,
+    follow_redirects: bool,
+) -> Result<reqwest::Client> {
+    #[allow(unused_mut)]
+    let mut client_builder = reqwest::Client::builder();
+    #[cfg(not(target_arch = "wasm32"))]
+    if !follow_redirects {
+        client_builder = client_builder.redirect(reqwest::redirect::Policy::none());
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    let mut client_builder = client_builder.danger_accept_invalid_certs(
+        tls.and_then(|tls| tls.accept_invalid_certificates)
+            .unwrap_or(config.accept_invalid_certificates),
+    );
+
+    client_builder = match () {
+        #[cfg(all(feature = "native-tls", not(target_arch = "wasm32")))]
+        () => client_builder.danger_accept_invalid_hostnames(
+            tls.and_then(|tls| tls.accept_invalid_hostnames)
+                .unwrap_or(config.accept_invalid_hostnames),
+        ),
+        #[cfg(any(not(feature = "native-tls"), target_arch = "wasm32"))]
+        () => client_builder,
+    };
+
+    #[cfg(not(target_arch = "wasm32"))]
+    for c in config
+        .extra_root_certificates
+        .iter()
+        .chain(tls.iter().flat_map(|tls| &tls.extra_root_certificates))
+    {
+        let cert = match c.encoding {
+            CertificateEncoding::Der => reqwest::Certificate::from_der(c.data.as_slice())?,
+            CertificateEncoding::Pem => reqwest::Certificate::from_pem(c.data.as_slice())?,
         };
+        client_builder = client_builder.add_root_certificate(cert);
+    }
 
-        #[cfg(not(target_arch = "wasm32"))]
-        for c in &config.extra_root_certificates {
-            let cert = match c.encoding {
-                CertificateEncoding::Der => reqwest::Certificate::from_der(c.data.as_slice())?,
-                CertificateEncoding::Pem => reqwest::Certificate::from_pem(c.data.as_slice())?,
-            };
-            client_builder = client_builder.add_root_certificate(cert);
-        }
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(identity) = tls
+        .and_then(|tls| tls.client_identity.as_ref())
+        .or(config.client_identity.as_ref())
+    {
+        client_builder = client_builder.identity(identity.to_identity()?);
+    }
 
-        if let Some(timeout) = config.read_timeout {
-            client_builder = client_builder.read_timeout(timeout);
-        }
-        if let Some(timeout) = config.connect_timeout {
-            client_builder = client_builder.connect_timeout(timeout);
-        }
+    if let Some(timeout) = config.read_timeout {
+        client_builder = client_builder.read_timeout(timeout);
+    }
+    if let Some(timeout) = config.connect_timeout {
+        client_builder = client_builder.connect_timeout(timeout);
+    }
 
-        client_builder = client_builder.user_agent(config.user_agent);
+    client_builder = client_builder.user_agent(config.user_agent);
 
-        if let Some(proxy_addr) = &config.https_proxy {
-            let no_proxy = config
-                .no_proxy
-                .as_ref()
-                .and_then(|no_proxy| NoProxy::from_string(no_proxy));
-            let proxy = Proxy::https(proxy_addr)?.no_proxy(no_proxy);
-            client_builder = client_builder.proxy(proxy);
-        }
+    #[cfg(not(target_arch = "wasm32"))]
+    for (host, addrs) in &config.dns_overrides {
+        client_builder = client_builder.resolve_to_addrs(host, addrs);
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(resolver) = &config.dns_resolver {
+        client_builder = client_builder.dns_resolver(Arc::new(ResolverAdapter(resolver.clone())));
+    }
 
-        if let Some(proxy_addr) = &config.http_proxy {
-            let no_proxy = config
-                .no_proxy
-                .as_ref()
-                .and_then(|no_proxy| NoProxy::from_string(no_proxy));
-            let proxy = Proxy::http(proxy_addr)?.no_proxy(no_proxy);
-            client_builder = client_builder.proxy(proxy);
+    if let Some(proxy_addr) = &config.https_proxy {
+        let no_proxy = config
+            .no_proxy
+            .as_ref()
+            .and_then(|no_proxy| NoProxy::from_string(no_proxy));
+        let proxy = Proxy::https(proxy_addr)?.no_proxy(no_proxy);
+        client_builder = client_builder.proxy(proxy);
+    }
+
+    if let Some(proxy_addr) = &config.http_proxy {
+        let no_proxy = config
+            .no_proxy
+            .as_ref()
+            .and_then(|no_proxy| NoProxy::from_string(no_proxy));
+        let proxy = Proxy::http(proxy_addr)?.no_proxy(no_proxy);
+        client_builder = client_builder.proxy(proxy);
+    }
+
+    Ok(client_builder.build()?)
+}
+
+/// The HTTP clients used by a [`Client`]. Registries with their own TLS settings get
+/// their own HTTP client, picked according to the host of each request.
+#[derive(Clone, Default)]
+struct HttpClients {
+    default: reqwest::Client,
+    // Registry -> HTTP client
+    by_host: Arc<HashMap<String, reqwest::Client>>,
+}
+
+impl HttpClients {
+    /// Returns the HTTP client for the host of the URL.
+    ///
+    /// Registries are matched with their port, either explicit or the default one of
+    /// the scheme, and then without it.
+    fn for_url(&self, url: &str) -> &reqwest::Client {
+        if self.by_host.is_empty() {
+            return &self.default;
         }
+        let url = match Url::parse(url) {
+            Ok(url) => url,
+            Err(_) => return &self.default,
+        };
+        let host = match url.host_str() {
+            Some(host) => host,
+            None => return &self.default,
+        };
+        url.port_or_known_default()
+            .and_then(|port| self.by_host.get(&format!("{host}:{port}")))
+            .or_else(|| self.by_host.get(host))
+            .unwrap_or(&self.default)
+    }
 
-        let default_token_expiration_secs = config.default_token_expiration_secs;
-        Ok(Self {
-            config: Arc::new(config),
-            tokens: TokenCache::new(default_token_expiration_secs),
-            client: client_builder.build()?,
-            push_chunk_size: PUSH_CHUNK_MAX_SIZE,
-            ..Default::default()
-        })
+    fn get<U: IntoUrl + AsRef<str>>(&self, url: U) -> RequestBuilder {
+        self.for_url(url.as_ref()).get(url)
+    }
+
+    fn head<U: IntoUrl + AsRef<str>>(&self, url: U) -> RequestBuilder {
+        self.for_url(url.as_ref()).head(url)
+    }
+
+    fn delete<U: IntoUrl + AsRef<str>>(&self, url: U) -> RequestBuilder {
+        self.for_url(url.as_ref()).delete(url)
+    }
+
+    fn patch<U: IntoUrl + AsRef<str>>(&self, url: U) -> RequestBuilder {
+        self.for_url(url.as_ref()).patch(url)
+    }
+
+    fn post<U: IntoUrl + AsRef<str>>(&self, url: U) -> RequestBuilder {
+        self.for_url(url.as_ref()).post(url)
+    }
+
+    fn put<U: IntoUrl + AsRef<str>>(&self, url: U) -> RequestBuilder {
+        self.for_url(url.as_ref()).put(url)
     }
 }
 
 impl Client {
     /// Create a new client with the supplied config
     pub fn new(config: ClientConfig) -> Self {
-        let default_token_expiration_secs = config.default_token_expiration_secs;
-        Client::try_from(config).unwrap_or_else(|err| {
+        let tokens = token_cache_for(&config, config.token_store.clone());
+        Client::with_token_cache(config, tokens.clone()).unwrap_or_else(|err| {
             warn!("Cannot create OCI client from config: {:?}", err);
             warn!("Creating client with default configuration");
             Self {
-                tokens: TokenCache::new(default_token_expiration_secs),
+                tokens,
                 push_chunk_size: PUSH_CHUNK_MAX_SIZE,
                 ..Default::default()
             }
@@ -377,19 +608,101 @@ impl Client {
         Self::new(config_source.client_config())
     }
 
-    async fn store_auth(&self, registry: &str, auth: RegistryAuth) {
+    /// Creates a view of the client with its own credentials and tokens.
+    ///
+    /// The view shares the HTTP connection pool, the limits on the requests and on the
+    /// bandwidth used with each registry, the probed capabilities of the registries and
+    /// the configuration of the client. It neither shares the credentials stored in the
+    /// client, the tokens it obtained, the authentication challenges of the registries
+    /// nor the diagnostics of their answers, and its own aren't visible to the client.
+    /// This allows a single client to act on behalf of several tenants. Clones of the
+    /// client, on the other hand, share all of them.
+    ///
+    /// The [`ClientConfig::token_store`] isn't used by the view, since it would share
+    /// the tokens across views. Neither is the [`ClientConfig::credential_provider`],
+    /// a provider of its own can be given to the view with
+    /// [`with_credential_provider`](Self::with_credential_provider).
+    pub fn scoped(&self) -> Self {
+        Self {
+            config: self.config.clone(),
+            auth_store: Arc::default(),
+            challenges: Arc::default(),
+            schemes: self.schemes.clone(),
+            capabilities: self.capabilities.clone(),
+            diagnostics: Arc::default(),
+            tokens: self.tokens.scoped(),
+            credential_provider: None,
+            client: self.client.clone(),
+            #[cfg(not(target_arch = "wasm32"))]
+            redirectless_client: self.redirectless_client.clone(),
+            limiter: self.limiter.clone(),
+            bandwidths: self.bandwidths.clone(),
+            push_chunk_size: self.push_chunk_size,
+        }
+    }
+
+    /// Uses the given provider of credentials instead of the
+    /// [`ClientConfig::credential_provider`], typically for a view created with
+    /// [`scoped`](Self::scoped).
+    pub fn with_credential_provider(mut self, provider: Arc<dyn CredentialProvider>) -> Self {
+        self.credential_provider = Some(provider);
+        self
+    }
+
+    async fn store_auth(&self, prefix: &str, auth: RegistryAuth) {
+        let previous = self
+            .auth_store
+            .write()
+            .await
+            .insert(prefix.to_string(), Zeroizing::new(auth.clone()));
+        if previous.is_some_and(|previous| *previous != auth) {
+            debug!(%prefix, "Credentials changed");
+            self.invalidate_prefix_tokens(prefix).await;
+        }
+    }
+
+    /// Stores the credentials passed to an operation on the image.
+    ///
+    /// They apply to the repository of the image, and become the credentials of the
+    /// whole registry when it has none yet.
+    async fn store_image_auth(&self, image: &Reference, auth: &RegistryAuth) {
+        let registry = image.resolve_registry();
+        self.store_auth(&format!("{registry}/{}", image.repository()), auth.clone())
+            .await;
         self.auth_store
             .write()
             .await
-            .insert(registry.to_string(), auth);
+            .entry(registry.to_string())
+            .or_insert_with(|| Zeroizing::new(auth.clone()));
     }
 
-    async fn is_stored_auth(&self, registry: &str) -> bool {
-        self.auth_store.read().await.contains_key(registry)
+    /// Returns the credentials stored for the longest prefix of the repository path.
+    async fn stored_auth(&self, reference: &Reference) -> Option<Zeroizing<RegistryAuth>> {
+        let auth_store = self.auth_store.read().await;
+        let mut path = format!(
+            "{}/{}",
+            reference.resolve_registry(),
+            reference.repository()
+        );
+        loop {
+            if let Some(auth) = auth_store.get(&path) {
+                return Some(auth.clone());
+            }
+            match path.rfind('/') {
+                Some(index) => path.truncate(index),
+                None => return None,
+            }
+        }
     }
 
     /// Store the authentication information for this registry if it's not already stored in the client.
     ///
+    /// The registry can be followed by a repository path prefix, such as `ghcr.io/org`,
+    /// to use different credentials for some repositories of the registry. The
+    /// credentials stored for the longest prefix of a repository are used. When they
+    /// differ from the credentials already stored for the same prefix, they replace
+    /// them and the tokens obtained for the matching repositories are dropped.
+    ///
     /// Most of the time, you don't need to call this method directly. It's called by other
     /// methods (where you have to provide the authentication information as parameter).
     ///
@@ -397,27 +710,220 @@ impl Client {
     /// store the authentication information, you can call this method to store the authentication
     /// information manually.
     pub async fn store_auth_if_needed(&self, registry: &str, auth: &RegistryAuth) {
-        if !self.is_stored_auth(registry).await {
-            self.store_auth(registry, auth.clone()).await;
+        self.store_auth(registry, auth.clone()).await;
+    }
+
+    /// Removes the credentials stored for the registry, or registry and repository path
+    /// prefix, and drops the tokens obtained for the matching repositories.
+    ///
+    /// Credentials stored for longer prefixes are kept.
+    pub async fn remove_auth(&self, prefix: &str) {
+        if self.auth_store.write().await.remove(prefix).is_some() {
+            self.invalidate_prefix_tokens(prefix).await;
         }
     }
 
-    /// Checks if we got a token, if we don't - create it and store it in cache.
-    async fn get_auth_token(
+    /// Removes all the stored credentials, and drops the tokens obtained with them.
+    pub async fn clear_auth(&self) {
+        let prefixes: Vec<String> = self
+            .auth_store
+            .write()
+            .await
+            .drain()
+            .map(|(k, _)| k)
+            .collect();
+        for prefix in prefixes {
+            self.invalidate_prefix_tokens(&prefix).await;
+        }
+    }
+
+    async fn invalidate_prefix_tokens(&self, prefix: &str) {
+        let (registry, path) = prefix.split_once('/').unwrap_or((prefix, ""));
+        self.tokens.invalidate_prefix(registry, path).await;
+    }
+
+    /// Drops the tokens cached for the given repository, for all the operations.
+    ///
+    /// The next request against the repository obtains a new token. This is useful
+    /// when a token has been revoked before its expiration.
+    pub async fn invalidate_tokens(&self, registry: &str, repository: &str) {
+        self.tokens.invalidate(registry, repository).await;
+    }
+
+    /// Returns the credentials to use for the given reference.
+    ///
+    /// The credentials passed to the client methods, or stored in the client, come
+    /// first. The credential provider is only asked when they are anonymous or missing,
+    /// or when the previous credentials have been `rejected`.
+    async fn credentials_for(
+        &self,
+        reference: &Reference,
+        rejected: bool,
+    ) -> Option<Zeroizing<RegistryAuth>> {
+        let stored = self.stored_auth(reference).await;
+        if !rejected && !matches!(stored.as_deref(), None | Some(RegistryAuth::Anonymous)) {
+            return stored;
+        }
+        let registry = reference.resolve_registry();
+        if let Some(provider) = &self.credential_provider {
+            let repository = reference.repository();
+            match provider.credentials(registry, repository).await {
+                Ok(Some(auth)) => return Some(Zeroizing::new(auth)),
+                Ok(None) => {}
+                Err(error) => {
+                    warn!(?error, %registry, %repository, "Credential provider failed");
+                }
+            }
+        }
+        stored
+    }
+
+    /// Forgets the tokens of the repository after its credentials have been rejected,
+    /// and lets the credential provider know about it.
+    async fn credentials_rejected(&self, reference: &Reference) {
+        let registry = reference.resolve_registry();
+        let repository = reference.repository();
+        if let Some(provider) = &self.credential_provider {
+            provider.credentials_rejected(registry, repository);
+        }
+        self.tokens.invalidate(registry, repository).await;
+    }
+
+    /// Obtains a new token for the scope requested by the challenge of a rejected
+    /// request, and caches it. The credential provider is asked for new credentials
+    /// when the previous ones have been `rejected`.
+    ///
+    /// Returns `None` when the credentials cannot be exchanged for a new token.
+    async fn reauthenticate(
+        &self,
+        reference: &Reference,
+        op: RegistryOperation,
+        sources: &[String],
+        challenge: &BearerChallenge,
+        rejected: bool,
+    ) -> Result<Option<RegistryTokenType>> {
+        let auth = self
+            .credentials_for(reference, rejected)
+            .await
+            .unwrap_or_else(|| Zeroizing::new(RegistryAuth::Anonymous));
+        if let RegistryAuth::Bearer(token) = &*auth {
+            // A static token cannot be renewed, unless the provider handed out a new one
+            if self.credential_provider.is_none() {
+                return Ok(None);
+            }
+            return Ok(Some(RegistryTokenType::Bearer(RegistryToken::new(
+                token.clone(),
+            ))));
+        }
+
+        // Some registries have a realm or service per repository
+        self.cache_challenge(
+            &format!(
+                "{}/{}",
+                reference.resolve_registry(),
+                reference.repository()
+            ),
+            AuthChallenge::Bearer(BearerChallenge {
+                scope: None,
+                error: None,
+                ..challenge.clone()
+            }),
+        )
+        .await;
+        // The challenge may lack the scopes of the mount sources
+        let mut scopes = registry_scopes(reference, op, sources);
+        for scope in challenge.scope.iter().flat_map(|s| s.split_whitespace()) {
+            if !scopes.iter().any(|s| s == scope) {
+                scopes.push(scope.to_string());
+            }
+        }
+        let token = self
+            .fetch_token(reference, challenge, &scopes, &auth)
+            .await?;
+        self.tokens
+            .insert_with_sources(reference, op, sources, token.clone())
+            .await;
+        Ok(Some(token))
+    }
+
+    /// Exchanges the refresh token issued along with the expiring token, if any, for a
+    /// new token.
+    async fn renew_token(
         &self,
         reference: &Reference,
         op: RegistryOperation,
+        sources: &[String],
     ) -> Option<RegistryTokenType> {
-        let registry = reference.resolve_registry();
-        let auth = self.auth_store.read().await.get(registry)?.clone();
-        match self.tokens.get(reference, op).await {
-            Some(token) => Some(token),
-            None => {
-                let token = self._auth(reference, &auth, op).await.ok()??;
-                self.tokens.insert(reference, op, token.clone()).await;
+        let refresh_token = self.tokens.refresh_token(reference, op, sources).await?;
+        let auth = Zeroizing::new(RegistryAuth::IdentityToken(refresh_token));
+        match self._auth(reference, &auth, op, sources).await {
+            Ok(Some(token)) => {
+                debug!("Renewed token with the refresh token");
+                self.tokens
+                    .insert_with_sources(reference, op, sources, token.clone())
+                    .await;
                 Some(token)
             }
+            res => {
+                debug!(error = ?res.err(), "Cannot renew token with the refresh token");
+                None
+            }
+        }
+    }
+
+    /// Checks if we got a token, if we don't - create it and store it in cache.
+    ///
+    /// The token also grants pull access to the `sources` repositories of the registry.
+    /// The request goes on anonymously when no token can be obtained, unless the
+    /// credentials would have been sent over plain HTTP. The credential provider is
+    /// asked for new credentials when the previous ones have been `rejected`.
+    async fn get_auth_token(
+        &self,
+        reference: &Reference,
+        op: RegistryOperation,
+        sources: &[String],
+        rejected: bool,
+    ) -> Result<Option<RegistryTokenType>> {
+        let has_provider = self.credential_provider.is_some();
+        if !has_provider && self.stored_auth(reference).await.is_none() {
+            return Ok(None);
+        }
+        if let Some(token) = self.tokens.get(reference, op, sources).await {
+            return Ok(Some(token));
         }
+
+        // Concurrent requests share the token fetched by the first one
+        let _guard = self.tokens.lock(reference, op, sources).await;
+        if let Some(token) = self.tokens.get(reference, op, sources).await {
+            return Ok(Some(token));
+        }
+        if let Some(token) = self.renew_token(reference, op, sources).await {
+            return Ok(Some(token));
+        }
+
+        let Some(auth) = self.credentials_for(reference, rejected).await else {
+            return Ok(None);
+        };
+        let token = match self._auth(reference, &auth, op, sources).await {
+            Err(OciDistributionError::AuthenticationFailure(reason)) if has_provider => {
+                debug!(%reason, "Credentials rejected by the authorization server, retrying with new ones");
+                self.credentials_rejected(reference).await;
+                let Some(auth) = self.credentials_for(reference, true).await else {
+                    return Ok(None);
+                };
+                self._auth(reference, &auth, op, sources).await
+            }
+            res => res,
+        };
+        let token = match token {
+            Ok(Some(token)) => token,
+            Err(error @ OciDistributionError::InsecureCredentialsError(_)) => return Err(error),
+            Ok(None) | Err(_) => return Ok(None),
+        };
+        self.tokens
+            .insert_with_sources(reference, op, sources, token.clone())
+            .await;
+        Ok(Some(token))
     }
 
     /// Fetches the available Tags for the given Reference
@@ -434,8 +940,7 @@ impl Client {
         let op = RegistryOperation::Pull;
         let url = self.to_list_tags_url(image);
 
-        self.store_auth_if_needed(image.resolve_registry(), auth)
-            .await;
+        self.store_image_auth(image, auth).await;
 
         let request = self.client.get(&url);
         let request = if let Some(num) = n {
@@ -451,19 +956,70 @@ impl Client {
         let request = RequestBuilderWrapper {
             client: self,
             request_builder: request,
+            body: None,
         };
-        let res = request
-            .apply_auth(image, op)
-            .await?
-            .into_request_builder()
-            .send()
-            .await?;
+        let res = request.send_with_auth(image, op).await?;
         let status = res.status();
+        let next = res
+            .headers()
+            .get(reqwest::header::LINK)
+            .and_then(|link| link.to_str().ok())
+            .and_then(next_page_last);
+        let headers = res.headers().clone();
         let body = res.bytes().await?;
 
-        validate_registry_response(status, &body, &url)?;
+        self.validate_registry_response(
+            status,
+            &headers,
+            &body,
+            &url,
+            ErrorContext::new(Method::GET, image),
+        )?;
+
+        let mut tags: TagResponse = serde_json::from_str(std::str::from_utf8(&body)?)?;
+        tags.next = next;
+        Ok(tags)
+    }
+
+    /// Deletes a manifest, or a tag.
+    ///
+    /// When the reference has a digest, the manifest with this digest is deleted, along
+    /// with the tags pointing to it. Otherwise only the tag of the reference is deleted,
+    /// which not all registries support: an
+    /// [`UnsupportedTagDeleteError`](OciDistributionError::UnsupportedTagDeleteError) is
+    /// returned for the registries known not to, see
+    /// [`RegistryQuirks::no_tag_delete`].
+    ///
+    /// The client will check if it's already been authenticated and if
+    /// not will attempt to do.
+    pub async fn delete_manifest(&self, image: &Reference, auth: &RegistryAuth) -> Result<()> {
+        if image.digest().is_none() && self.registry_quirks(image.resolve_registry()).no_tag_delete
+        {
+            return Err(OciDistributionError::UnsupportedTagDeleteError(
+                image.whole(),
+            ));
+        }
+        let url = self.to_v2_manifest_url(image);
+        debug!(?url, "delete manifest");
+        self.store_image_auth(image, auth).await;
 
-        Ok(serde_json::from_str(std::str::from_utf8(&body)?)?)
+        let res = RequestBuilderWrapper::from_client(self, |client| client.delete(&url))
+            .send_with_auth(image, RegistryOperation::Delete)
+            .await?;
+        match res.status() {
+            // The OCI spec requires the status code to be 202 Accepted
+            StatusCode::ACCEPTED => Ok(()),
+            status => {
+                let headers = res.headers().clone();
+                self.validate_registry_response(
+                    status,
+                    &headers,
+                    &res.bytes().await?,
+                    &url,
+                    ErrorContext::new(Method::DELETE, image),
+                )
+            }
+        }
     }
 
     /// Pull an image and return the bytes
@@ -477,8 +1033,7 @@ impl Client {
         accepted_media_types: Vec<&str>,
     ) -> Result<ImageData> {
         debug!("Pulling image: {:?}", image);
-        self.store_auth_if_needed(image.resolve_registry(), auth)
-            .await;
+        self.store_image_auth(image, auth).await;
 
         let (manifest, digest, config) = self._pull_manifest_and_config(image).await?;
 
@@ -512,6 +1067,9 @@ impl Client {
             manifest: Some(manifest),
             config,
             digest: Some(digest),
+            diagnostics: self
+                .diagnostics(image.resolve_registry())
+                .unwrap_or_default(),
         })
     }
 
@@ -521,13 +1079,11 @@ impl Client {
         let request = RequestBuilderWrapper {
             client: self,
             request_builder: self.client.head(&url),
+            body: None,
         };
 
         let res = request
-            .apply_auth(image, RegistryOperation::Pull)
-            .await?
-            .into_request_builder()
-            .send()
+            .send_with_auth(image, RegistryOperation::Pull)
             .await?;
 
         match res.error_for_status() {
@@ -560,8 +1116,7 @@ impl Client {
         manifest: Option<OciImageManifest>,
     ) -> Result<PushResponse> {
         debug!("Pushing image: {:?}", image_ref);
-        self.store_auth_if_needed(image_ref.resolve_registry(), auth)
-            .await;
+        self.store_image_auth(image_ref, auth).await;
 
         let manifest: OciImageManifest = match manifest {
             Some(m) => m,
@@ -595,6 +1150,9 @@ impl Client {
         Ok(PushResponse {
             config_url,
             manifest_url,
+            diagnostics: self
+                .diagnostics(image_ref.resolve_registry())
+                .unwrap_or_default(),
         })
     }
 
@@ -605,7 +1163,8 @@ impl Client {
         data: impl Into<bytes::Bytes>,
         digest: &str,
     ) -> Result<String> {
-        if self.config.use_monolithic_push {
+        let quirks = self.registry_quirks(image_ref.resolve_registry());
+        if self.config.use_monolithic_push || quirks.monolithic_push {
             return self.push_blob_monolithically(image_ref, data, digest).await;
         }
         let data = data.into();
@@ -617,7 +1176,9 @@ impl Client {
             .await
         {
             Ok(url) => Ok(url),
-            Err(OciDistributionError::SpecViolationError(violation)) => {
+            Err(OciDistributionError::SpecViolationError(violation))
+                if quirks.monolithic_fallback =>
+            {
                 warn!(?violation, "Registry is not respecting the OCI Distribution Specification when doing chunked push operations");
                 warn!("Attempting monolithic push");
                 self.push_blob_monolithically(image_ref, data, digest).await
@@ -629,7 +1190,7 @@ impl Client {
     /// Pushes a blob to the registry as a monolith
     ///
     /// Returns the pullable location of the blob
-    async fn push_blob_monolithically(
+    pub(crate) async fn push_blob_monolithically(
         &self,
         image: &Reference,
         blob_data: impl Into<bytes::Bytes>,
@@ -649,13 +1210,13 @@ impl Client {
         blob_data: impl Into<bytes::Bytes>,
         blob_digest: &str,
     ) -> Result<String> {
-        let mut location = self.begin_push_chunked_session(image).await?;
+        let (mut location, min_chunk_size) = self.begin_push_chunked_session(image).await?;
+        let chunk_size = self.push_chunk_size.max(min_chunk_size.unwrap_or_default());
         let mut start: usize = 0;
 
         let mut blob_data: bytes::Bytes = blob_data.into();
         while !blob_data.is_empty() {
-            let chunk_size = self.push_chunk_size.min(blob_data.len());
-            let chunk = blob_data.split_to(chunk_size);
+            let chunk = blob_data.split_to(chunk_size.min(blob_data.len()));
             (location, start) = self.push_chunk(&location, image, chunk, start).await?;
         }
         self.end_push_chunked_session(&location, image, blob_digest)
@@ -671,18 +1232,38 @@ impl Client {
         mut blob_data_stream: T,
         blob_digest: &str,
     ) -> Result<String> {
-        let mut location = self.begin_push_chunked_session(image).await?;
+        let (mut location, min_chunk_size) = self.begin_push_chunked_session(image).await?;
+        let chunk_size = self.push_chunk_size.max(min_chunk_size.unwrap_or_default());
         let mut range_start = 0;
 
+        // Only the last chunk may be smaller than the minimum size, so the data is
+        // buffered into full chunks when there is one
+        let mut pending = bytes::BytesMut::new();
         while let Some(blob_data) = blob_data_stream.next().await {
             let mut blob_data = blob_data?;
+            if min_chunk_size.is_some() {
+                pending.extend_from_slice(&blob_data);
+                while pending.len() >= chunk_size {
+                    let chunk = pending.split_to(chunk_size).freeze();
+                    (location, range_start) = self
+                        .push_chunk(&location, image, chunk, range_start)
+                        .await?;
+                }
+                continue;
+            }
             while !blob_data.is_empty() {
-                let chunk = blob_data.split_to(self.push_chunk_size.min(blob_data.len()));
+                let chunk = blob_data.split_to(chunk_size.min(blob_data.len()));
                 (location, range_start) = self
                     .push_chunk(&location, image, chunk, range_start)
                     .await?;
             }
         }
+        if !pending.is_empty() {
+            (location, range_start) = self
+                .push_chunk(&location, image, pending.freeze(), range_start)
+                .await?;
+        }
+        debug!(size = range_start, "Pushed blob stream");
         self.end_push_chunked_session(&location, image, blob_digest)
             .await
     }
@@ -697,29 +1278,17 @@ impl Client {
         authentication: &RegistryAuth,
         operation: RegistryOperation,
     ) -> Result<Option<String>> {
-        self.store_auth_if_needed(image.resolve_registry(), authentication)
-            .await;
+        self.store_image_auth(image, authentication).await;
         // preserve old caching behavior
-        match self._auth(image, authentication, operation).await {
-            Ok(Some(RegistryTokenType::Bearer(token))) => {
-                self.tokens
-                    .insert(image, operation, RegistryTokenType::Bearer(token.clone()))
-                    .await;
-                Ok(Some(token.token().to_string()))
-            }
-            Ok(Some(RegistryTokenType::Basic(username, password))) => {
-                self.tokens
-                    .insert(
-                        image,
-                        operation,
-                        RegistryTokenType::Basic(username, password),
-                    )
-                    .await;
-                Ok(None)
-            }
-            Ok(None) => Ok(None),
-            Err(e) => Err(e),
-        }
+        let Some(token) = self._auth(image, authentication, operation, &[]).await? else {
+            return Ok(None);
+        };
+        let bearer = match &token {
+            RegistryTokenType::Bearer(token) => Some(token.token().to_string()),
+            RegistryTokenType::Basic(..) => None,
+        };
+        self.tokens.insert(image, operation, token).await;
+        Ok(bearer)
     }
 
     /// Internal auth that retrieves token.
@@ -728,76 +1297,175 @@ impl Client {
         image: &Reference,
         authentication: &RegistryAuth,
         operation: RegistryOperation,
+        sources: &[String],
     ) -> Result<Option<RegistryTokenType>> {
         debug!("Authorizing for image: {:?}", image);
-        // The version request will tell us where to go.
-        let url = format!(
-            "{}://{}/v2/",
-            self.config.protocol.scheme_for(image.resolve_registry()),
-            image.resolve_registry()
-        );
-        debug!(?url);
 
         if let RegistryAuth::Bearer(token) = authentication {
-            return Ok(Some(RegistryTokenType::Bearer(RegistryToken::Token {
-                token: token.clone(),
-            })));
+            return Ok(Some(RegistryTokenType::Bearer(RegistryToken::new(
+                token.clone(),
+            ))));
         }
 
-        let res = self.client.get(&url).send().await?;
-        let dist_hdr = match res.headers().get(reqwest::header::WWW_AUTHENTICATE) {
-            Some(h) => h,
-            None => return Ok(None),
-        };
+        if let Some(challenge) = self.cached_challenge(image).await {
+            match self
+                .authenticate_with(image, &challenge, authentication, operation, sources)
+                .await
+            {
+                // The authorization server of the registry might have changed
+                Err(error) => debug!(?error, "Cannot obtain token, requesting a new challenge"),
+                res => return res,
+            }
+        }
+        let challenge = self.request_challenge(image).await?;
+        self.authenticate_with(image, &challenge, authentication, operation, sources)
+            .await
+    }
 
-        let challenge = match BearerChallenge::try_from(dist_hdr) {
-            Ok(c) => c,
-            Err(e) => {
-                debug!(error = ?e, "Falling back to HTTP Basic Auth");
-                if let RegistryAuth::Basic(username, password) = authentication {
-                    return Ok(Some(RegistryTokenType::Basic(
+    /// Answers the authentication challenge of the registry with the given credentials.
+    async fn authenticate_with(
+        &self,
+        image: &Reference,
+        challenge: &AuthChallenge,
+        authentication: &RegistryAuth,
+        operation: RegistryOperation,
+        sources: &[String],
+    ) -> Result<Option<RegistryTokenType>> {
+        let challenge = match challenge {
+            AuthChallenge::None => return Ok(None),
+            AuthChallenge::Bearer(challenge) => challenge,
+            AuthChallenge::Basic => {
+                debug!("Falling back to HTTP Basic Auth");
+                return Ok(match authentication {
+                    RegistryAuth::Basic(username, password) => Some(RegistryTokenType::Basic(
                         username.to_string(),
                         password.to_string(),
-                    )));
-                }
-                return Ok(None);
+                    )),
+                    RegistryAuth::IdentityToken(token) => Some(RegistryTokenType::Basic(
+                        IDENTITY_TOKEN_USERNAME.to_string(),
+                        token.to_string(),
+                    )),
+                    _ => None,
+                });
             }
         };
 
-        // Allow for either push or pull authentication
-        let scope = match operation {
-            RegistryOperation::Pull => format!("repository:{}:pull", image.repository()),
-            RegistryOperation::Push => format!("repository:{}:pull,push", image.repository()),
-        };
+        let scopes = registry_scopes(image, operation, sources);
+        self.fetch_token(image, challenge, &scopes, authentication)
+            .await
+            .map(Some)
+    }
 
-        let realm = challenge.realm.as_ref();
-        let service = challenge.service.as_ref();
-        let mut query = vec![("scope", &scope)];
+    /// Returns the challenge cached for the repository of the image, or else for its
+    /// registry.
+    async fn cached_challenge(&self, image: &Reference) -> Option<AuthChallenge> {
+        let registry = image.resolve_registry();
+        let challenges = self.challenges.read().await;
+        challenges
+            .get(&format!("{registry}/{}", image.repository()))
+            .or_else(|| challenges.get(registry))
+            .cloned()
+    }
 
-        if let Some(s) = service {
-            query.push(("service", s))
-        }
+    /// Pings the registry to find out how to authenticate, and caches the answer.
+    async fn request_challenge(&self, image: &Reference) -> Result<AuthChallenge> {
+        // The version request will tell us where to go.
+        let (res, _) = self.ping(image.resolve_registry()).await?;
+        Ok(self.challenge_of(image.resolve_registry(), &res).await)
+    }
 
-        // TODO: At some point in the future, we should support sending a secret to the
-        // server for auth. This particular workflow is for read-only public auth.
-        debug!(?realm, ?service, ?scope, "Making authentication call");
+    /// Sends the version request `GET /v2/` to the registry, returning the answer and
+    /// the URL of the request
+    async fn ping(&self, registry: &str) -> Result<(Response, String)> {
+        self.resolve_scheme(registry).await;
+        let url = format!("{}://{}/v2/", self.scheme_for(registry), registry);
+        debug!(?url);
 
-        let auth_res = self
-            .client
-            .get(realm)
-            .query(&query)
-            .apply_authentication(authentication)
-            .send()
-            .await?;
+        let res = self.send(self.client.get(&url)).await?;
+        Ok((res, url))
+    }
 
-        match auth_res.status() {
+    /// Reads the authentication challenge from the answer of the registry to the
+    /// version request, and caches it
+    async fn challenge_of(&self, registry: &str, res: &Response) -> AuthChallenge {
+        let challenge = match res.headers().get(reqwest::header::WWW_AUTHENTICATE) {
+            Some(h) => match BearerChallenge::try_from(h) {
+                Ok(c) => AuthChallenge::Bearer(c),
+                Err(e) => {
+                    debug!(error = ?e, "No Bearer challenge");
+                    AuthChallenge::Basic
+                }
+            },
+            None => AuthChallenge::None,
+        };
+        self.cache_challenge(registry, challenge.clone()).await;
+        challenge
+    }
+
+    /// Caches the challenge of a registry, or of a repository when `key` is made of the
+    /// registry and repository.
+    async fn cache_challenge(&self, key: &str, challenge: AuthChallenge) {
+        self.challenges
+            .write()
+            .await
+            .insert(key.to_string(), challenge);
+    }
+
+    /// Obtains a token for the given scopes from the authorization server described
+    /// by the challenge.
+    async fn fetch_token(
+        &self,
+        image: &Reference,
+        challenge: &BearerChallenge,
+        scopes: &[String],
+        authentication: &RegistryAuth,
+    ) -> Result<RegistryTokenType> {
+        let realm = challenge.realm.as_ref();
+        let service = challenge.service.as_ref();
+        debug!(?realm, ?service, ?scopes, "Making authentication call");
+
+        let oauth2_res = match self
+            .oauth2_token_request(realm, service, scopes, authentication)
+            .await?
+        {
+            Some(res)
+                if res.status() == reqwest::StatusCode::NOT_FOUND
+                    || res.status() == reqwest::StatusCode::METHOD_NOT_ALLOWED =>
+            {
+                debug!(status = ?res.status(), "OAuth2 token flow not supported, falling back to GET");
+                None
+            }
+            res => res,
+        };
+
+        let auth_res = match oauth2_res {
+            Some(res) => res,
+            None => {
+                let mut query: Vec<(&str, &str)> =
+                    scopes.iter().map(|s| ("scope", s.as_str())).collect();
+
+                if let Some(s) = service {
+                    query.push(("service", s))
+                }
+
+                self.send(
+                    self.client
+                        .get(realm)
+                        .query(&query)
+                        .apply_authentication(authentication),
+                )
+                .await?
+            }
+        };
+
+        match auth_res.status() {
             reqwest::StatusCode::OK => {
-                let text = auth_res.text().await?;
-                debug!("Received response from auth request: {}", text);
+                let text = Zeroizing::new(auth_res.text().await?);
                 let token: RegistryToken = serde_json::from_str(&text)
                     .map_err(|e| OciDistributionError::RegistryTokenDecodeError(e.to_string()))?;
+                debug!(?token, "Received response from auth request");
                 debug!("Successfully authorized for image '{:?}'", image);
-                Ok(Some(RegistryTokenType::Bearer(token)))
+                Ok(RegistryTokenType::Bearer(token))
             }
             _ => {
                 let reason = auth_res.text().await?;
@@ -807,6 +1475,46 @@ impl Client {
         }
     }
 
+    /// Exchanges the credentials for a token with a POST request, following the
+    /// OAuth2 flow used by Docker.
+    ///
+    /// Returns `None` when the credentials are not meant to be used with this flow.
+    async fn oauth2_token_request(
+        &self,
+        realm: &str,
+        service: Option<&String>,
+        scopes: &[String],
+        authentication: &RegistryAuth,
+    ) -> Result<Option<Response>> {
+        let mut form = match authentication {
+            RegistryAuth::IdentityToken(token) => {
+                vec![("grant_type", "refresh_token"), ("refresh_token", token)]
+            }
+            RegistryAuth::Basic(username, password) if self.config.oauth2_password_grant => {
+                vec![
+                    ("grant_type", "password"),
+                    ("username", username),
+                    ("password", password),
+                ]
+            }
+            _ => return Ok(None),
+        };
+        // OAuth2 expects the scopes as a single space-separated value
+        let scope = scopes.join(" ");
+        form.push(("client_id", OAUTH2_CLIENT_ID));
+        form.push(("scope", &scope));
+        if let Some(s) = service {
+            form.push(("service", s));
+        }
+
+        debug!(?realm, ?service, ?scope, "Making OAuth2 token request");
+        // The credentials are sent in the body of the request
+        let url =
+            Url::parse(realm).map_err(|e| OciDistributionError::UrlParseError(e.to_string()))?;
+        self.check_credentials_allowed(&url)?;
+        Ok(Some(self.send(self.client.post(realm).form(&form)).await?))
+    }
+
     /// Fetch a manifest's digest from the remote OCI Distribution service.
     ///
     /// If the connection has already gone through authentication, this will
@@ -820,23 +1528,26 @@ impl Client {
         image: &Reference,
         auth: &RegistryAuth,
     ) -> Result<String> {
-        self.store_auth_if_needed(image.resolve_registry(), auth)
-            .await;
+        self.store_image_auth(image, auth).await;
 
         let url = self.to_v2_manifest_url(image);
         debug!("HEAD image manifest from {}", url);
         let res = RequestBuilderWrapper::from_client(self, |client| client.head(&url))
-            .apply_accept(MIME_TYPES_DISTRIBUTION_MANIFEST)?
-            .apply_auth(image, RegistryOperation::Pull)
-            .await?
-            .into_request_builder()
-            .send()
+            .apply_accept(self.manifest_media_types(image))?
+            .send_with_auth(image, RegistryOperation::Pull)
             .await?;
 
-        if let Some(digest) = digest_header_value(res.headers().clone())? {
+        let headers = res.headers().clone();
+        if let Some(digest) = digest_header_value(headers.clone())? {
             let status = res.status();
             let body = res.bytes().await?;
-            validate_registry_response(status, &body, &url)?;
+            self.validate_registry_response(
+                status,
+                &headers,
+                &body,
+                &url,
+                ErrorContext::new(Method::HEAD, image),
+            )?;
 
             // If the reference has a digest and the digest header has a matching algorithm, compare
             // them and return an error if they don't match.
@@ -858,17 +1569,20 @@ impl Client {
         } else {
             debug!("GET image manifest from {}", url);
             let res = RequestBuilderWrapper::from_client(self, |client| client.get(&url))
-                .apply_accept(MIME_TYPES_DISTRIBUTION_MANIFEST)?
-                .apply_auth(image, RegistryOperation::Pull)
-                .await?
-                .into_request_builder()
-                .send()
+                .apply_accept(self.manifest_media_types(image))?
+                .send_with_auth(image, RegistryOperation::Pull)
                 .await?;
             let status = res.status();
             trace!(headers = ?res.headers(), "Got Headers");
             let headers = res.headers().clone();
             let body = res.bytes().await?;
-            validate_registry_response(status, &body, &url)?;
+            self.validate_registry_response(
+                status,
+                &headers,
+                &body,
+                &url,
+                ErrorContext::new(Method::GET, image),
+            )?;
 
             validate_digest(&body, digest_header_value(headers)?, image.digest())
                 .map_err(OciDistributionError::from)
@@ -910,8 +1624,7 @@ impl Client {
         image: &Reference,
         auth: &RegistryAuth,
     ) -> Result<(OciImageManifest, String)> {
-        self.store_auth_if_needed(image.resolve_registry(), auth)
-            .await;
+        self.store_image_auth(image, auth).await;
 
         self._pull_image_manifest(image).await
     }
@@ -929,8 +1642,7 @@ impl Client {
         auth: &RegistryAuth,
         accepted_media_types: &[&str],
     ) -> Result<(bytes::Bytes, String)> {
-        self.store_auth_if_needed(image.resolve_registry(), auth)
-            .await;
+        self.store_image_auth(image, auth).await;
 
         self._pull_manifest_raw(image, accepted_media_types).await
     }
@@ -947,8 +1659,7 @@ impl Client {
         image: &Reference,
         auth: &RegistryAuth,
     ) -> Result<(OciManifest, String)> {
-        self.store_auth_if_needed(image.resolve_registry(), auth)
-            .await;
+        self.store_image_auth(image, auth).await;
 
         self._pull_manifest(image).await
     }
@@ -1010,16 +1721,19 @@ impl Client {
 
         let res = RequestBuilderWrapper::from_client(self, |client| client.get(&url))
             .apply_accept(accepted_media_types)?
-            .apply_auth(image, RegistryOperation::Pull)
-            .await?
-            .into_request_builder()
-            .send()
+            .send_with_auth(image, RegistryOperation::Pull)
             .await?;
         let status = res.status();
         let headers = res.headers().clone();
         let body = res.bytes().await?;
 
-        validate_registry_response(status, &body, &url)?;
+        self.validate_registry_response(
+            status,
+            &headers,
+            &body,
+            &url,
+            ErrorContext::new(Method::GET, image),
+        )?;
 
         let digest_header = digest_header_value(headers)?;
         let digest = validate_digest(&body, digest_header, image.digest())?;
@@ -1033,7 +1747,7 @@ impl Client {
     /// use the bearer token. Otherwise, this will attempt an anonymous pull.
     async fn _pull_manifest(&self, image: &Reference) -> Result<(OciManifest, String)> {
         let (body, digest) = self
-            ._pull_manifest_raw(image, MIME_TYPES_DISTRIBUTION_MANIFEST)
+            ._pull_manifest_raw(image, self.manifest_media_types(image))
             .await?;
 
         self.validate_image_manifest(&body).await?;
@@ -1079,8 +1793,7 @@ impl Client {
         image: &Reference,
         auth: &RegistryAuth,
     ) -> Result<(OciImageManifest, String, String)> {
-        self.store_auth_if_needed(image.resolve_registry(), auth)
-            .await;
+        self.store_image_auth(image, auth).await;
 
         self._pull_manifest_and_config(image)
             .await
@@ -1120,8 +1833,7 @@ impl Client {
         auth: &RegistryAuth,
         manifest: OciImageIndex,
     ) -> Result<String> {
-        self.store_auth_if_needed(reference.resolve_registry(), auth)
-            .await;
+        self.store_image_auth(reference, auth).await;
         self.push_manifest(reference, &OciManifest::ImageIndex(manifest))
             .await
     }
@@ -1149,7 +1861,21 @@ impl Client {
         let layer_digest = layer.as_layer_descriptor().digest.to_string();
         let mut layer_digester = Digester::new(&layer_digest)?;
 
-        let mut stream = response.error_for_status()?.bytes_stream();
+        let status = response.status();
+        if !status.is_success() {
+            let url = response.url().to_string();
+            let headers = response.headers().clone();
+            let body = response.bytes().await?;
+            let context = ErrorContext::new(Method::GET, image).with_digest(&layer_digest);
+            // Fails, as the status isn't successful
+            return self.validate_registry_response(status, &headers, &body, &url, context);
+        }
+        let stream = body_stream(response);
+        let mut stream: BoxStream<'_, reqwest::Result<bytes::Bytes>> =
+            match self.bandwidths.for_registry(image.resolve_registry()) {
+                Some(throttle) => Box::pin(throttle.stream(stream)),
+                None => Box::pin(stream),
+            };
 
         while let Some(bytes) = stream.next().await {
             let bytes = bytes?;
@@ -1222,6 +1948,7 @@ impl Client {
             self.pull_blob_response(image, &layer, None, None).await?,
             layer,
             true,
+            self.bandwidths.for_registry(image.resolve_registry()),
         )
     }
 
@@ -1245,17 +1972,23 @@ impl Client {
             .pull_blob_response(image, &layer, Some(offset), length)
             .await?;
 
+        let throttle = self.bandwidths.for_registry(image.resolve_registry());
         let status = response.status();
         match status {
             StatusCode::OK => Ok(BlobResponse::Full(stream_from_response(
-                response, &layer, true,
+                response, &layer, true, throttle,
             )?)),
             StatusCode::PARTIAL_CONTENT => Ok(BlobResponse::Partial(stream_from_response(
-                response, &layer, false,
+                response, &layer, false, throttle,
             )?)),
             _ => Err(OciDistributionError::ServerError {
                 code: status.as_u16(),
                 url: response.url().to_string(),
+                diagnostics: Box::new(ResponseDiagnostics::from_headers(response.headers())),
+                context: Box::new(
+                    ErrorContext::new(Method::GET, image)
+                        .with_digest(layer.as_layer_descriptor().digest),
+                ),
                 message: response.text().await?,
             }),
         }
@@ -1272,11 +2005,7 @@ impl Client {
         let layer = layer.as_layer_descriptor();
         let url = self.to_v2_blob_url(image, layer.digest);
 
-        let mut request = RequestBuilderWrapper::from_client(self, |client| client.get(&url))
-            .apply_accept(MIME_TYPES_DISTRIBUTION_MANIFEST)?
-            .apply_auth(image, RegistryOperation::Pull)
-            .await?
-            .into_request_builder();
+        let mut request = self.client.get(&url);
         if let (Some(off), Some(len)) = (offset, length) {
             let end = (off + len).saturating_sub(1);
             request = request.header(
@@ -1289,7 +2018,14 @@ impl Client {
                 HeaderValue::from_str(&format!("bytes={offset}-")).unwrap(),
             );
         }
-        let mut response = request.send().await?;
+        let mut response = RequestBuilderWrapper {
+            client: self,
+            request_builder: request,
+            body: None,
+        }
+        .apply_accept(MIME_TYPES_DISTRIBUTION_MANIFEST)?
+        .send_with_auth(image, RegistryOperation::Pull)
+        .await?;
 
         if let Some(urls) = &layer.urls {
             for url in urls {
@@ -1314,7 +2050,7 @@ impl Client {
                             HeaderValue::from_str(&format!("bytes={offset}-")).unwrap(),
                         );
                     }
-                    response = request.send().await?
+                    response = self.send(request).await?
                 }
             }
         }
@@ -1322,46 +2058,159 @@ impl Client {
         Ok(response)
     }
 
+    /// Resolves the URL to download a blob from, without downloading it.
+    ///
+    /// Many registries redirect the downloads of blobs to presigned URLs of an object
+    /// storage or of a CDN. The redirects within the registry are followed with
+    /// authentication, and the first URL outside of the registry is returned without
+    /// being requested, along with its expiry when the URL tells it. When the registry
+    /// serves the blob itself, its own URL is returned.
+    ///
+    /// The URL can then be handed to another downloader. The content isn't verified
+    /// by this client anymore, which is up to the caller, e.g. with a [`Digester`]
+    /// created for the digest of the layer.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn resolve_blob_url(
+        &self,
+        image: &Reference,
+        layer: impl AsLayerDescriptor,
+    ) -> Result<BlobUrl> {
+        let digest = layer.as_layer_descriptor().digest;
+        let clients = self.redirectless_client()?;
+        let mut url = self.to_v2_blob_url(image, digest);
+        for _ in 0..MAX_BLOB_REDIRECTS {
+            let response = RequestBuilderWrapper {
+                client: self,
+                request_builder: clients.get(&url),
+                body: None,
+            }
+            .apply_accept(MIME_TYPES_DISTRIBUTION_MANIFEST)?
+            .send_with_auth(image, RegistryOperation::Pull)
+            .await?;
+
+            let status = response.status();
+            if status.is_success() {
+                // The blob is served by the registry, and isn't downloaded as the
+                // response is dropped
+                return Ok(BlobUrl::new(response.url().clone(), false));
+            }
+            if !status.is_redirection() {
+                let url = response.url().to_string();
+                let headers = response.headers().clone();
+                let body = response.bytes().await?;
+                let context = ErrorContext::new(Method::GET, image).with_digest(digest);
+                self.validate_registry_response(status, &headers, &body, &url, context)?;
+                return Err(OciDistributionError::SpecViolationError(format!(
+                    "unexpected status {status} when downloading blob {digest}"
+                )));
+            }
+
+            let location = response
+                .headers()
+                .get(reqwest::header::LOCATION)
+                .ok_or(OciDistributionError::RegistryNoLocationError)?
+                .to_str()?;
+            let next = response
+                .url()
+                .join(location)
+                .map_err(|e| OciDistributionError::UrlParseError(e.to_string()))?;
+            if next.scheme() != response.url().scheme()
+                || next.authority() != response.url().authority()
+            {
+                debug!(%digest, url = %next, "Blob download redirected out of the registry");
+                return Ok(BlobUrl::new(next, true));
+            }
+            url = next.into();
+        }
+        Err(OciDistributionError::SpecViolationError(format!(
+            "too many redirects when downloading blob {digest}"
+        )))
+    }
+
+    /// The HTTP clients not following redirects
+    #[cfg(not(target_arch = "wasm32"))]
+    fn redirectless_client(&self) -> Result<&HttpClients> {
+        if let Some(clients) = self.redirectless_client.get() {
+            return Ok(clients);
+        }
+        let clients = http_clients_for(&self.config, false)?;
+        Ok(self.redirectless_client.get_or_init(|| clients))
+    }
+
     /// Begins a session to push an image to registry in a monolithical way
     ///
     /// Returns URL with session UUID
     async fn begin_push_monolithical_session(&self, image: &Reference) -> Result<String> {
         let url = &self.to_v2_blob_upload_url(image);
         debug!(?url, "begin_push_monolithical_session");
-        let res = RequestBuilderWrapper::from_client(self, |client| client.post(url))
-            .apply_auth(image, RegistryOperation::Push)
-            .await?
-            .into_request_builder()
-            // We set "Content-Length" to 0 here even though the OCI Distribution
-            // spec does not strictly require that. In practice we have seen that
-            // certain registries require "Content-Length" to be present for all
-            // types of push sessions.
-            .header("Content-Length", 0)
-            .send()
-            .await?;
+        let res = RequestBuilderWrapper::from_client(self, |client| {
+            self.empty_upload_request(image, client.post(url))
+        })
+        .send_with_auth(image, RegistryOperation::Push)
+        .await?;
 
         // OCI spec requires the status code be 202 Accepted to successfully begin the push process
-        self.extract_location_header(image, res, &reqwest::StatusCode::ACCEPTED)
-            .await
+        self.extract_location_header(
+            image,
+            res,
+            &reqwest::StatusCode::ACCEPTED,
+            ErrorContext::new(Method::POST, image),
+        )
+        .await
     }
 
     /// Begins a session to push an image to registry as a series of chunks
     ///
-    /// Returns URL with session UUID
-    async fn begin_push_chunked_session(&self, image: &Reference) -> Result<String> {
+    /// Returns URL with session UUID, and the minimum size of the chunks but the last
+    /// one, if any
+    async fn begin_push_chunked_session(
+        &self,
+        image: &Reference,
+    ) -> Result<(String, Option<usize>)> {
         let url = &self.to_v2_blob_upload_url(image);
         debug!(?url, "begin_push_session");
-        let res = RequestBuilderWrapper::from_client(self, |client| client.post(url))
-            .apply_auth(image, RegistryOperation::Push)
-            .await?
-            .into_request_builder()
-            .header("Content-Length", 0)
-            .send()
-            .await?;
+        let res = RequestBuilderWrapper::from_client(self, |client| {
+            self.empty_upload_request(image, client.post(url))
+        })
+        .send_with_auth(image, RegistryOperation::Push)
+        .await?;
+
+        let required = res
+            .headers()
+            .get(OCI_CHUNK_MIN_LENGTH_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<usize>().ok());
+        let min_chunk_size = self
+            .registry_quirks(image.resolve_registry())
+            .min_chunk_size
+            .max(required);
 
         // OCI spec requires the status code be 202 Accepted to successfully begin the push process
-        self.extract_location_header(image, res, &reqwest::StatusCode::ACCEPTED)
-            .await
+        let location = self
+            .extract_location_header(
+                image,
+                res,
+                &reqwest::StatusCode::ACCEPTED,
+                ErrorContext::new(Method::POST, image),
+            )
+            .await?;
+        Ok((location, min_chunk_size))
+    }
+
+    /// Prepares a request starting or closing an upload, which has no content
+    fn empty_upload_request(&self, image: &Reference, request: RequestBuilder) -> RequestBuilder {
+        // We set "Content-Length" to 0 here even though the OCI Distribution
+        // spec does not strictly require that. In practice we have seen that
+        // certain registries require "Content-Length" to be present for all
+        // types of push sessions.
+        if self
+            .registry_quirks(image.resolve_registry())
+            .empty_upload_content_length
+        {
+            request.header("Content-Length", 0)
+        } else {
+            request
+        }
     }
 
     /// Closes the chunked push session
@@ -1375,15 +2224,18 @@ impl Client {
     ) -> Result<String> {
         let url = Url::parse_with_params(location, &[("digest", digest)])
             .map_err(|e| OciDistributionError::GenericError(Some(e.to_string())))?;
-        let res = RequestBuilderWrapper::from_client(self, |client| client.put(url.clone()))
-            .apply_auth(image, RegistryOperation::Push)
-            .await?
-            .into_request_builder()
-            .header("Content-Length", 0)
-            .send()
-            .await?;
-        self.extract_location_header(image, res, &reqwest::StatusCode::CREATED)
-            .await
+        let res = RequestBuilderWrapper::from_client(self, |client| {
+            self.empty_upload_request(image, client.put(url))
+        })
+        .send_with_auth(image, RegistryOperation::Push)
+        .await?;
+        self.extract_location_header(
+            image,
+            res,
+            &reqwest::StatusCode::CREATED,
+            ErrorContext::new(Method::PUT, image).with_digest(digest),
+        )
+        .await
     }
 
     /// Pushes a layer to a registry as a monolithical blob.
@@ -1412,18 +2264,20 @@ impl Client {
         );
         headers.insert("Content-Type", "application/octet-stream".parse().unwrap());
 
-        let res = RequestBuilderWrapper::from_client(self, |client| client.put(&url))
-            .apply_auth(image, RegistryOperation::Push)
-            .await?
-            .into_request_builder()
-            .headers(headers)
-            .body(layer)
-            .send()
-            .await?;
+        let res =
+            RequestBuilderWrapper::from_client(self, |client| client.put(&url).headers(headers))
+                .with_body(layer)
+                .send_with_auth(image, RegistryOperation::Push)
+                .await?;
 
         // Returns location
-        self.extract_location_header(image, res, &reqwest::StatusCode::CREATED)
-            .await
+        self.extract_location_header(
+            image,
+            res,
+            &reqwest::StatusCode::CREATED,
+            ErrorContext::new(Method::PUT, image).with_digest(blob_digest),
+        )
+        .await
     }
 
     /// Pushes a single chunk of a blob to a registry, as part of a chunked blob upload.
@@ -1464,19 +2318,22 @@ impl Client {
             "Pushing chunk"
         );
 
-        let res = RequestBuilderWrapper::from_client(self, |client| client.patch(location))
-            .apply_auth(image, RegistryOperation::Push)
-            .await?
-            .into_request_builder()
-            .headers(headers)
-            .body(blob_chunk)
-            .send()
-            .await?;
+        let res = RequestBuilderWrapper::from_client(self, |client| {
+            client.patch(location).headers(headers)
+        })
+        .with_body(blob_chunk)
+        .send_with_auth(image, RegistryOperation::Push)
+        .await?;
 
         // Returns location for next chunk and the start byte for the next range
         Ok((
-            self.extract_location_header(image, res, &reqwest::StatusCode::ACCEPTED)
-                .await?,
+            self.extract_location_header(
+                image,
+                res,
+                &reqwest::StatusCode::ACCEPTED,
+                ErrorContext::new(Method::PATCH, image),
+            )
+            .await?,
             end_range_inclusive + 1,
         ))
     }
@@ -1495,15 +2352,23 @@ impl Client {
         )
         .map_err(|e| OciDistributionError::UrlParseError(e.to_string()))?;
 
-        let res = RequestBuilderWrapper::from_client(self, |client| client.post(url.clone()))
-            .apply_auth(image, RegistryOperation::Push)
-            .await?
-            .into_request_builder()
-            .send()
+        // The token must also grant pull access to the source repository
+        let sources = if source.resolve_registry() == image.resolve_registry() {
+            vec![source.repository().to_string()]
+        } else {
+            vec![]
+        };
+        let res = RequestBuilderWrapper::from_client(self, |client| client.post(url))
+            .send_with_auth_for_sources(image, RegistryOperation::Push, &sources)
             .await?;
 
-        self.extract_location_header(image, res, &reqwest::StatusCode::CREATED)
-            .await?;
+        self.extract_location_header(
+            image,
+            res,
+            &reqwest::StatusCode::CREATED,
+            ErrorContext::new(Method::POST, image).with_digest(digest),
+        )
+        .await?;
 
         Ok(())
     }
@@ -1518,6 +2383,16 @@ impl Client {
 
         // Serialize the manifest with a canonical json formatter, as described at
         // https://github.com/opencontainers/image-spec/blob/main/considerations.md#json
+        let docker_manifest;
+        let manifest = if self
+            .registry_quirks(image.resolve_registry())
+            .docker_media_types_only
+        {
+            docker_manifest = with_docker_media_types(manifest);
+            &docker_manifest
+        } else {
+            manifest
+        };
         let mut body = Vec::new();
         let mut ser = serde_json::Serializer::with_formatter(&mut body, CanonicalFormatter::new());
         manifest.serialize(&mut ser).unwrap();
@@ -1535,33 +2410,39 @@ impl Client {
         body: impl Into<bytes::Bytes>,
         content_type: HeaderValue,
     ) -> Result<String> {
+        let body = body.into();
         let url = self.to_v2_manifest_url(image);
         debug!(?url, ?content_type, "push manifest");
 
         let mut headers = HeaderMap::new();
         headers.insert("Content-Type", content_type);
 
-        let body = body.into();
-
         // Calculate the digest of the manifest, this is useful
         // if the remote registry is violating the OCI Distribution Specification.
         // See below for more details.
         let manifest_hash = sha256_digest(&body);
 
-        let res = RequestBuilderWrapper::from_client(self, |client| client.put(url.clone()))
-            .apply_auth(image, RegistryOperation::Push)
-            .await?
-            .into_request_builder()
-            .headers(headers)
-            .body(body)
-            .send()
-            .await?;
+        let res = RequestBuilderWrapper::from_client(self, |client| {
+            client.put(url.clone()).headers(headers)
+        })
+        .with_body(body)
+        .send_with_auth(image, RegistryOperation::Push)
+        .await?;
 
         let ret = self
-            .extract_location_header(image, res, &reqwest::StatusCode::CREATED)
+            .extract_location_header(
+                image,
+                res,
+                &reqwest::StatusCode::CREATED,
+                ErrorContext::new(Method::PUT, image),
+            )
             .await;
 
-        if matches!(ret, Err(OciDistributionError::RegistryNoLocationError)) {
+        if matches!(ret, Err(OciDistributionError::RegistryNoLocationError))
+            && self
+                .registry_quirks(image.resolve_registry())
+                .missing_manifest_location
+        {
             // The registry is violating the OCI Distribution Spec, BUT the OCI
             // image/artifact has been uploaded successfully.
             // The `Location` header contains the sha256 digest of the manifest,
@@ -1588,31 +2469,83 @@ impl Client {
         image: &Reference,
         artifact_type: Option<&str>,
     ) -> Result<OciImageIndex> {
+        if self
+            .registry_quirks(image.resolve_registry())
+            .no_referrers_api
+        {
+            return self.pull_referrers_index(image, artifact_type).await;
+        }
         let url = self.to_v2_referrers_url(image, artifact_type)?;
         debug!("Pulling referrers from {}", url);
 
         let res = RequestBuilderWrapper::from_client(self, |client| client.get(&url))
             .apply_accept(MIME_TYPES_DISTRIBUTION_MANIFEST)?
-            .apply_auth(image, RegistryOperation::Pull)
-            .await?
-            .into_request_builder()
-            .send()
+            .send_with_auth(image, RegistryOperation::Pull)
             .await?;
         let status = res.status();
+        let headers = res.headers().clone();
         let body = res.bytes().await?;
 
-        validate_registry_response(status, &body, &url)?;
+        self.validate_registry_response(
+            status,
+            &headers,
+            &body,
+            &url,
+            ErrorContext::new(Method::GET, image),
+        )?;
         let manifest = serde_json::from_slice(&body)
             .map_err(|e| OciDistributionError::ManifestParsingError(e.to_string()))?;
 
         Ok(manifest)
     }
 
+    /// Pulls the referrers of the image from the index of the referrers tag schema, for
+    /// the registries without the referrers API
+    async fn pull_referrers_index(
+        &self,
+        image: &Reference,
+        artifact_type: Option<&str>,
+    ) -> Result<OciImageIndex> {
+        let Some(digest) = image.digest() else {
+            return Err(OciDistributionError::GenericError(Some(
+                "Getting referrers for a tag is not supported".into(),
+            )));
+        };
+        let mut index = self
+            .referrers_index(&referrers_index_reference(image, digest))
+            .await?;
+        if let (Some(artifact_type), Some(manifests)) =
+            (artifact_type, index["manifests"].as_array_mut())
+        {
+            manifests.retain(|manifest| manifest["artifactType"] == artifact_type);
+        }
+        serde_json::from_value(index)
+            .map_err(|e| OciDistributionError::ManifestParsingError(e.to_string()))
+    }
+
+    /// Pulls an index of the referrers tag schema, which is empty when missing
+    async fn referrers_index(&self, index_image: &Reference) -> Result<serde_json::Value> {
+        match self
+            ._pull_manifest_raw(index_image, &[OCI_IMAGE_INDEX_MEDIA_TYPE])
+            .await
+        {
+            Ok((body, _)) => serde_json::from_slice(&body)
+                .map_err(|e| OciDistributionError::ManifestParsingError(e.to_string())),
+            Err(error) if error.is_not_found() => Ok(serde_json::json!({
+                "schemaVersion": 2,
+                "mediaType": OCI_IMAGE_INDEX_MEDIA_TYPE,
+                "manifests": [],
+            })),
+            Err(error) => Err(error),
+        }
+    }
+
     async fn extract_location_header(
         &self,
         image: &Reference,
         res: reqwest::Response,
         expected_status: &reqwest::StatusCode,
+        context: ErrorContext,
     ) -> Result<String> {
         debug!(expected_status_code=?expected_status.as_u16(),
             status_code=?res.status().as_u16(),
@@ -1631,10 +2564,14 @@ impl Client {
                 res.status(),
             )))
         } else {
+            let status = res.status();
             let url = res.url().to_string();
-            let code = res.status().as_u16();
-            let message = res.text().await?;
-            Err(OciDistributionError::ServerError { url, code, message })
+            let headers = res.headers().clone();
+            let body = res.bytes().await?;
+            self.validate_registry_response(status, &headers, &body, &url, context)?;
+            Err(OciDistributionError::SpecViolationError(format!(
+                "Expected HTTP Status {expected_status}, got {status} instead",
+            )))
         }
     }
 
@@ -1652,19 +2589,478 @@ impl Client {
             let registry = image.resolve_registry();
             Ok(format!(
                 "{scheme}://{registry}{lh}",
-                scheme = self.config.protocol.scheme_for(registry)
+                scheme = self.scheme_for(registry)
             ))
         } else {
             Ok(lh.to_string())
         }
     }
 
+    /// Probes the registry: checks that it implements the distribution API, finds out
+    /// how it asks its clients to authenticate, and lists its extensions.
+    ///
+    /// An error is returned when the registry cannot be reached or doesn't answer the
+    /// version request `GET /v2/` as the specification requires, which makes this
+    /// usable as a health check. The capabilities found out are cached for the
+    /// registry, see [`Client::capabilities`].
+    pub async fn probe(&self, registry: &str) -> Result<RegistryCapabilities> {
+        let mut capabilities = self.probe_registry(registry).await?;
+        self.cache_capabilities(registry, &mut capabilities);
+        Ok(capabilities)
+    }
+
+    /// Probes the registry of the image like [`Client::probe`], and checks in the
+    /// repository of the image whether the registry implements the referrers API,
+    /// accepts uploads, requires chunks of a minimum size, mounts blobs and deletes
+    /// tags.
+    ///
+    /// The checks write to the repository, and need push and delete access to it: upload
+    /// sessions are started and then cancelled, and a tag that doesn't exist is deleted.
+    /// The checks the credentials don't allow are left unknown. The capabilities found
+    /// out are cached for the registry, and the operations on the registry then apply
+    /// the workarounds they call for, see [`Client::registry_quirks`]. Whether tags can be
+    /// deleted is only reported.
+    pub async fn probe_repository(
+        &self,
+        image: &Reference,
+        auth: &RegistryAuth,
+    ) -> Result<RegistryCapabilities> {
+        let registry = image.resolve_registry();
+        let mut capabilities = self.probe_registry(registry).await?;
+        self.store_image_auth(image, auth).await;
+        // Digests always have referrers, blobs and upload sessions to check with
+        let image = Reference::with_digest(
+            image.registry().to_string(),
+            image.repository().to_string(),
+            sha256_digest(b"{}"),
+        );
+
+        capabilities.referrers_api = self.probe_referrers(&image).await;
+        (capabilities.uploads, capabilities.chunk_min_length) = self.probe_uploads(&image).await;
+        capabilities.blob_mount = self.probe_blob_mount(&image).await;
+        capabilities.tag_delete = self.probe_tag_delete(&image).await;
+        self.cache_capabilities(registry, &mut capabilities);
+        Ok(capabilities)
+    }
+
+    /// The capabilities of the registry found out by its last probe, if any
+    pub fn capabilities(&self, registry: &str) -> Option<RegistryCapabilities> {
+        self.capabilities.read().unwrap().get(registry).cloned()
+    }
+
+    /// Caches the capabilities of the registry, keeping the ones checked by a previous
+    /// probe but not by this one
+    fn cache_capabilities(&self, registry: &str, capabilities: &mut RegistryCapabilities) {
+        let mut cache = self.capabilities.write().unwrap();
+        if let Some(previous) = cache.get(registry) {
+            capabilities.merge(previous);
+        }
+        cache.insert(registry.to_string(), capabilities.clone());
+    }
+
+    /// Checks the answer of the registry to the version request, and lists its
+    /// extensions
+    async fn probe_registry(&self, registry: &str) -> Result<RegistryCapabilities> {
+        let (res, url) = self.ping(registry).await?;
+        let status = res.status();
+        if status == StatusCode::NOT_FOUND {
+            return Err(OciDistributionError::SpecViolationError(format!(
+                "{registry} doesn't implement the distribution API"
+            )));
+        }
+        let api_version = res
+            .headers()
+            .get(DISTRIBUTION_API_VERSION_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        if let Some(version) = api_version
+            .as_deref()
+            .filter(|version| *version != DISTRIBUTION_API_VERSION)
+        {
+            return Err(OciDistributionError::SpecViolationError(format!(
+                "{registry} implements the unsupported API version {version}"
+            )));
+        }
+        let auth = match res.headers().get(reqwest::header::WWW_AUTHENTICATE) {
+            Some(value) => AuthScheme::from_header(value),
+            None => AuthScheme::None,
+        };
+        self.challenge_of(registry, &res).await;
+        if status != StatusCode::UNAUTHORIZED {
+            let headers = res.headers().clone();
+            self.validate_registry_response(
+                status,
+                &headers,
+                &res.bytes().await?,
+                &url,
+                ErrorContext {
+                    method: Some(Method::GET),
+                    ..Default::default()
+                },
+            )?;
+        }
+
+        let url = format!("{url}_oci/ext/discover");
+        let extensions = match self.send(self.client.get(&url)).await {
+            Ok(res) if res.status() == StatusCode::OK => res
+                .json::<ExtensionList>()
+                .await
+                .map(|list| list.extensions)
+                .unwrap_or_default(),
+            Ok(_) => vec![],
+            Err(error) => {
+                debug!(?error, %registry, "Cannot discover extensions");
+                vec![]
+            }
+        };
+
+        Ok(RegistryCapabilities {
+            api_version,
+            auth,
+            extensions,
+            ..Default::default()
+        })
+    }
+
+    /// Checks whether the registry implements the referrers API, by listing the
+    /// referrers of the digest of the image
+    async fn probe_referrers(&self, image: &Reference) -> Option<bool> {
+        let url = self.to_v2_referrers_url(image, None).ok()?;
+        let res = RequestBuilderWrapper::from_client(self, |client| client.get(&url))
+            .apply_accept(&[OCI_IMAGE_INDEX_MEDIA_TYPE])
+            .ok()?
+            .send_with_auth(image, RegistryOperation::Pull)
+            .await;
+        match probe_status(res).await? {
+            (StatusCode::OK, _) => Some(true),
+            // The repository may just not exist
+            (StatusCode::NOT_FOUND, codes) if !codes.contains(&OciErrorCode::NameUnknown) => {
+                Some(false)
+            }
+            _ => None,
+        }
+    }
+
+    /// Checks whether the registry accepts uploads to the repository, and the minimum
+    /// size of their chunks, by starting an upload session and then cancelling it
+    async fn probe_uploads(&self, image: &Reference) -> (Option<bool>, Option<usize>) {
+        let url = self.to_v2_blob_upload_url(image);
+        let res = RequestBuilderWrapper::from_client(self, |client| {
+            self.empty_upload_request(image, client.post(&url))
+        })
+        .send_with_auth(image, RegistryOperation::Push)
+        .await;
+        let res = match res {
+            Ok(res) if res.status() == StatusCode::ACCEPTED => res,
+            res => {
+                let uploads = match probe_status(res).await {
+                    Some((StatusCode::METHOD_NOT_ALLOWED, _)) => Some(false),
+                    Some((_, codes)) if codes.contains(&OciErrorCode::Unsupported) => Some(false),
+                    _ => None,
+                };
+                return (uploads, None);
+            }
+        };
+        let chunk_min_length = res
+            .headers()
+            .get(OCI_CHUNK_MIN_LENGTH_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<usize>().ok());
+        self.cancel_upload(image, res).await;
+        (Some(true), chunk_min_length)
+    }
+
+    /// Checks whether the registry mounts blobs, by mounting the blob of the digest of
+    /// the image from the repository into itself. This is only known when the
+    /// repository has the blob.
+    async fn probe_blob_mount(&self, image: &Reference) -> Option<bool> {
+        let digest = image.digest()?;
+        if !self.blob_exists(image, digest).await.ok()? {
+            return None;
+        }
+        let url = Url::parse_with_params(
+            &self.to_v2_blob_upload_url(image),
+            &[("mount", digest), ("from", image.repository())],
+        )
+        .ok()?;
+        let res = RequestBuilderWrapper::from_client(self, |client| {
+            self.empty_upload_request(image, client.post(url))
+        })
+        .send_with_auth(image, RegistryOperation::Push)
+        .await
+        .ok()?;
+        match res.status() {
+            StatusCode::CREATED => Some(true),
+            StatusCode::ACCEPTED => {
+                self.cancel_upload(image, res).await;
+                Some(false)
+            }
+            _ => None,
+        }
+    }
+
+    /// Checks whether the registry deletes tags, by deleting a tag that doesn't exist
+    async fn probe_tag_delete(&self, image: &Reference) -> Option<bool> {
+        let tag = Reference::with_tag(
+            image.registry().to_string(),
+            image.repository().to_string(),
+            PROBE_TAG.to_string(),
+        );
+        let url = self.to_v2_manifest_url(&tag);
+        let res = RequestBuilderWrapper::from_client(self, |client| client.delete(&url))
+            .send_with_auth(&tag, RegistryOperation::Delete)
+            .await;
+        match probe_status(res).await? {
+            (StatusCode::ACCEPTED, _) => Some(true),
+            (StatusCode::NOT_FOUND, codes) if !codes.contains(&OciErrorCode::NameUnknown) => {
+                Some(true)
+            }
+            (StatusCode::METHOD_NOT_ALLOWED, _) => Some(false),
+            (_, codes) if codes.contains(&OciErrorCode::Unsupported) => Some(false),
+            _ => None,
+        }
+    }
+
+    /// Cancels the upload session started by the response
+    async fn cancel_upload(&self, image: &Reference, res: Response) {
+        let url = match self
+            .extract_location_header(
+                image,
+                res,
+                &StatusCode::ACCEPTED,
+                ErrorContext::new(Method::POST, image),
+            )
+            .await
+        {
+            Ok(url) => url,
+            Err(error) => {
+                debug!(?error, "Cannot find upload session to cancel");
+                return;
+            }
+        };
+        if let Err(error) = RequestBuilderWrapper::from_client(self, |client| client.delete(&url))
+            .send_with_auth(image, RegistryOperation::Push)
+            .await
+        {
+            debug!(?error, "Cannot cancel upload session");
+        }
+    }
+
+    /// The quirks of the registry: the ones configured in
+    /// [`ClientConfig::registry_quirks`], or else the ones detected from its host name by
+    /// [`RegistryQuirks::detect`], plus the workarounds called for by the capabilities
+    /// found out by the last probe of the registry
+    pub fn registry_quirks(&self, registry: &str) -> RegistryQuirks {
+        let quirks = &self.config.registry_quirks;
+        let mut quirks = quirks
+            .get(registry)
+            .or_else(|| {
+                let (host, _) = registry.rsplit_once(':')?;
+                quirks.get(host)
+            })
+            .copied()
+            .unwrap_or_else(|| RegistryQuirks::detect(registry));
+        if let Some(capabilities) = self.capabilities.read().unwrap().get(registry) {
+            capabilities.apply_to(&mut quirks);
+        }
+        quirks
+    }
+
+    /// The manifest media types to accept from the registry of the image
+    fn manifest_media_types(&self, image: &Reference) -> &'static [&'static str] {
+        if self
+            .registry_quirks(image.resolve_registry())
+            .docker_media_types_only
+        {
+            DOCKER_MIME_TYPES_DISTRIBUTION_MANIFEST
+        } else {
+            MIME_TYPES_DISTRIBUTION_MANIFEST
+        }
+    }
+
+    /// Sends the request once the limits on the requests to its host allow it, see
+    /// [`ClientConfig::max_concurrent_requests_per_registry`]
+    async fn send(&self, request: RequestBuilder) -> Result<Response> {
+        self.send_throttled(request, None, None).await
+    }
+
+    /// Like [`send`](Self::send), with the `body` of the request sent no faster than the
+    /// throttle allows
+    #[cfg_attr(target_arch = "wasm32", allow(unused_variables))]
+    async fn send_throttled(
+        &self,
+        request: RequestBuilder,
+        body: Option<bytes::Bytes>,
+        throttle: Option<Throttle>,
+    ) -> Result<Response> {
+        let (client, request) = request.build_split();
+        #[cfg_attr(target_arch = "wasm32", allow(unused_mut))]
+        let mut request = request?;
+        if request
+            .headers()
+            .contains_key(reqwest::header::AUTHORIZATION)
+        {
+            self.check_credentials_allowed(request.url())?;
+        }
+        if let Some(body) = body {
+            *request.body_mut() = Some(match throttle {
+                #[cfg(not(target_arch = "wasm32"))]
+                Some(throttle) if !body.is_empty() => {
+                    // The length of streamed bodies isn't known in advance
+                    request
+                        .headers_mut()
+                        .entry(reqwest::header::CONTENT_LENGTH)
+                        .or_insert_with(|| HeaderValue::from(body.len()));
+                    throttle.body(body)
+                }
+                _ => body.into(),
+            });
+        }
+        let host = request.url().authority().to_string();
+        let permit = self.limiter.acquire(&host).await;
+        #[cfg_attr(target_arch = "wasm32", allow(unused_mut))]
+        let mut response = client.execute(request).await?;
+        self.limiter.answered(&host, &response);
+        self.record_diagnostics(&host, &response);
+        // The request is in flight until its body has been streamed, see `body_stream`
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(permit) = permit {
+            crate::rate_limit::hold_permit(&mut response, permit);
+        }
+        #[cfg(target_arch = "wasm32")]
+        drop(permit);
+        Ok(response)
+    }
+
+    /// Refuses to send credentials to the URL when it uses plain HTTP and its host isn't
+    /// allowed by [`ClientConfig::allow_credentials_over_http`].
+    ///
+    /// The credentials sent to a registry are also dropped by the HTTP client when the
+    /// registry redirects to another host, as done for the URLs of the layers.
+    fn check_credentials_allowed(&self, url: &Url) -> Result<()> {
+        if url.scheme() != "http" {
+            return Ok(());
+        }
+        let host = match (url.host_str(), url.port()) {
+            (Some(host), Some(port)) => format!("{host}:{port}"),
+            (Some(host), None) => host.to_string(),
+            (None, _) => String::new(),
+        };
+        if self
+            .config
+            .allow_credentials_over_http
+            .iter()
+            .any(|pattern| registry_matches(pattern, &host))
+        {
+            return Ok(());
+        }
+        warn!(%host, "Refusing to send credentials over plain HTTP");
+        Err(OciDistributionError::InsecureCredentialsError(host))
+    }
+
+    /// Records the metadata of an answer of the registry, see [`Client::diagnostics`]
+    fn record_diagnostics(&self, registry: &str, response: &Response) {
+        let diagnostics = ResponseDiagnostics::from_headers(response.headers());
+        if diagnostics.is_empty() {
+            return;
+        }
+        for warning in &diagnostics.warnings {
+            warn!(%registry, %warning, "Warning from registry");
+        }
+        self.diagnostics
+            .lock()
+            .unwrap()
+            .entry(registry.to_string())
+            .or_default()
+            .update(&diagnostics);
+    }
+
+    /// The metadata of the answers of the registry so far: the request ID and the rate
+    /// limit of its latest answer sending them, and the distinct warnings it sent
+    pub fn diagnostics(&self, registry: &str) -> Option<ResponseDiagnostics> {
+        self.diagnostics.lock().unwrap().get(registry).cloned()
+    }
+
+    /// Validates the response of the registry, slowing down the requests to the
+    /// registry when it reports that too many requests were made
+    fn validate_registry_response(
+        &self,
+        status: reqwest::StatusCode,
+        headers: &HeaderMap,
+        body: &[u8],
+        url: &str,
+        context: ErrorContext,
+    ) -> Result<()> {
+        let result = validate_registry_response(status, headers, body, url, context);
+        if let Err(OciDistributionError::RegistryError { envelope, .. }) = &result {
+            let too_many_requests = envelope
+                .errors
+                .iter()
+                .any(|error| error.code == OciErrorCode::Toomanyrequests);
+            // 429 responses have already been taken into account when received
+            if too_many_requests && status != StatusCode::TOO_MANY_REQUESTS {
+                if let Ok(url) = Url::parse(url) {
+                    self.limiter.throttled(url.authority(), None);
+                }
+            }
+        }
+        result
+    }
+
+    /// The scheme to use to connect to the registry
+    fn scheme_for(&self, registry: &str) -> &str {
+        if let Some(scheme) = self.schemes.read().unwrap().get(registry) {
+            return scheme;
+        }
+        self.config.protocol.scheme_for(registry)
+    }
+
+    /// Chooses the scheme of a registry that may fall back to HTTP, when not already
+    /// done, by pinging it with HTTPS and then HTTP.
+    ///
+    /// Nothing is chosen when the registry cannot be reached at all.
+    async fn resolve_scheme(&self, registry: &str) {
+        if !self.config.protocol.falls_back_to_http(registry)
+            || self.schemes.read().unwrap().contains_key(registry)
+        {
+            return;
+        }
+        let scheme = match self
+            .send(self.client.get(format!("https://{registry}/v2/")))
+            .await
+        {
+            Ok(_) => "https",
+            Err(OciDistributionError::RequestError(error)) if error.is_connect() => {
+                debug!(?error, %registry, "Cannot connect with HTTPS, trying HTTP");
+                match self
+                    .send(self.client.get(format!("http://{registry}/v2/")))
+                    .await
+                {
+                    Ok(_) => "http",
+                    Err(error) => {
+                        debug!(?error, %registry, "Cannot connect with HTTP");
+                        return;
+                    }
+                }
+            }
+            Err(error) => {
+                debug!(?error, %registry, "Cannot ping registry");
+                return;
+            }
+        };
+        debug!(%registry, %scheme, "Chose scheme of registry");
+        self.schemes
+            .write()
+            .unwrap()
+            .insert(registry.to_string(), scheme);
+    }
+
     /// Convert a Reference to a v2 manifest URL.
     fn to_v2_manifest_url(&self, reference: &Reference) -> String {
         let registry = reference.resolve_registry();
         format!(
             "{scheme}://{registry}/v2/{repository}/manifests/{reference}{ns}",
-            scheme = self.config.protocol.scheme_for(registry),
+            scheme = self.scheme_for(registry),
             repository = reference.repository(),
             reference = if let Some(digest) = reference.digest() {
                 digest
@@ -1683,7 +3079,7 @@ impl Client {
         let registry = reference.resolve_registry();
         format!(
             "{scheme}://{registry}/v2/{repository}/blobs/{digest}{ns}",
-            scheme = self.config.protocol.scheme_for(registry),
+            scheme = self.scheme_for(registry),
             repository = reference.repository(),
             ns = reference
                 .namespace()
@@ -1701,7 +3097,7 @@ impl Client {
         let registry = reference.resolve_registry();
         format!(
             "{scheme}://{registry}/v2/{repository}/tags/list{ns}",
-            scheme = self.config.protocol.scheme_for(registry),
+            scheme = self.scheme_for(registry),
             repository = reference.repository(),
             ns = reference
                 .namespace()
@@ -1717,9 +3113,9 @@ impl Client {
         artifact_type: Option<&str>,
     ) -> Result<String> {
         let registry = reference.resolve_registry();
-        Ok(format!(
-            "{scheme}://{registry}/v2/{repository}/referrers/{reference}{at}",
-            scheme = self.config.protocol.scheme_for(registry),
+        let url = format!(
+            "{scheme}://{registry}/v2/{repository}/referrers/{reference}",
+            scheme = self.scheme_for(registry),
             repository = reference.repository(),
             reference = if let Some(digest) = reference.digest() {
                 digest
@@ -1728,21 +3124,127 @@ impl Client {
                     "Getting referrers for a tag is not supported".into(),
                 )));
             },
-            at = artifact_type
-                .map(|at| format!("?artifactType={at}"))
-                .unwrap_or_default(),
-        ))
+        );
+        match artifact_type {
+            // Media types such as `application/vnd.oci.image.manifest.v1+json` must be
+            // encoded
+            Some(at) => Ok(Url::parse_with_params(&url, &[("artifactType", at)])
+                .map_err(|e| OciDistributionError::UrlParseError(e.to_string()))?
+                .to_string()),
+            None => Ok(url),
+        }
     }
 }
 
+/// The status of the answer to a probing request, with the codes of the errors it
+/// reports, if it could be sent
+async fn probe_status(res: Result<Response>) -> Option<(StatusCode, Vec<OciErrorCode>)> {
+    let res = match res {
+        Ok(res) => res,
+        Err(error) => {
+            debug!(?error, "Cannot send probing request");
+            return None;
+        }
+    };
+    let status = res.status();
+    let codes = res
+        .bytes()
+        .await
+        .ok()
+        .and_then(|body| serde_json::from_slice::<OciEnvelope>(&body).ok())
+        .map(|envelope| {
+            envelope
+                .errors
+                .into_iter()
+                .map(|error| error.code)
+                .collect()
+        })
+        .unwrap_or_default();
+    Some((status, codes))
+}
+
+/// The scopes to request for an operation on `reference`, plus pull access to each
+/// of the `sources` repositories of the same registry (used for cross-repo mounts).
+fn registry_scopes(
+    reference: &Reference,
+    op: RegistryOperation,
+    sources: &[String],
+) -> Vec<String> {
+    // Allow for either push or pull authentication
+    let target = match op {
+        RegistryOperation::Pull => format!("repository:{}:pull", reference.repository()),
+        RegistryOperation::Push => format!("repository:{}:pull,push", reference.repository()),
+        RegistryOperation::Delete => format!("repository:{}:delete", reference.repository()),
+    };
+    std::iter::once(target)
+        .chain(
+            sources
+                .iter()
+                .filter(|source| source.as_str() != reference.repository())
+                .map(|source| format!("repository:{source}:pull")),
+        )
+        .collect()
+}
+
+/// The reference of the index of the referrers tag schema for the given digest
+fn referrers_index_reference(image: &Reference, digest: &str) -> Reference {
+    Reference::with_tag(
+        image.registry().to_string(),
+        image.repository().to_string(),
+        referrers_tag(digest),
+    )
+}
+
+/// The manifest with the Docker media types equivalent to its OCI ones
+fn with_docker_media_types(manifest: &OciManifest) -> OciManifest {
+    let mut manifest = manifest.clone();
+    match &mut manifest {
+        OciManifest::Image(image) => {
+            image.media_type = Some(
+                docker_media_type(image.media_type.as_deref().unwrap_or(OCI_IMAGE_MEDIA_TYPE))
+                    .to_string(),
+            );
+            image.config.media_type = docker_media_type(&image.config.media_type).to_string();
+            for layer in &mut image.layers {
+                layer.media_type = docker_media_type(&layer.media_type).to_string();
+            }
+        }
+        OciManifest::ImageIndex(index) => {
+            index.media_type = Some(
+                docker_media_type(
+                    index
+                        .media_type
+                        .as_deref()
+                        .unwrap_or(IMAGE_MANIFEST_LIST_MEDIA_TYPE),
+                )
+                .to_string(),
+            );
+            for entry in &mut index.manifests {
+                entry.media_type = docker_media_type(&entry.media_type).to_string();
+            }
+        }
+    }
+    manifest
+}
+
 /// The OCI spec technically does not allow any codes but 200, 500, 401, and 404.
 /// Obviously, HTTP servers are going to send other codes. This tries to catch the
 /// obvious ones (200, 4XX, 5XX). Anything else is just treated as an error.
-fn validate_registry_response(status: reqwest::StatusCode, body: &[u8], url: &str) -> Result<()> {
+fn validate_registry_response(
+    status: reqwest::StatusCode,
+    headers: &HeaderMap,
+    body: &[u8],
+    url: &str,
+    context: ErrorContext,
+) -> Result<()> {
+    let diagnostics = || Box::new(ResponseDiagnostics::from_headers(headers));
+    let context = Box::new(context);
     match status {
         reqwest::StatusCode::OK => Ok(()),
         reqwest::StatusCode::UNAUTHORIZED => Err(OciDistributionError::UnauthorizedError {
             url: url.to_string(),
+            diagnostics: diagnostics(),
+            context,
         }),
         s if s.is_success() => Err(OciDistributionError::SpecViolationError(format!(
             "Expected HTTP Status {}, got {} instead",
@@ -1755,12 +3257,17 @@ fn validate_registry_response(status: reqwest::StatusCode, body: &[u8], url: &st
                 Ok(envelope) => Err(OciDistributionError::RegistryError {
                     envelope,
                     url: url.to_string(),
+                    status: s.as_u16(),
+                    diagnostics: diagnostics(),
+                    context,
                 }),
                 // Fall back to a plain server error if the body isn't a valid `OciEnvelope`
                 Err(_) => Err(OciDistributionError::ServerError {
                     code: s.as_u16(),
                     url: url.to_string(),
                     message: String::from_utf8_lossy(body).to_string(),
+                    diagnostics: diagnostics(),
+                    context,
                 }),
             }
         }
@@ -1771,23 +3278,47 @@ fn validate_registry_response(status: reqwest::StatusCode, body: &[u8], url: &st
                 code: s.as_u16(),
                 url: url.to_string(),
                 message: text.to_string(),
+                diagnostics: diagnostics(),
+                context,
             })
         }
     }
 }
 
-/// Converts a response into a stream
+/// Extracts the `last` parameter of the next page from the `Link` header of a paginated
+/// response, such as `</v2/hello/tags/list?n=10&last=v10>; rel="next"`
+fn next_page_last(link: &str) -> Option<String> {
+    link.split(',').find_map(|link| {
+        let (target, params) = link.split_once(';')?;
+        if !params
+            .split(';')
+            .any(|param| matches!(param.trim(), "rel=\"next\"" | "rel=next"))
+        {
+            return None;
+        }
+        let target = target.trim().strip_prefix('<')?.strip_suffix('>')?;
+        // The target is usually relative to the registry
+        let url = Url::parse("http://registry").ok()?.join(target).ok()?;
+        url.query_pairs()
+            .find(|(key, _)| key == "last")
+            .map(|(_, last)| last.into_owned())
+    })
+}
+
+/// Converts a response into a stream, throttled before its content is verified
 fn stream_from_response(
     response: Response,
     layer: impl AsLayerDescriptor,
     verify: bool,
+    throttle: Option<Throttle>,
 ) -> Result<SizedStream> {
     let content_length = response.content_length();
     let headers = response.headers().clone();
-    let stream = response
-        .error_for_status()?
-        .bytes_stream()
-        .map_err(std::io::Error::other);
+    let stream = body_stream(response.error_for_status()?).map_err(std::io::Error::other);
+    let stream: BoxStream<'static, std::io::Result<bytes::Bytes>> = match throttle {
+        Some(throttle) => Box::pin(throttle.stream(stream)),
+        None => Box::pin(stream),
+    };
 
     let expected_layer_digest = layer.as_layer_descriptor().digest.to_string();
     let layer_digester = Digester::new(&expected_layer_digest)?;
@@ -1802,13 +3333,13 @@ fn stream_from_response(
         .map(|(_, digest)| digest.to_owned());
     let stream: BoxStream<'static, std::result::Result<bytes::Bytes, std::io::Error>> = if verify {
         Box::pin(VerifyingStream::new(
-            Box::pin(stream),
+            stream,
             layer_digester,
             expected_layer_digest,
             header_digester_and_digest,
         ))
     } else {
-        Box::pin(stream)
+        stream
     };
     Ok(SizedStream {
         content_length,
@@ -1823,6 +3354,8 @@ fn stream_from_response(
 struct RequestBuilderWrapper<'a> {
     client: &'a Client,
     request_builder: RequestBuilder,
+    /// The body of the request, kept apart to be throttled when sent
+    body: Option<bytes::Bytes>,
 }
 
 // RequestBuilderWrapper type management
@@ -1832,15 +3365,22 @@ impl<'a> RequestBuilderWrapper<'a> {
     /// function `f`.
     fn from_client(
         client: &'a Client,
-        f: impl Fn(&reqwest::Client) -> RequestBuilder,
+        f: impl FnOnce(&HttpClients) -> RequestBuilder,
     ) -> RequestBuilderWrapper<'a> {
         let request_builder = f(&client.client);
         RequestBuilderWrapper {
             client,
             request_builder,
+            body: None,
         }
     }
 
+    /// Sets the body of the request
+    fn with_body(mut self, body: bytes::Bytes) -> RequestBuilderWrapper<'a> {
+        self.body = Some(body);
+        self
+    }
+
     // Produces a final `RequestBuilder` out of this `RequestBuilderWrapper`
     fn into_request_builder(self) -> RequestBuilder {
         self.request_builder
@@ -1863,6 +3403,7 @@ impl<'a> RequestBuilderWrapper<'a> {
         Ok(RequestBuilderWrapper {
             client: self.client,
             request_builder,
+            body: self.body.clone(),
         })
     }
 
@@ -1871,15 +3412,26 @@ impl<'a> RequestBuilderWrapper<'a> {
     /// If the struct has Some(bearer), this will insert the bearer token in an
     /// Authorization header. It will also set the Accept header, which must
     /// be set on all OCI Registry requests. If the struct has HTTP Basic Auth
-    /// credentials, these will be configured.
+    /// credentials, these will be configured. The token also grants pull access to
+    /// the `sources` repositories of the registry.
     async fn apply_auth(
         &self,
         image: &Reference,
         op: RegistryOperation,
+        sources: &[String],
     ) -> Result<RequestBuilderWrapper<'_>> {
+        let token = self
+            .client
+            .get_auth_token(image, op, sources, false)
+            .await?;
+        self.apply_token(token)
+    }
+
+    /// Updates request to authenticate with the given token, if any.
+    fn apply_token(&self, token: Option<RegistryTokenType>) -> Result<RequestBuilderWrapper<'_>> {
         let mut headers = HeaderMap::new();
 
-        if let Some(token) = self.client.get_auth_token(image, op).await {
+        if let Some(token) = &token {
             match token {
                 RegistryTokenType::Bearer(token) => {
                     debug!("Using bearer token authentication.");
@@ -1899,22 +3451,144 @@ impl<'a> RequestBuilderWrapper<'a> {
                             })?
                             .headers(headers)
                             .basic_auth(username.to_string(), Some(password.to_string())),
+                        body: self.body.clone(),
                     });
                 }
             }
         }
-        Ok(RequestBuilderWrapper {
-            client: self.client,
-            request_builder: self
-                .request_builder
-                .try_clone()
-                .ok_or_else(|| {
-                    OciDistributionError::GenericError(Some(
-                        "could not clone request builder".to_string(),
-                    ))
-                })?
-                .headers(headers),
-        })
+        Ok(RequestBuilderWrapper {
+            client: self.client,
+            request_builder: self
+                .request_builder
+                .try_clone()
+                .ok_or_else(|| {
+                    OciDistributionError::GenericError(Some(
+                        "could not clone request builder".to_string(),
+                    ))
+                })?
+                .headers(headers),
+            body: self.body.clone(),
+        })
+    }
+
+    /// Authenticates and sends the request.
+    ///
+    /// When the registry rejects the request with a Bearer challenge, a token is
+    /// obtained for the scope requested by the challenge and the request is sent once
+    /// more. When a [`CredentialProvider`] is configured and the challenge isn't about
+    /// an insufficient scope, the tokens of the repository are dropped and new
+    /// credentials are requested from the provider beforehand.
+    async fn send_with_auth(&self, image: &Reference, op: RegistryOperation) -> Result<Response> {
+        self.send_with_auth_for_sources(image, op, &[]).await
+    }
+
+    /// Like [`send_with_auth`](Self::send_with_auth), with a token that also grants
+    /// pull access to the `sources` repositories of the registry.
+    async fn send_with_auth_for_sources(
+        &self,
+        image: &Reference,
+        op: RegistryOperation,
+        sources: &[String],
+    ) -> Result<Response> {
+        let registry = image.resolve_registry();
+        if self.client.config.protocol.falls_back_to_http(registry) {
+            self.client.resolve_scheme(registry).await;
+            if let Some(request) = self.with_scheme_of(registry)? {
+                return request.send_authenticated(image, op, sources).await;
+            }
+        }
+        self.send_authenticated(image, op, sources).await
+    }
+
+    /// Switches the request to the scheme chosen for the registry, when it targets the
+    /// registry and was created before the scheme was chosen.
+    fn with_scheme_of(&self, registry: &str) -> Result<Option<RequestBuilderWrapper<'a>>> {
+        let scheme = self.client.scheme_for(registry);
+        let request_builder = self.request_builder.try_clone().ok_or_else(|| {
+            OciDistributionError::GenericError(Some("could not clone request builder".to_string()))
+        })?;
+        let (client, request) = request_builder.build_split();
+        let mut request = request?;
+        if request.url().scheme() == scheme || request.url().authority() != registry {
+            return Ok(None);
+        }
+        if request.url_mut().set_scheme(scheme).is_err() {
+            return Ok(None);
+        }
+        Ok(Some(RequestBuilderWrapper {
+            client: self.client,
+            request_builder: RequestBuilder::from_parts(client, request),
+            body: self.body.clone(),
+        }))
+    }
+
+    /// Authenticates and sends the request, see [`send_with_auth`](Self::send_with_auth).
+    async fn send_authenticated(
+        &self,
+        image: &Reference,
+        op: RegistryOperation,
+        sources: &[String],
+    ) -> Result<Response> {
+        // Only the uploads have a body to throttle
+        let throttle = self
+            .client
+            .bandwidths
+            .for_registry(image.resolve_registry());
+        let res = self
+            .client
+            .send_throttled(
+                self.apply_auth(image, op, sources)
+                    .await?
+                    .into_request_builder(),
+                self.body.clone(),
+                throttle.clone(),
+            )
+            .await?;
+        if res.status() != StatusCode::UNAUTHORIZED {
+            return Ok(res);
+        }
+
+        let challenge = res
+            .headers()
+            .get(reqwest::header::WWW_AUTHENTICATE)
+            .and_then(|h| BearerChallenge::try_from(h).ok());
+        let has_provider = self.client.credential_provider.is_some();
+        if challenge.is_none() && !has_provider {
+            return Ok(res);
+        }
+
+        debug!(url = %res.url(), ?challenge, "Request rejected, re-authenticating");
+        // The credentials are still valid when the token only lacks the required scope
+        let insufficient_scope = challenge
+            .as_ref()
+            .and_then(|c| c.error.as_deref())
+            .is_some_and(|e| e == "insufficient_scope");
+        let rejected = has_provider && !insufficient_scope;
+        if rejected {
+            self.client.credentials_rejected(image).await;
+        }
+        let retry = match challenge {
+            Some(challenge) => match self
+                .client
+                .reauthenticate(image, op, sources, &challenge, rejected)
+                .await
+            {
+                Ok(Some(token)) => self.apply_token(Some(token))?,
+                Ok(None) => return Ok(res),
+                Err(error) => {
+                    debug!(?error, "Cannot re-authenticate");
+                    return Ok(res);
+                }
+            },
+            None => self.apply_token(
+                self.client
+                    .get_auth_token(image, op, sources, rejected)
+                    .await?,
+            )?,
+        };
+        self.client
+            .send_throttled(retry.into_request_builder(), self.body.clone(), throttle)
+            .await
     }
 }
 
@@ -1937,11 +3611,270 @@ pub struct Certificate {
     pub data: Vec<u8>,
 }
 
+/// A client identity, presented to the registries requiring mutual TLS authentication.
+///
+/// The private key and the password are wiped from memory when dropped.
+#[derive(Clone)]
+pub enum ClientIdentity {
+    /// A certificate chain and its private key.
+    ///
+    /// Both must be PEM encoded. The key must be a PKCS#8 key when the `native-tls`
+    /// feature is used, RSA and SEC1 keys are also accepted with `rustls-tls`.
+    Pem {
+        /// The certificate chain, starting with the client certificate
+        certificate: Certificate,
+        /// The private key of the client certificate
+        key: Zeroizing<Vec<u8>>,
+    },
+    /// A DER-encoded PKCS#12 archive holding the certificate chain and its private key.
+    ///
+    /// Only supported with the `native-tls` feature.
+    Pkcs12 {
+        /// The archive
+        archive: Zeroizing<Vec<u8>>,
+        /// The password protecting the archive
+        password: Zeroizing<String>,
+    },
+}
+
+impl fmt::Debug for ClientIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientIdentity::Pem { certificate, .. } => f
+                .debug_struct("Pem")
+                .field("certificate", certificate)
+                .field("key", &"<redacted>")
+                .finish(),
+            ClientIdentity::Pkcs12 { .. } => f
+                .debug_struct("Pkcs12")
+                .field("archive", &"<redacted>")
+                .field("password", &"<redacted>")
+                .finish(),
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl ClientIdentity {
+    fn to_identity(&self) -> Result<reqwest::Identity> {
+        match self {
+            ClientIdentity::Pem { certificate, key } => match certificate.encoding {
+                CertificateEncoding::Pem => pem_identity(&certificate.data, key),
+                CertificateEncoding::Der => Err(OciDistributionError::ClientIdentityError(
+                    "the certificate must be PEM encoded".to_string(),
+                )),
+            },
+            ClientIdentity::Pkcs12 { archive, password } => pkcs12_identity(archive, password),
+        }
+    }
+}
+
+#[cfg(all(feature = "native-tls", not(target_arch = "wasm32")))]
+fn pem_identity(certificate: &[u8], key: &[u8]) -> Result<reqwest::Identity> {
+    Ok(reqwest::Identity::from_pkcs8_pem(certificate, key)?)
+}
+
+#[cfg(all(
+    not(feature = "native-tls"),
+    any(feature = "rustls-tls", feature = "rustls-tls-native-roots"),
+    not(target_arch = "wasm32")
+))]
+fn pem_identity(certificate: &[u8], key: &[u8]) -> Result<reqwest::Identity> {
+    // rustls expects the certificates and the key in a single buffer
+    let mut pem = Zeroizing::new(certificate.to_vec());
+    pem.push(b'\n');
+    pem.extend_from_slice(key);
+    Ok(reqwest::Identity::from_pem(&pem)?)
+}
+
+#[cfg(all(feature = "native-tls", not(target_arch = "wasm32")))]
+fn pkcs12_identity(archive: &[u8], password: &str) -> Result<reqwest::Identity> {
+    Ok(reqwest::Identity::from_pkcs12_der(archive, password)?)
+}
+
+#[cfg(all(not(feature = "native-tls"), not(target_arch = "wasm32")))]
+fn pkcs12_identity(_archive: &[u8], _password: &str) -> Result<reqwest::Identity> {
+    Err(OciDistributionError::ClientIdentityError(
+        "PKCS#12 identities require the `native-tls` feature".to_string(),
+    ))
+}
+
+/// A DNS resolver used by the [`Client`] instead of the resolver of the system.
+///
+/// Only the host names are resolved, the connections are still made, and the TLS
+/// certificates still validated, against the host of each request.
+///
+/// ```rust
+/// use std::net::SocketAddr;
+///
+/// use futures_util::future::BoxFuture;
+/// use oci_client::client::DnsResolver;
+/// use oci_client::errors::Result;
+///
+/// struct Loopback;
+///
+/// impl DnsResolver for Loopback {
+///     fn resolve<'a>(&'a self, _host: &'a str) -> BoxFuture<'a, Result<Vec<SocketAddr>>> {
+///         Box::pin(async move { Ok(vec![SocketAddr::from(([127, 0, 0, 1], 0))]) })
+///     }
+/// }
+/// ```
+pub trait DnsResolver: Send + Sync {
+    /// Returns the addresses of the given host.
+    ///
+    /// The port of the request is used instead of the port of the addresses, unless it
+    /// has none, in which case a port `0` is replaced by the default port of the scheme.
+    fn resolve<'a>(&'a self, host: &'a str) -> BoxFuture<'a, Result<Vec<SocketAddr>>>;
+}
+
+/// Exposes a [`DnsResolver`] to reqwest
+#[cfg(not(target_arch = "wasm32"))]
+struct ResolverAdapter(Arc<dyn DnsResolver>);
+
+#[cfg(not(target_arch = "wasm32"))]
+impl reqwest::dns::Resolve for ResolverAdapter {
+    fn resolve(&self, name: reqwest::dns::Name) -> reqwest::dns::Resolving {
+        let resolver = self.0.clone();
+        Box::pin(async move {
+            let addrs = resolver.resolve(name.as_str()).await?;
+            Ok(Box::new(addrs.into_iter()) as reqwest::dns::Addrs)
+        })
+    }
+}
+
+/// TLS settings specific to a registry
+///
+/// They are combined with the global TLS settings of the [`ClientConfig`], and only
+/// apply to the connections to the registry.
+#[derive(Debug, Clone, Default)]
+pub struct RegistryTlsConfig {
+    /// Accept invalid hostname, instead of [`ClientConfig::accept_invalid_hostnames`]
+    #[cfg(feature = "native-tls")]
+    pub accept_invalid_hostnames: Option<bool>,
+
+    /// Accept invalid certificates, instead of
+    /// [`ClientConfig::accept_invalid_certificates`]
+    pub accept_invalid_certificates: Option<bool>,
+
+    /// Root certificates to trust, in addition to
+    /// [`ClientConfig::extra_root_certificates`]
+    pub extra_root_certificates: Vec<Certificate>,
+
+    /// The identity presented to the registry, instead of
+    /// [`ClientConfig::client_identity`]
+    pub client_identity: Option<ClientIdentity>,
+}
+
+impl RegistryTlsConfig {
+    /// Loads the TLS settings of a registry from a directory laid out like the
+    /// `certs.d/<host[:port]>` directories of Docker and containerd:
+    ///
+    /// * `*.crt` files hold PEM-encoded root certificates, such as `ca.crt`
+    /// * `*.cert` files hold a PEM-encoded client certificate, such as `client.cert`,
+    ///   whose private key is in the `*.key` file with the same name
+    ///
+    /// When there are several client certificates, the first one by name is used.
+    pub fn from_dir(dir: impl AsRef<Path>) -> Result<Self> {
+        let dir = dir.as_ref();
+        let mut paths = std::fs::read_dir(dir)?
+            .map(|entry| Ok(entry?.path()))
+            .collect::<std::io::Result<Vec<_>>>()?;
+        paths.sort();
+
+        let mut tls = RegistryTlsConfig::default();
+        for path in paths {
+            match path.extension().and_then(|e| e.to_str()) {
+                Some("crt") => {
+                    let bundle = std::fs::read(&path)?;
+                    tls.extra_root_certificates
+                        .extend(split_pem_certificates(&bundle).map(|data| Certificate {
+                            encoding: CertificateEncoding::Pem,
+                            data,
+                        }));
+                }
+                Some("cert") => {
+                    let key_path = path.with_extension("key");
+                    if !key_path.exists() {
+                        return Err(OciDistributionError::ClientIdentityError(format!(
+                            "missing private key {key_path:?} of the client certificate"
+                        )));
+                    }
+                    if tls.client_identity.is_some() {
+                        debug!(?path, "Ignoring additional client certificate");
+                        continue;
+                    }
+                    tls.client_identity = Some(ClientIdentity::Pem {
+                        certificate: Certificate {
+                            encoding: CertificateEncoding::Pem,
+                            data: std::fs::read(&path)?,
+                        },
+                        key: Zeroizing::new(std::fs::read(&key_path)?),
+                    });
+                }
+                Some("key") if !path.with_extension("cert").exists() => {
+                    return Err(OciDistributionError::ClientIdentityError(format!(
+                        "missing client certificate of the private key {path:?}"
+                    )));
+                }
+                _ => {}
+            }
+        }
+        Ok(tls)
+    }
+
+    /// Loads the TLS settings of all the registries of a `certs.d` directory, such as
+    /// `/etc/docker/certs.d`, keyed by the names of their directories, `host` or
+    /// `host:port`. See [`from_dir`](Self::from_dir) for the layout of each directory.
+    ///
+    /// The result is meant to be added to [`ClientConfig::registry_tls`]. A missing
+    /// directory has no registries.
+    pub fn load_certs_d(root: impl AsRef<Path>) -> Result<HashMap<String, Self>> {
+        let entries = match std::fs::read_dir(root) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(HashMap::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut registries = HashMap::new();
+        for entry in entries {
+            let entry = entry?;
+            if !entry.file_type()?.is_dir() {
+                continue;
+            }
+            let registry = entry.file_name().to_string_lossy().into_owned();
+            registries.insert(registry, Self::from_dir(entry.path())?);
+        }
+        Ok(registries)
+    }
+}
+
+/// Splits a PEM bundle into its certificates
+pub(crate) fn split_pem_certificates(bundle: &[u8]) -> impl Iterator<Item = Vec<u8>> + '_ {
+    const BEGIN: &str = "-----BEGIN CERTIFICATE-----";
+    const END: &str = "-----END CERTIFICATE-----";
+    let mut rest = std::str::from_utf8(bundle).unwrap_or_default();
+    std::iter::from_fn(move || {
+        let start = rest.find(BEGIN)?;
+        let end = start + rest[start..].find(END)? + END.len();
+        let certificate = rest.as_bytes()[start..end].to_vec();
+        rest = &rest[end..];
+        Some(certificate)
+    })
+}
+
 /// A client configuration
 pub struct ClientConfig {
     /// Which protocol the client should use
     pub protocol: ClientProtocol,
 
+    /// The hosts to which credentials may be sent over plain HTTP, given like the
+    /// registries of [`ClientProtocol`]. The requests carrying credentials or tokens,
+    /// including the ones to the authorization servers, are otherwise refused with
+    /// [`OciDistributionError::InsecureCredentialsError`] when they would be sent over
+    /// HTTP, e.g. with [`ClientProtocol::Http`].
+    ///
+    /// This defaults to an empty list.
+    pub allow_credentials_over_http: Vec<String>,
+
     /// Accept invalid hostname. Defaults to false
     #[cfg(feature = "native-tls")]
     pub accept_invalid_hostnames: bool,
@@ -1952,10 +3885,37 @@ pub struct ClientConfig {
     /// Use monolithic push for pushing blobs. Defaults to false
     pub use_monolithic_push: bool,
 
+    /// The quirks of some registries, keyed by registry. A registry may be given with a
+    /// port, otherwise the quirks apply to all its ports. The quirks of the other
+    /// registries are detected from their host names, see [`RegistryQuirks::detect`].
+    ///
+    /// This defaults to an empty map.
+    pub registry_quirks: HashMap<String, RegistryQuirks>,
+
+    /// Exchange [`RegistryAuth::Basic`] credentials for a bearer token using the OAuth2
+    /// password grant (a POST request) instead of the GET token flow. The GET flow is
+    /// still used when the authorization server doesn't support OAuth2. Identity tokens
+    /// always use the OAuth2 flow.
+    ///
+    /// Defaults to false
+    pub oauth2_password_grant: bool,
+
     /// A list of extra root certificate to trust. This can be used to connect
     /// to servers using self-signed certificates
     pub extra_root_certificates: Vec<Certificate>,
 
+    /// The identity presented to the registries requiring mutual TLS authentication.
+    ///
+    /// This defaults to `None`.
+    pub client_identity: Option<ClientIdentity>,
+
+    /// TLS settings of specific registries, keyed by `host` or `host:port`. A registry
+    /// matching an entry gets its own HTTP connections. They can be loaded from a
+    /// `certs.d` directory with [`RegistryTlsConfig::load_certs_d`].
+    ///
+    /// This defaults to an empty map.
+    pub registry_tls: HashMap<String, RegistryTlsConfig>,
+
     /// A function that defines the client's behaviour if an Image Index Manifest
     /// (i.e Manifest List) is encountered when pulling an image.
     /// Defaults to [current_platform_resolver](self::current_platform_resolver),
@@ -1977,12 +3937,84 @@ pub struct ClientConfig {
     /// This defaults to [`DEFAULT_MAX_CONCURRENT_DOWNLOAD`].
     pub max_concurrent_download: usize,
 
+    /// Maximum number of requests in flight to each registry, across all the
+    /// operations of the client and of its clones. A request is in flight until the
+    /// headers of its response are received, the bodies being streamed afterwards are
+    /// bounded by [`max_concurrent_download`](Self::max_concurrent_download).
+    ///
+    /// This defaults to `None`, meaning the number of requests is not bounded.
+    pub max_concurrent_requests_per_registry: Option<usize>,
+
+    /// Maximum number of requests per second sent to each registry, across all the
+    /// operations of the client and of its clones.
+    ///
+    /// This defaults to `None`, meaning the rate of requests is not bounded.
+    pub max_requests_per_second_per_registry: Option<f64>,
+
+    /// Slow down the requests to a registry each time it answers with
+    /// `429 Too Many Requests` or a `TOOMANYREQUESTS` error, waiting for the time
+    /// given by its `Retry-After` header, up to a minute, and then halving the rate
+    /// of requests. The rate is restored as the registry answers the next requests.
+    ///
+    /// This defaults to `true`. It has no effect on WebAssembly, as are the limits on
+    /// the rate of requests.
+    pub adaptive_rate_limit: bool,
+
+    /// Maximum number of bytes per second transferred by the blobs pulled and pushed
+    /// by the client and by its clones, all registries combined. The content of the
+    /// blobs is verified as usual. The bandwidth isn't capped on WebAssembly.
+    ///
+    /// This defaults to `None`, meaning the bandwidth is not capped.
+    pub max_bytes_per_second: Option<u64>,
+
+    /// Maximum number of bytes per second transferred by the blobs pulled from and
+    /// pushed to some registries, keyed by registry. A registry may be given with a
+    /// port, otherwise the limit applies to all its ports. These limits apply in
+    /// addition to [`max_bytes_per_second`](Self::max_bytes_per_second).
+    ///
+    /// This defaults to an empty map.
+    pub max_bytes_per_second_by_registry: HashMap<String, u64>,
+
     /// Default token expiration in seconds, to use when the token claim
     /// doesn't provide a value.
     ///
     /// This defaults to [`DEFAULT_TOKEN_EXPIRATION_SECS`].
     pub default_token_expiration_secs: usize,
 
+    /// A persistent backend for the bearer tokens obtained by the client, such as a
+    /// [`FileTokenStore`](crate::FileTokenStore).
+    ///
+    /// This defaults to `None`, tokens are then only kept in memory.
+    pub token_store: Option<Arc<dyn TokenStore>>,
+
+    /// A provider of credentials, asked for the credentials of a repository each time
+    /// a new token is needed for it and no [`RegistryAuth`] other than
+    /// [`RegistryAuth::Anonymous`] has been passed to the client methods, and again
+    /// when the credentials of the repository are rejected.
+    ///
+    /// This defaults to `None`.
+    pub credential_provider: Option<Arc<dyn CredentialProvider>>,
+
+    /// Number of seconds before their expiration at which tokens are renewed, so that
+    /// they don't expire in the middle of a request. The margin is capped to half of the
+    /// lifetime of each token.
+    ///
+    /// This defaults to [`DEFAULT_TOKEN_REFRESH_MARGIN_SECS`].
+    pub token_refresh_margin_secs: usize,
+
+    /// Maximum number of tokens kept in memory. Once reached, expired tokens and then
+    /// the least recently used ones are evicted.
+    ///
+    /// This defaults to `None`, meaning the number of tokens is not bounded.
+    pub max_cached_tokens: Option<usize>,
+
+    /// Interval at which expired tokens are removed from memory by a background task.
+    /// The task is only started when the client is created within a tokio runtime, and
+    /// stops once the client is dropped.
+    ///
+    /// This defaults to `None`, expired tokens are then only replaced or evicted.
+    pub token_prune_interval: Option<Duration>,
+
     /// Enables a read timeout for the client.
     ///
     /// See [`reqwest::ClientBuilder::read_timeout`] for more information.
@@ -1993,6 +4025,19 @@ pub struct ClientConfig {
     /// See [`reqwest::ClientBuilder::connect_timeout`] for more information.
     pub connect_timeout: Option<Duration>,
 
+    /// Addresses to connect to instead of resolving some host names, keyed by host name.
+    /// The connections are still made, and the TLS certificates still validated, against
+    /// the host of each request. See [`DnsResolver::resolve`] for the handling of ports.
+    ///
+    /// This defaults to an empty map.
+    pub dns_overrides: HashMap<String, Vec<SocketAddr>>,
+
+    /// A resolver used to resolve the host names not found in
+    /// [`dns_overrides`](Self::dns_overrides).
+    ///
+    /// This defaults to `None`, meaning the resolver of the system is used.
+    pub dns_resolver: Option<Arc<dyn DnsResolver>>,
+
     /// Set the `User-Agent` used by the client.
     ///
     /// This defaults to [`DEFAULT_USER_AGENT`].
@@ -2018,17 +4063,34 @@ impl Default for ClientConfig {
     fn default() -> Self {
         Self {
             protocol: ClientProtocol::default(),
+            allow_credentials_over_http: Vec::new(),
             #[cfg(feature = "native-tls")]
             accept_invalid_hostnames: false,
             accept_invalid_certificates: false,
             use_monolithic_push: false,
+            registry_quirks: HashMap::new(),
+            oauth2_password_grant: false,
             extra_root_certificates: Vec::new(),
+            client_identity: None,
+            registry_tls: HashMap::new(),
             platform_resolver: Some(Box::new(current_platform_resolver)),
             max_concurrent_upload: DEFAULT_MAX_CONCURRENT_UPLOAD,
             max_concurrent_download: DEFAULT_MAX_CONCURRENT_DOWNLOAD,
+            max_concurrent_requests_per_registry: None,
+            max_requests_per_second_per_registry: None,
+            adaptive_rate_limit: true,
+            max_bytes_per_second: None,
+            max_bytes_per_second_by_registry: HashMap::new(),
             default_token_expiration_secs: DEFAULT_TOKEN_EXPIRATION_SECS,
+            token_store: None,
+            credential_provider: None,
+            token_refresh_margin_secs: DEFAULT_TOKEN_REFRESH_MARGIN_SECS,
+            max_cached_tokens: None,
+            token_prune_interval: None,
             read_timeout: None,
             connect_timeout: None,
+            dns_overrides: HashMap::new(),
+            dns_resolver: None,
             user_agent: DEFAULT_USER_AGENT,
             https_proxy: None,
             http_proxy: None,
@@ -2116,6 +4178,15 @@ pub fn current_platform_resolver(manifests: &[ImageIndexEntry]) -> Option<String
 }
 
 /// The protocol that the client should use to connect
+///
+/// The registries listed by [`HttpsExcept`](Self::HttpsExcept) and
+/// [`HttpsWithHttpFallback`](Self::HttpsWithHttpFallback) are patterns, which can be:
+///
+/// * a host, matching the registry on any port: `localhost`
+/// * a host and a port, matching the registry on this port only: `localhost:5000`
+/// * a host with `*` wildcards, optionally followed by a port: `*.local`
+/// * an IP range in the CIDR notation, matching the registries on any port whose host
+///   is an IP address of the range: `10.0.0.0/8`
 #[derive(Debug, Clone, PartialEq, Eq, Default)]
 pub enum ClientProtocol {
     #[allow(missing_docs)]
@@ -2123,8 +4194,12 @@ pub enum ClientProtocol {
     #[allow(missing_docs)]
     #[default]
     Https,
-    #[allow(missing_docs)]
+    /// Use HTTP for the matching registries, HTTPS for the others
     HttpsExcept(Vec<String>),
+    /// Use HTTPS, falling back to HTTP for the matching registries and the loopback
+    /// addresses when they cannot be reached with HTTPS, like Docker does for insecure
+    /// registries. The protocol is chosen when first connecting to the registry.
+    HttpsWithHttpFallback(Vec<String>),
 }
 
 impl ClientProtocol {
@@ -2133,20 +4208,116 @@ impl ClientProtocol {
             ClientProtocol::Https => "https",
             ClientProtocol::Http => "http",
             ClientProtocol::HttpsExcept(exceptions) => {
-                if exceptions.contains(&registry.to_owned()) {
+                if exceptions.iter().any(|e| registry_matches(e, registry)) {
                     "http"
                 } else {
                     "https"
                 }
             }
+            ClientProtocol::HttpsWithHttpFallback(_) => "https",
+        }
+    }
+
+    /// Whether the registry may be reached with HTTP when HTTPS fails
+    fn falls_back_to_http(&self, registry: &str) -> bool {
+        match self {
+            ClientProtocol::HttpsWithHttpFallback(registries) => {
+                registries.iter().any(|r| registry_matches(r, registry))
+                    || split_host_port(registry).0 == "localhost"
+                    || split_host_port(registry)
+                        .0
+                        .parse::<std::net::IpAddr>()
+                        .is_ok_and(|ip| ip.is_loopback())
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Whether the registry matches the pattern, see [`ClientProtocol`]
+fn registry_matches(pattern: &str, registry: &str) -> bool {
+    let (host, port) = split_host_port(registry);
+    if let Some((network, prefix_len)) = pattern.split_once('/') {
+        return match (network.parse(), prefix_len.parse(), host.parse()) {
+            (Ok(network), Ok(prefix_len), Ok(ip)) => ip_in_network(ip, network, prefix_len),
+            _ => false,
+        };
+    }
+    let (pattern_host, pattern_port) = split_host_port(pattern);
+    if pattern_port.is_some() && pattern_port != port {
+        return false;
+    }
+    wildcard_matches(
+        &pattern_host.to_ascii_lowercase(),
+        &host.to_ascii_lowercase(),
+    )
+}
+
+/// Splits `host[:port]` into its host, without the brackets of IPv6 addresses, and port
+fn split_host_port(registry: &str) -> (&str, Option<&str>) {
+    if let Some(rest) = registry.strip_prefix('[') {
+        return match rest.split_once(']') {
+            Some((host, port)) => (host, port.strip_prefix(':')),
+            None => (registry, None),
+        };
+    }
+    match registry.split_once(':') {
+        // A bare IPv6 address has several colons
+        Some((host, port)) if !port.contains(':') => (host, Some(port)),
+        _ => (registry, None),
+    }
+}
+
+/// Matches a value against a pattern where `*` stands for any sequence of characters
+fn wildcard_matches(pattern: &str, value: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == value,
+        Some((prefix, rest)) => {
+            let Some(value) = value.strip_prefix(prefix) else {
+                return false;
+            };
+            (0..=value.len())
+                .filter(|&i| value.is_char_boundary(i))
+                .any(|i| wildcard_matches(rest, &value[i..]))
+        }
+    }
+}
+
+fn ip_in_network(ip: std::net::IpAddr, network: std::net::IpAddr, prefix_len: u32) -> bool {
+    use std::net::IpAddr;
+    match (ip, network) {
+        (IpAddr::V4(ip), IpAddr::V4(network)) if prefix_len <= 32 => {
+            let mask = u32::MAX.checked_shl(32 - prefix_len).unwrap_or(0);
+            u32::from(ip) & mask == u32::from(network) & mask
         }
+        (IpAddr::V6(ip), IpAddr::V6(network)) if prefix_len <= 128 => {
+            let mask = u128::MAX.checked_shl(128 - prefix_len).unwrap_or(0);
+            u128::from(ip) & mask == u128::from(network) & mask
+        }
+        _ => false,
     }
 }
 
+/// How a registry asks its clients to authenticate
+#[derive(Clone, Debug)]
+enum AuthChallenge {
+    /// The registry doesn't require authentication
+    None,
+    /// HTTP Basic authentication, or an unsupported scheme
+    Basic,
+    /// Token authentication
+    Bearer(BearerChallenge),
+}
+
 #[derive(Clone, Debug)]
 struct BearerChallenge {
     pub realm: Box<str>,
     pub service: Option<String>,
+    /// The scope required to access the resource, as requested by the registry
+    pub scope: Option<String>,
+    /// The reason why the request has been rejected, e.g. `invalid_token` or
+    /// `insufficient_scope`
+    pub error: Option<String>,
 }
 
 impl TryFrom<&HeaderValue> for BearerChallenge {
@@ -2184,6 +4355,8 @@ impl TryFrom<&ChallengeRef<'_>> for BearerChallenge {
         }
         let mut realm = None;
         let mut service = None;
+        let mut scope = None;
+        let mut error = None;
         for (k, v) in &value.params {
             if k.eq_ignore_ascii_case("realm") {
                 realm = Some(v.to_unescaped());
@@ -2192,6 +4365,14 @@ impl TryFrom<&ChallengeRef<'_>> for BearerChallenge {
             if k.eq_ignore_ascii_case("service") {
                 service = Some(v.to_unescaped());
             }
+
+            if k.eq_ignore_ascii_case("scope") {
+                scope = Some(v.to_unescaped());
+            }
+
+            if k.eq_ignore_ascii_case("error") {
+                error = Some(v.to_unescaped());
+            }
         }
 
         let realm = realm.ok_or("missing required parameter realm")?;
@@ -2199,6 +4380,8 @@ impl TryFrom<&ChallengeRef<'_>> for BearerChallenge {
         Ok(BearerChallenge {
             realm: realm.into_boxed_str(),
             service,
+            scope,
+            error,
         })
     }
 }
@@ -2282,7 +4465,8 @@ mod test {
                 .get("https://example.com/some/module.wasm"))
             .apply_auth(
                 &Reference::try_from(HELLO_IMAGE_TAG)?,
-                RegistryOperation::Pull
+                RegistryOperation::Pull,
+                &[]
             )
             .await?
             .into_request_builder()
@@ -2319,9 +4503,7 @@ mod test {
             .insert(
                 &Reference::try_from(HELLO_IMAGE_TAG)?,
                 RegistryOperation::Pull,
-                RegistryTokenType::Bearer(RegistryToken::Token {
-                    token: token.clone(),
-                }),
+                RegistryTokenType::Bearer(RegistryToken::new(token.clone())),
             )
             .await;
 
@@ -2330,7 +4512,8 @@ mod test {
                 .get("https://example.com/some/module.wasm"))
             .apply_auth(
                 &Reference::try_from(HELLO_IMAGE_TAG)?,
-                RegistryOperation::Pull
+                RegistryOperation::Pull,
+                &[]
             )
             .await?
             .into_request_builder()
@@ -2478,6 +4661,98 @@ mod test {
         );
     }
 
+    #[test]
+    fn referrers_url_encodes_artifact_type() {
+        let reference = Reference::try_from("webassembly.azurecr.io/hello@sha256:ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff".to_owned())
+            .expect("Could not parse reference");
+        let c = Client::new(ClientConfig::default());
+        assert_eq!(
+            "https://webassembly.azurecr.io/v2/hello/referrers/sha256:ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff?artifactType=application%2Fvnd.example%2Bjson",
+            c.to_v2_referrers_url(&reference, Some("application/vnd.example+json"))
+                .unwrap()
+        );
+    }
+
+    #[rstest(
+        link,
+        expected,
+        case(r#"</v2/hello/tags/list?n=2&last=b>; rel="next""#, Some("b")),
+        case(
+            r#"<https://registry.io/v2/hello/tags/list?last=v%2B1&n=2>;rel=next"#,
+            Some("v+1")
+        ),
+        case(r#"</v2/hello/tags/list?n=2&last=b>; rel="prev""#, None),
+        case(r#"</v2/hello/tags/list?n=2>; rel="next""#, None)
+    )]
+    fn test_next_page_last(link: &str, expected: Option<&str>) {
+        assert_eq!(next_page_last(link).as_deref(), expected);
+    }
+
+    #[rstest(
+        pattern,
+        registry,
+        expected,
+        case("localhost", "localhost", true),
+        case("localhost", "localhost:5000", true),
+        case("localhost:5000", "localhost:5000", true),
+        case("localhost:5000", "localhost:5001", false),
+        case("localhost:5000", "localhost", false),
+        case("*.local", "registry.local", true),
+        case("*.local", "a.registry.local:5000", true),
+        case("*.local", "registry.localhost", false),
+        case("*.local:5000", "registry.local:5001", false),
+        case("REGISTRY.local", "registry.LOCAL", true),
+        case("10.0.0.0/8", "10.1.2.3:5000", true),
+        case("10.0.0.0/8", "11.1.2.3", false),
+        case("10.0.0.0/8", "registry.local", false),
+        case("0.0.0.0/0", "192.168.1.1", true),
+        case("fd00::/8", "[fd12::1]:5000", true),
+        case("fd00::/8", "[fe80::1]:5000", false),
+        case("[::1]", "[::1]:5000", true)
+    )]
+    fn test_registry_matches(pattern: &str, registry: &str, expected: bool) {
+        assert_eq!(registry_matches(pattern, registry), expected);
+    }
+
+    #[test]
+    fn test_https_with_http_fallback_candidates() {
+        let protocol = ClientProtocol::HttpsWithHttpFallback(vec!["*.local".to_string()]);
+        assert_eq!(protocol.scheme_for("registry.local"), "https");
+        assert!(protocol.falls_back_to_http("registry.local:5000"));
+        assert!(protocol.falls_back_to_http("localhost:5000"));
+        assert!(protocol.falls_back_to_http("127.0.0.1:5000"));
+        assert!(protocol.falls_back_to_http("[::1]:5000"));
+        assert!(!protocol.falls_back_to_http("docker.io"));
+        assert!(!ClientProtocol::HttpsExcept(vec!["localhost".to_string()])
+            .falls_back_to_http("localhost"));
+    }
+
+    #[test]
+    fn test_check_credentials_allowed() {
+        let c = Client::new(ClientConfig {
+            allow_credentials_over_http: vec!["localhost:5000".to_string(), "*.local".to_string()],
+            ..Default::default()
+        });
+        let allowed = |url: &str| c.check_credentials_allowed(&url.parse().unwrap()).is_ok();
+        assert!(allowed("https://registry.io/v2/"));
+        assert!(allowed("http://localhost:5000/v2/"));
+        assert!(allowed("http://registry.local/token"));
+        assert!(!allowed("http://localhost:5001/v2/"));
+        assert!(!allowed("http://registry.io/v2/"));
+        assert!(matches!(
+            c.check_credentials_allowed(&"http://[::1]:5000/v2/".parse().unwrap()),
+            Err(OciDistributionError::InsecureCredentialsError(host)) if host == "[::1]:5000"
+        ));
+
+        let c = Client::new(ClientConfig {
+            allow_credentials_over_http: vec!["*".to_string()],
+            ..Default::default()
+        });
+        assert!(c
+            .check_credentials_allowed(&"http://127.0.0.1:5000/v2/".parse().unwrap())
+            .is_ok());
+    }
+
     #[test]
     fn blob_url_generation_uses_http_if_on_exception_list() {
         let insecure_registries = vec!["localhost".to_owned(), "oci.registry.local".to_owned()];
@@ -2599,6 +4874,217 @@ mod test {
         let text = r#"_ _ _ kjbwef??98{9898 }} }}"#;
         let res: Result<RegistryToken, serde_json::Error> = serde_json::from_str(text);
         assert!(res.is_err());
+
+        // expiration and refresh token fields
+        let text = r#"{"token": "abc", "expires_in": 300, "issued_at": "2024-01-01T00:00:00Z", "refresh_token": "def"}"#;
+        let res: Result<RegistryToken, serde_json::Error> = serde_json::from_str(text);
+        let rt = res.unwrap();
+        assert_eq!(rt.token(), "abc");
+        assert_eq!(rt.stated_expiration(u64::MAX), Some(1704067200 + 300));
+
+        // malformed optional fields are ignored
+        let text = r#"{"token": "abc", "expires_in": "soon", "issued_at": 42}"#;
+        let res: Result<RegistryToken, serde_json::Error> = serde_json::from_str(text);
+        let rt = res.unwrap();
+        assert_eq!(rt.token(), "abc");
+        assert_eq!(rt.stated_expiration(0), None);
+    }
+
+    #[test]
+    fn test_bearer_challenge_parsing() {
+        let header = HeaderValue::from_static(
+            r#"Bearer realm="https://auth.example.com/token",service="registry.example.com",scope="repository:foo:pull",error="insufficient_scope""#,
+        );
+        let challenge = BearerChallenge::try_from(&header).expect("valid challenge");
+        assert_eq!(challenge.realm.as_ref(), "https://auth.example.com/token");
+        assert_eq!(challenge.service.as_deref(), Some("registry.example.com"));
+        assert_eq!(challenge.scope.as_deref(), Some("repository:foo:pull"));
+        assert_eq!(challenge.error.as_deref(), Some("insufficient_scope"));
+
+        let header = HeaderValue::from_static(r#"Bearer realm="https://auth.example.com/token""#);
+        let challenge = BearerChallenge::try_from(&header).expect("valid challenge");
+        assert!(challenge.service.is_none());
+        assert!(challenge.scope.is_none());
+        assert!(challenge.error.is_none());
+
+        let header = HeaderValue::from_static(r#"Basic realm="registry""#);
+        assert!(BearerChallenge::try_from(&header).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_stored_auth_longest_prefix() {
+        let client = Client::default();
+        let basic = |user: &str| RegistryAuth::Basic(user.to_string(), "pass".to_string());
+        let stored = |repository: &str| {
+            let reference =
+                Reference::try_from(format!("registry.example.com/{repository}:latest")).unwrap();
+            let client = client.clone();
+            async move { client.stored_auth(&reference).await.as_deref().cloned() }
+        };
+        client
+            .store_auth_if_needed("registry.example.com", &basic("default"))
+            .await;
+        client
+            .store_auth_if_needed("registry.example.com/org-a", &basic("org-a"))
+            .await;
+
+        assert_eq!(stored("org-a/app").await, Some(basic("org-a")));
+        assert_eq!(stored("org-ab/app").await, Some(basic("default")));
+        assert_eq!(stored("org-b/app").await, Some(basic("default")));
+
+        // Different credentials replace the stored ones
+        client
+            .store_auth_if_needed("registry.example.com/org-a", &basic("robot"))
+            .await;
+        assert_eq!(stored("org-a/app").await, Some(basic("robot")));
+
+        client.remove_auth("registry.example.com/org-a").await;
+        assert_eq!(stored("org-a/app").await, Some(basic("default")));
+        client.clear_auth().await;
+        assert_eq!(stored("org-a/app").await, None);
+    }
+
+    fn pem_identity() -> ClientIdentity {
+        ClientIdentity::Pem {
+            certificate: Certificate {
+                encoding: CertificateEncoding::Pem,
+                data: include_bytes!("../tests/fixtures/tls/client.cert").to_vec(),
+            },
+            key: Zeroizing::new(include_bytes!("../tests/fixtures/tls/client.key").to_vec()),
+        }
+    }
+
+    #[test]
+    fn test_client_identity() {
+        let config = ClientConfig {
+            client_identity: Some(pem_identity()),
+            ..Default::default()
+        };
+        assert!(Client::try_from(config).is_ok());
+
+        let der = ClientIdentity::Pem {
+            certificate: Certificate {
+                encoding: CertificateEncoding::Der,
+                data: Vec::new(),
+            },
+            key: Zeroizing::default(),
+        };
+        let config = ClientConfig {
+            client_identity: Some(der),
+            ..Default::default()
+        };
+        assert!(matches!(
+            Client::try_from(config),
+            Err(OciDistributionError::ClientIdentityError(_))
+        ));
+
+        let pkcs12 = ClientIdentity::Pkcs12 {
+            archive: Zeroizing::new(include_bytes!("../tests/fixtures/tls/client.p12").to_vec()),
+            password: Zeroizing::new("password".to_string()),
+        };
+        assert!(!format!("{pkcs12:?}").contains("password\""));
+        let config = ClientConfig {
+            client_identity: Some(pkcs12),
+            ..Default::default()
+        };
+        assert_eq!(
+            Client::try_from(config).is_ok(),
+            cfg!(feature = "native-tls")
+        );
+    }
+
+    #[test]
+    fn test_registry_http_client() {
+        let tls = RegistryTlsConfig {
+            accept_invalid_certificates: Some(true),
+            client_identity: Some(pem_identity()),
+            ..Default::default()
+        };
+        let config = ClientConfig {
+            registry_tls: HashMap::from([
+                ("secure.example.com".to_string(), tls.clone()),
+                ("localhost:5000".to_string(), tls),
+            ]),
+            ..Default::default()
+        };
+        let clients = Client::try_from(config).unwrap().client;
+        let secure = &clients.by_host["secure.example.com"];
+        let local = &clients.by_host["localhost:5000"];
+
+        let picks =
+            |url: &str, client: &reqwest::Client| std::ptr::eq(clients.for_url(url), client);
+        assert!(picks("https://secure.example.com/v2/", secure));
+        assert!(picks("https://secure.example.com:8443/v2/", secure));
+        assert!(picks("http://localhost:5000/v2/", local));
+        assert!(picks("http://localhost/v2/", &clients.default));
+        assert!(picks("https://docker.io/v2/", &clients.default));
+    }
+
+    #[test]
+    fn test_load_certs_d() {
+        let root = tempfile::tempdir().unwrap();
+        let fixtures = path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/tls");
+        let certificate = fs::read(fixtures.join("client.cert")).unwrap();
+
+        let dir = root.path().join("registry.example.com:5000");
+        fs::create_dir(&dir).unwrap();
+        let mut bundle = certificate.clone();
+        bundle.extend_from_slice(&certificate);
+        fs::write(dir.join("ca.crt"), bundle).unwrap();
+        fs::copy(fixtures.join("client.cert"), dir.join("client.cert")).unwrap();
+        fs::copy(fixtures.join("client.key"), dir.join("client.key")).unwrap();
+        // Only holds a root certificate
+        let dir = root.path().join("other.example.com");
+        fs::create_dir(&dir).unwrap();
+        fs::write(dir.join("ca.crt"), &certificate).unwrap();
+
+        let registries = RegistryTlsConfig::load_certs_d(root.path()).unwrap();
+        assert_eq!(registries.len(), 2);
+        let tls = &registries["registry.example.com:5000"];
+        assert_eq!(tls.extra_root_certificates.len(), 2);
+        assert_eq!(
+            tls.extra_root_certificates[0].data,
+            certificate.trim_ascii_end()
+        );
+        assert!(tls.client_identity.is_some());
+        let tls = &registries["other.example.com"];
+        assert_eq!(tls.extra_root_certificates.len(), 1);
+        assert!(tls.client_identity.is_none());
+
+        let config = ClientConfig {
+            registry_tls: registries,
+            ..Default::default()
+        };
+        assert!(Client::try_from(config).is_ok());
+
+        // A private key without its certificate
+        fs::remove_file(root.path().join("other.example.com/ca.crt")).unwrap();
+        fs::write(root.path().join("other.example.com/client.key"), "").unwrap();
+        assert!(matches!(
+            RegistryTlsConfig::load_certs_d(root.path()),
+            Err(OciDistributionError::ClientIdentityError(_))
+        ));
+
+        assert!(RegistryTlsConfig::load_certs_d(root.path().join("missing"))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_registry_scopes() {
+        let reference = Reference::try_from("registry.example.com/target:latest").unwrap();
+        assert_eq!(
+            registry_scopes(&reference, RegistryOperation::Pull, &[]),
+            vec!["repository:target:pull"]
+        );
+        assert_eq!(
+            registry_scopes(
+                &reference,
+                RegistryOperation::Push,
+                &["source".to_string(), "target".to_string()]
+            ),
+            vec!["repository:target:pull,push", "repository:source:pull"]
+        );
     }
 
     fn check_auth_token(token: &str) {
@@ -2626,11 +5112,11 @@ mod test {
 
             let tok = c
                 .tokens
-                .get(&reference, RegistryOperation::Pull)
+                .get(&reference, RegistryOperation::Pull, &[])
                 .await
                 .expect("token is available");
             // We test that the token is longer than a minimal hash.
-            if let RegistryTokenType::Bearer(tok) = tok {
+            if let RegistryTokenType::Bearer(tok) = &tok {
                 check_auth_token(tok.token());
             } else {
                 panic!("Unexpeted Basic Auth Token");
@@ -2654,6 +5140,7 @@ mod test {
 
         let client = Client::new(ClientConfig {
             protocol: ClientProtocol::HttpsExcept(vec![format!("localhost:{}", port)]),
+            allow_credentials_over_http: vec![format!("localhost:{}", port)],
             ..Default::default()
         });
 
@@ -3062,7 +5549,7 @@ mod test {
             .await
             .expect("result from auth request");
 
-        let location = c
+        let (location, _) = c
             .begin_push_chunked_session(&image)
             .await
             .expect("failed to begin push session");
@@ -3170,6 +5657,7 @@ mod test {
 
         let c = Client::new(ClientConfig {
             protocol: ClientProtocol::HttpsExcept(vec![format!("localhost:{}", port)]),
+            allow_credentials_over_http: vec![format!("localhost:{}", port)],
             ..Default::default()
         });
 
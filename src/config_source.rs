@@ -0,0 +1,737 @@
+//! Implementations of [`ClientConfigSource`] loading the client configuration from a
+//! file or from environment variables
+//!
+//! The file source, `FileConfigSource`, requires the `config-file` feature.
+
+use std::collections::HashMap;
+#[cfg(feature = "config-file")]
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::client::{
+    split_pem_certificates, Certificate, CertificateEncoding, ClientConfig, ClientConfigSource,
+    ClientProtocol, RegistryTlsConfig,
+};
+use crate::errors::{OciDistributionError, Result};
+
+/// Prefix of the environment variables specific to this crate
+const ENV_PREFIX: &str = "OCI_CLIENT_";
+
+/// The protocol setting of a configuration file or of the environment
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Protocol {
+    Http,
+    Https,
+}
+
+/// The settings that can be loaded from a file or from the environment. Unset settings
+/// keep the defaults of [`ClientConfig`].
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Settings {
+    protocol: Option<Protocol>,
+    insecure_registries: Option<Vec<String>>,
+    http_fallback: Option<bool>,
+    allow_credentials_over_http: Option<Vec<String>>,
+    accept_invalid_hostnames: Option<bool>,
+    accept_invalid_certificates: Option<bool>,
+    use_monolithic_push: Option<bool>,
+    oauth2_password_grant: Option<bool>,
+    extra_root_certificates: Option<Vec<PathBuf>>,
+    certs_d: Option<PathBuf>,
+    max_concurrent_upload: Option<usize>,
+    max_concurrent_download: Option<usize>,
+    max_concurrent_requests_per_registry: Option<usize>,
+    max_requests_per_second_per_registry: Option<f64>,
+    adaptive_rate_limit: Option<bool>,
+    max_bytes_per_second: Option<u64>,
+    max_bytes_per_second_by_registry: Option<HashMap<String, u64>>,
+    default_token_expiration_secs: Option<usize>,
+    token_refresh_margin_secs: Option<usize>,
+    max_cached_tokens: Option<usize>,
+    token_prune_interval_secs: Option<u64>,
+    read_timeout_secs: Option<u64>,
+    connect_timeout_secs: Option<u64>,
+    https_proxy: Option<String>,
+    http_proxy: Option<String>,
+    no_proxy: Option<String>,
+}
+
+impl Settings {
+    /// Reads the settings from environment variables, given as name/value pairs
+    fn from_vars<K, V>(vars: impl IntoIterator<Item = (K, V)>) -> Result<Self>
+    where
+        K: Into<String>,
+        V: Into<String>,
+    {
+        let vars: HashMap<String, String> = vars
+            .into_iter()
+            .map(|(k, v)| (k.into(), v.into()))
+            .filter(|(_, v)| !v.is_empty())
+            .collect();
+        // Proxy variables are commonly set in lower case as well
+        let var = |name: &str| {
+            vars.get(name)
+                .or_else(|| vars.get(&name.to_lowercase()))
+                .cloned()
+        };
+        let crate_var = |name: &str| vars.get(&format!("{ENV_PREFIX}{name}")).cloned();
+        let parsed = |name: &str| -> Result<Option<u64>> {
+            crate_var(name)
+                .map(|value| {
+                    value.trim().parse().map_err(|_| {
+                        invalid(format!("{ENV_PREFIX}{name} is not a number: {value:?}"))
+                    })
+                })
+                .transpose()
+        };
+
+        Ok(Settings {
+            protocol: crate_var("PROTOCOL")
+                .map(|value| match value.trim().to_lowercase().as_str() {
+                    "http" => Ok(Protocol::Http),
+                    "https" => Ok(Protocol::Https),
+                    _ => Err(invalid(format!(
+                        "{ENV_PREFIX}PROTOCOL must be http or https: {value:?}"
+                    ))),
+                })
+                .transpose()?,
+            insecure_registries: crate_var("INSECURE_REGISTRIES").as_deref().map(list),
+            http_fallback: crate_var("HTTP_FALLBACK")
+                .map(|value| match value.trim().to_lowercase().as_str() {
+                    "1" | "true" | "yes" => Ok(true),
+                    "0" | "false" | "no" => Ok(false),
+                    _ => Err(invalid(format!(
+                        "{ENV_PREFIX}HTTP_FALLBACK is not a boolean: {value:?}"
+                    ))),
+                })
+                .transpose()?,
+            allow_credentials_over_http: crate_var("ALLOW_CREDENTIALS_OVER_HTTP")
+                .as_deref()
+                .map(list),
+            extra_root_certificates: var("SSL_CERT_FILE").map(|path| vec![path.into()]),
+            certs_d: crate_var("CERTS_D").map(PathBuf::from),
+            max_concurrent_upload: parsed("MAX_CONCURRENT_UPLOAD")?.map(|n| n as usize),
+            max_concurrent_download: parsed("MAX_CONCURRENT_DOWNLOAD")?.map(|n| n as usize),
+            read_timeout_secs: parsed("READ_TIMEOUT_SECS")?,
+            connect_timeout_secs: parsed("CONNECT_TIMEOUT_SECS")?,
+            https_proxy: var("HTTPS_PROXY"),
+            http_proxy: var("HTTP_PROXY"),
+            no_proxy: var("NO_PROXY"),
+            ..Default::default()
+        })
+    }
+
+    /// Reads the settings from a TOML or JSON file, depending on its extension. Relative
+    /// paths are resolved against the directory of the file.
+    #[cfg(feature = "config-file")]
+    fn from_file(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| invalid(format!("cannot read {path:?}: {e}")))?;
+        let mut settings: Settings = match path.extension().and_then(|e| e.to_str()) {
+            Some("toml") => toml::from_str(&content)
+                .map_err(|e| invalid(format!("cannot parse {path:?}: {e}")))?,
+            Some("json") => serde_json::from_str(&content)
+                .map_err(|e| invalid(format!("cannot parse {path:?}: {e}")))?,
+            _ => {
+                return Err(invalid(format!(
+                    "{path:?} is neither a TOML nor a JSON file"
+                )))
+            }
+        };
+
+        let dir = path.parent().unwrap_or(Path::new(""));
+        if let Some(paths) = settings.extra_root_certificates.as_mut() {
+            for path in paths {
+                *path = dir.join(&*path);
+            }
+        }
+        if let Some(path) = settings.certs_d.as_mut() {
+            *path = dir.join(&*path);
+        }
+        Ok(settings)
+    }
+
+    /// Returns the settings, overridden by the ones set in `overrides`
+    #[cfg(feature = "config-file")]
+    fn merge(self, overrides: Settings) -> Self {
+        Settings {
+            protocol: overrides.protocol.or(self.protocol),
+            insecure_registries: overrides.insecure_registries.or(self.insecure_registries),
+            http_fallback: overrides.http_fallback.or(self.http_fallback),
+            allow_credentials_over_http: overrides
+                .allow_credentials_over_http
+                .or(self.allow_credentials_over_http),
+            accept_invalid_hostnames: overrides
+                .accept_invalid_hostnames
+                .or(self.accept_invalid_hostnames),
+            accept_invalid_certificates: overrides
+                .accept_invalid_certificates
+                .or(self.accept_invalid_certificates),
+            use_monolithic_push: overrides.use_monolithic_push.or(self.use_monolithic_push),
+            oauth2_password_grant: overrides
+                .oauth2_password_grant
+                .or(self.oauth2_password_grant),
+            extra_root_certificates: overrides
+                .extra_root_certificates
+                .or(self.extra_root_certificates),
+            certs_d: overrides.certs_d.or(self.certs_d),
+            max_concurrent_upload: overrides
+                .max_concurrent_upload
+                .or(self.max_concurrent_upload),
+            max_concurrent_download: overrides
+                .max_concurrent_download
+                .or(self.max_concurrent_download),
+            max_concurrent_requests_per_registry: overrides
+                .max_concurrent_requests_per_registry
+                .or(self.max_concurrent_requests_per_registry),
+            max_requests_per_second_per_registry: overrides
+                .max_requests_per_second_per_registry
+                .or(self.max_requests_per_second_per_registry),
+            adaptive_rate_limit: overrides.adaptive_rate_limit.or(self.adaptive_rate_limit),
+            max_bytes_per_second: overrides.max_bytes_per_second.or(self.max_bytes_per_second),
+            max_bytes_per_second_by_registry: overrides
+                .max_bytes_per_second_by_registry
+                .or(self.max_bytes_per_second_by_registry),
+            default_token_expiration_secs: overrides
+                .default_token_expiration_secs
+                .or(self.default_token_expiration_secs),
+            token_refresh_margin_secs: overrides
+                .token_refresh_margin_secs
+                .or(self.token_refresh_margin_secs),
+            max_cached_tokens: overrides.max_cached_tokens.or(self.max_cached_tokens),
+            token_prune_interval_secs: overrides
+                .token_prune_interval_secs
+                .or(self.token_prune_interval_secs),
+            read_timeout_secs: overrides.read_timeout_secs.or(self.read_timeout_secs),
+            connect_timeout_secs: overrides.connect_timeout_secs.or(self.connect_timeout_secs),
+            https_proxy: overrides.https_proxy.or(self.https_proxy),
+            http_proxy: overrides.http_proxy.or(self.http_proxy),
+            no_proxy: overrides.no_proxy.or(self.no_proxy),
+        }
+    }
+}
+
+/// Settings that passed validation, along with the certificates they refer to
+struct LoadedSettings {
+    settings: Settings,
+    protocol: ClientProtocol,
+    extra_root_certificates: Vec<Certificate>,
+    registry_tls: HashMap<String, RegistryTlsConfig>,
+}
+
+impl LoadedSettings {
+    /// Validates the settings and loads the certificates they refer to
+    fn load(settings: Settings) -> Result<Self> {
+        let count = |value: Option<usize>| value.map(|n| n as u64);
+        for (name, value) in [
+            (
+                "max_concurrent_upload",
+                count(settings.max_concurrent_upload),
+            ),
+            (
+                "max_concurrent_download",
+                count(settings.max_concurrent_download),
+            ),
+            (
+                "max_concurrent_requests_per_registry",
+                count(settings.max_concurrent_requests_per_registry),
+            ),
+            (
+                "default_token_expiration_secs",
+                count(settings.default_token_expiration_secs),
+            ),
+            ("max_cached_tokens", count(settings.max_cached_tokens)),
+            (
+                "token_prune_interval_secs",
+                settings.token_prune_interval_secs,
+            ),
+            ("read_timeout_secs", settings.read_timeout_secs),
+            ("connect_timeout_secs", settings.connect_timeout_secs),
+            ("max_bytes_per_second", settings.max_bytes_per_second),
+        ] {
+            if value == Some(0) {
+                return Err(invalid(format!("{name} must be greater than zero")));
+            }
+        }
+        for (registry, limit) in settings.max_bytes_per_second_by_registry.iter().flatten() {
+            if *limit == 0 {
+                return Err(invalid(format!(
+                    "max_bytes_per_second_by_registry of {registry} must be greater than zero"
+                )));
+            }
+        }
+        if let Some(rps) = settings.max_requests_per_second_per_registry {
+            if !(rps.is_finite() && rps > 0.0) {
+                return Err(invalid(
+                    "max_requests_per_second_per_registry must be greater than zero".to_string(),
+                ));
+            }
+        }
+        for proxy in [&settings.https_proxy, &settings.http_proxy]
+            .into_iter()
+            .flatten()
+        {
+            reqwest::Proxy::all(proxy)
+                .map_err(|e| invalid(format!("invalid proxy {proxy:?}: {e}")))?;
+        }
+        #[cfg(not(feature = "native-tls"))]
+        if settings.accept_invalid_hostnames.is_some() {
+            return Err(invalid(
+                "accept_invalid_hostnames requires the native-tls feature".to_string(),
+            ));
+        }
+
+        let insecure_registries = settings.insecure_registries.clone().unwrap_or_default();
+        for (name, registries) in [
+            ("insecure_registries", &insecure_registries),
+            (
+                "allow_credentials_over_http",
+                settings
+                    .allow_credentials_over_http
+                    .as_ref()
+                    .unwrap_or(&vec![]),
+            ),
+        ] {
+            if registries.iter().any(|r| r.trim().is_empty()) {
+                return Err(invalid(format!("{name} must not contain empty entries")));
+            }
+        }
+        let protocol = match settings.protocol {
+            Some(Protocol::Http) if !insecure_registries.is_empty() => {
+                return Err(invalid(
+                    "insecure_registries cannot be used with the http protocol".to_string(),
+                ));
+            }
+            Some(Protocol::Http) => ClientProtocol::Http,
+            _ if settings.http_fallback == Some(true) => {
+                ClientProtocol::HttpsWithHttpFallback(insecure_registries)
+            }
+            _ if !insecure_registries.is_empty() => {
+                ClientProtocol::HttpsExcept(insecure_registries)
+            }
+            _ => ClientProtocol::Https,
+        };
+
+        let mut extra_root_certificates = Vec::new();
+        for path in settings.extra_root_certificates.iter().flatten() {
+            let bundle =
+                std::fs::read(path).map_err(|e| invalid(format!("cannot read {path:?}: {e}")))?;
+            let count = extra_root_certificates.len();
+            extra_root_certificates.extend(split_pem_certificates(&bundle).map(|data| {
+                Certificate {
+                    encoding: CertificateEncoding::Pem,
+                    data,
+                }
+            }));
+            if extra_root_certificates.len() == count {
+                return Err(invalid(format!("no PEM certificate found in {path:?}")));
+            }
+        }
+
+        let registry_tls = match &settings.certs_d {
+            Some(path) => RegistryTlsConfig::load_certs_d(path)
+                .map_err(|e| invalid(format!("cannot load {path:?}: {e}")))?,
+            None => HashMap::new(),
+        };
+
+        Ok(LoadedSettings {
+            settings,
+            protocol,
+            extra_root_certificates,
+            registry_tls,
+        })
+    }
+
+    fn client_config(&self) -> ClientConfig {
+        let settings = &self.settings;
+        let defaults = ClientConfig::default();
+        ClientConfig {
+            protocol: self.protocol.clone(),
+            allow_credentials_over_http: settings
+                .allow_credentials_over_http
+                .clone()
+                .unwrap_or_default(),
+            #[cfg(feature = "native-tls")]
+            accept_invalid_hostnames: settings
+                .accept_invalid_hostnames
+                .unwrap_or(defaults.accept_invalid_hostnames),
+            accept_invalid_certificates: settings
+                .accept_invalid_certificates
+                .unwrap_or(defaults.accept_invalid_certificates),
+            use_monolithic_push: settings
+                .use_monolithic_push
+                .unwrap_or(defaults.use_monolithic_push),
+            oauth2_password_grant: settings
+                .oauth2_password_grant
+                .unwrap_or(defaults.oauth2_password_grant),
+            extra_root_certificates: self.extra_root_certificates.clone(),
+            registry_tls: self.registry_tls.clone(),
+            max_concurrent_upload: settings
+                .max_concurrent_upload
+                .unwrap_or(defaults.max_concurrent_upload),
+            max_concurrent_download: settings
+                .max_concurrent_download
+                .unwrap_or(defaults.max_concurrent_download),
+            max_concurrent_requests_per_registry: settings
+                .max_concurrent_requests_per_registry
+                .or(defaults.max_concurrent_requests_per_registry),
+            max_requests_per_second_per_registry: settings
+                .max_requests_per_second_per_registry
+                .or(defaults.max_requests_per_second_per_registry),
+            adaptive_rate_limit: settings
+                .adaptive_rate_limit
+                .unwrap_or(defaults.adaptive_rate_limit),
+            max_bytes_per_second: settings
+                .max_bytes_per_second
+                .or(defaults.max_bytes_per_second),
+            max_bytes_per_second_by_registry: settings
+                .max_bytes_per_second_by_registry
+                .clone()
+                .unwrap_or_default(),
+            default_token_expiration_secs: settings
+                .default_token_expiration_secs
+                .unwrap_or(defaults.default_token_expiration_secs),
+            token_refresh_margin_secs: settings
+                .token_refresh_margin_secs
+                .unwrap_or(defaults.token_refresh_margin_secs),
+            max_cached_tokens: settings.max_cached_tokens.or(defaults.max_cached_tokens),
+            token_prune_interval: settings
+                .token_prune_interval_secs
+                .map(Duration::from_secs)
+                .or(defaults.token_prune_interval),
+            read_timeout: settings
+                .read_timeout_secs
+                .map(Duration::from_secs)
+                .or(defaults.read_timeout),
+            connect_timeout: settings
+                .connect_timeout_secs
+                .map(Duration::from_secs)
+                .or(defaults.connect_timeout),
+            https_proxy: settings.https_proxy.clone().or(defaults.https_proxy),
+            http_proxy: settings.http_proxy.clone().or(defaults.http_proxy),
+            no_proxy: settings.no_proxy.clone().or(defaults.no_proxy),
+            ..defaults
+        }
+    }
+}
+
+/// Splits a comma-separated list of registries
+fn list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|registry| !registry.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+fn invalid(message: String) -> OciDistributionError {
+    OciDistributionError::ClientConfigError(message)
+}
+
+/// A [`ClientConfigSource`] reading the configuration from a TOML or JSON file.
+///
+/// The format is chosen from the extension of the file, `.toml` or `.json`. All the
+/// settings are optional, unknown ones are rejected:
+///
+/// ```toml
+/// protocol = "https"                # or "http"
+/// insecure_registries = ["localhost:5000", "*.internal", "10.0.0.0/8"]
+/// http_fallback = false             # fall back to HTTP for the insecure registries
+/// allow_credentials_over_http = ["localhost:5000"]
+/// accept_invalid_hostnames = false  # requires the native-tls feature
+/// accept_invalid_certificates = false
+/// use_monolithic_push = false
+/// oauth2_password_grant = false
+/// extra_root_certificates = ["ca.pem"]
+/// certs_d = "/etc/docker/certs.d"
+/// max_concurrent_upload = 16
+/// max_concurrent_download = 16
+/// max_concurrent_requests_per_registry = 32
+/// max_requests_per_second_per_registry = 50.0
+/// adaptive_rate_limit = true       # slow down when the registry answers with 429
+/// max_bytes_per_second = 10_000_000
+/// max_bytes_per_second_by_registry = { "registry.internal:5000" = 1_000_000 }
+/// default_token_expiration_secs = 60
+/// token_refresh_margin_secs = 30
+/// max_cached_tokens = 1000
+/// token_prune_interval_secs = 300
+/// read_timeout_secs = 30
+/// connect_timeout_secs = 10
+/// https_proxy = "http://proxy:3128"
+/// http_proxy = "http://proxy:3128"
+/// no_proxy = "localhost,.internal"
+/// ```
+///
+/// Relative paths are resolved against the directory of the file. The file is read,
+/// and the certificates it refers to are loaded, when the source is created.
+///
+/// This requires the `config-file` feature.
+#[cfg(feature = "config-file")]
+pub struct FileConfigSource {
+    settings: LoadedSettings,
+}
+
+#[cfg(feature = "config-file")]
+impl FileConfigSource {
+    /// Reads and validates the configuration file at `path`
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self> {
+        Ok(FileConfigSource {
+            settings: LoadedSettings::load(Settings::from_file(path.as_ref())?)?,
+        })
+    }
+
+    /// Overrides the settings of the file with the ones set by the environment, as read
+    /// by [`EnvConfigSource`]
+    pub fn with_env_overrides(self) -> Result<Self> {
+        self.with_var_overrides(std::env::vars())
+    }
+
+    /// Overrides the settings of the file with the ones set by the given environment
+    /// variables. See [`EnvConfigSource`] for the variables that are used.
+    pub fn with_var_overrides<K, V>(self, vars: impl IntoIterator<Item = (K, V)>) -> Result<Self>
+    where
+        K: Into<String>,
+        V: Into<String>,
+    {
+        let settings = self.settings.settings.merge(Settings::from_vars(vars)?);
+        Ok(FileConfigSource {
+            settings: LoadedSettings::load(settings)?,
+        })
+    }
+}
+
+#[cfg(feature = "config-file")]
+impl ClientConfigSource for FileConfigSource {
+    fn client_config(&self) -> ClientConfig {
+        self.settings.client_config()
+    }
+}
+
+/// A [`ClientConfigSource`] reading the configuration from environment variables.
+///
+/// The following variables are used, empty ones are ignored:
+///
+/// * `HTTPS_PROXY`, `HTTP_PROXY` and `NO_PROXY`, or their lower case variants
+/// * `SSL_CERT_FILE`: a PEM bundle of root certificates to trust
+/// * `OCI_CLIENT_PROTOCOL`: `http` or `https`
+/// * `OCI_CLIENT_INSECURE_REGISTRIES`: a comma-separated list of registries accessed
+///   over HTTP, see [`ClientProtocol`] for the patterns
+/// * `OCI_CLIENT_HTTP_FALLBACK`: `true` to try HTTPS first for the insecure registries
+/// * `OCI_CLIENT_ALLOW_CREDENTIALS_OVER_HTTP`: a comma-separated list of the hosts to
+///   which credentials may be sent over HTTP
+/// * `OCI_CLIENT_CERTS_D`: a `certs.d` directory, see
+///   [`RegistryTlsConfig::load_certs_d`]
+/// * `OCI_CLIENT_READ_TIMEOUT_SECS` and `OCI_CLIENT_CONNECT_TIMEOUT_SECS`
+/// * `OCI_CLIENT_MAX_CONCURRENT_UPLOAD` and `OCI_CLIENT_MAX_CONCURRENT_DOWNLOAD`
+pub struct EnvConfigSource {
+    settings: LoadedSettings,
+}
+
+impl EnvConfigSource {
+    /// Reads and validates the configuration set by the environment of the process
+    pub fn from_env() -> Result<Self> {
+        Self::from_vars(std::env::vars())
+    }
+
+    /// Reads and validates the configuration set by the given environment variables
+    pub fn from_vars<K, V>(vars: impl IntoIterator<Item = (K, V)>) -> Result<Self>
+    where
+        K: Into<String>,
+        V: Into<String>,
+    {
+        Ok(EnvConfigSource {
+            settings: LoadedSettings::load(Settings::from_vars(vars)?)?,
+        })
+    }
+}
+
+impl ClientConfigSource for EnvConfigSource {
+    fn client_config(&self) -> ClientConfig {
+        self.settings.client_config()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "config-file")]
+    fn write(dir: &Path, name: &str, content: &str) -> PathBuf {
+        let path = dir.join(name);
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    #[cfg(feature = "config-file")]
+    #[test]
+    fn file_config_source_toml() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::copy("tests/fixtures/tls/client.cert", dir.path().join("ca.pem")).unwrap();
+        let path = write(
+            dir.path(),
+            "config.toml",
+            r#"
+            insecure_registries = ["localhost:5000", "*.internal"]
+            extra_root_certificates = ["ca.pem"]
+            max_concurrent_upload = 4
+            max_concurrent_requests_per_registry = 8
+            max_requests_per_second_per_registry = 2.5
+            max_bytes_per_second_by_registry = { "registry.internal" = 1000 }
+            read_timeout_secs = 30
+            https_proxy = "http://proxy:3128"
+            no_proxy = "localhost"
+            "#,
+        );
+
+        let config = FileConfigSource::from_path(&path).unwrap().client_config();
+
+        assert_eq!(
+            config.protocol,
+            ClientProtocol::HttpsExcept(vec![
+                "localhost:5000".to_string(),
+                "*.internal".to_string()
+            ])
+        );
+        assert_eq!(config.extra_root_certificates.len(), 1);
+        assert_eq!(config.max_concurrent_upload, 4);
+        assert_eq!(
+            config.max_concurrent_download,
+            ClientConfig::default().max_concurrent_download
+        );
+        assert_eq!(config.max_concurrent_requests_per_registry, Some(8));
+        assert_eq!(config.max_requests_per_second_per_registry, Some(2.5));
+        assert!(config.adaptive_rate_limit);
+        assert_eq!(config.max_bytes_per_second, None);
+        assert_eq!(
+            config.max_bytes_per_second_by_registry,
+            HashMap::from([("registry.internal".to_string(), 1000)])
+        );
+        assert_eq!(config.read_timeout, Some(Duration::from_secs(30)));
+        assert_eq!(config.https_proxy.as_deref(), Some("http://proxy:3128"));
+        assert_eq!(config.no_proxy.as_deref(), Some("localhost"));
+    }
+
+    #[cfg(feature = "config-file")]
+    #[test]
+    fn file_config_source_json() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write(
+            dir.path(),
+            "config.json",
+            r#"{"protocol": "http", "use_monolithic_push": true}"#,
+        );
+
+        let config = FileConfigSource::from_path(&path).unwrap().client_config();
+
+        assert_eq!(config.protocol, ClientProtocol::Http);
+        assert!(config.use_monolithic_push);
+    }
+
+    #[cfg(feature = "config-file")]
+    #[rstest::rstest(
+        name,
+        content,
+        case("config.yaml", "protocol: http"),
+        case("config.toml", "protocol = \"ftp\""),
+        case("config.toml", "unknown_setting = 1"),
+        case("config.toml", "max_concurrent_upload = 0"),
+        case("config.toml", "max_requests_per_second_per_registry = 0.0"),
+        case(
+            "config.toml",
+            "max_bytes_per_second_by_registry = { \"localhost\" = 0 }"
+        ),
+        case("config.toml", "read_timeout_secs = -1"),
+        case("config.toml", "allow_credentials_over_http = [\"\"]"),
+        case("config.toml", "https_proxy = \"http://[::1\""),
+        case("config.toml", "extra_root_certificates = [\"missing.pem\"]"),
+        case("config.toml", "extra_root_certificates = [\"config.toml\"]"),
+        case(
+            "config.json",
+            r#"{"protocol": "http", "insecure_registries": ["localhost"]}"#
+        )
+    )]
+    fn file_config_source_invalid(name: &str, content: &str) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write(dir.path(), name, content);
+
+        let err = FileConfigSource::from_path(&path)
+            .err()
+            .expect("invalid config");
+
+        assert!(
+            matches!(err, OciDistributionError::ClientConfigError(_)),
+            "{err:?}"
+        );
+    }
+
+    #[test]
+    fn env_config_source() {
+        let config = EnvConfigSource::from_vars([
+            ("https_proxy", "http://proxy:3128"),
+            ("NO_PROXY", "localhost"),
+            ("HTTP_PROXY", ""),
+            (
+                "OCI_CLIENT_INSECURE_REGISTRIES",
+                "localhost:5000, 10.0.0.0/8",
+            ),
+            ("OCI_CLIENT_HTTP_FALLBACK", "true"),
+            ("OCI_CLIENT_ALLOW_CREDENTIALS_OVER_HTTP", "localhost:5000"),
+            ("OCI_CLIENT_CONNECT_TIMEOUT_SECS", "5"),
+            ("OCI_CLIENT_MAX_CONCURRENT_DOWNLOAD", "8"),
+        ])
+        .unwrap()
+        .client_config();
+
+        assert_eq!(
+            config.protocol,
+            ClientProtocol::HttpsWithHttpFallback(vec![
+                "localhost:5000".to_string(),
+                "10.0.0.0/8".to_string()
+            ])
+        );
+        assert_eq!(
+            config.allow_credentials_over_http,
+            vec!["localhost:5000".to_string()]
+        );
+        assert_eq!(config.https_proxy.as_deref(), Some("http://proxy:3128"));
+        assert_eq!(config.http_proxy, None);
+        assert_eq!(config.no_proxy.as_deref(), Some("localhost"));
+        assert_eq!(config.connect_timeout, Some(Duration::from_secs(5)));
+        assert_eq!(config.max_concurrent_download, 8);
+
+        for vars in [
+            [("OCI_CLIENT_READ_TIMEOUT_SECS", "soon")],
+            [("OCI_CLIENT_MAX_CONCURRENT_UPLOAD", "0")],
+            [("OCI_CLIENT_HTTP_FALLBACK", "maybe")],
+            [("SSL_CERT_FILE", "/nonexistent/ca.pem")],
+        ] {
+            assert!(EnvConfigSource::from_vars(vars).is_err(), "{vars:?}");
+        }
+    }
+
+    #[cfg(feature = "config-file")]
+    #[test]
+    fn env_overrides_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write(
+            dir.path(),
+            "config.toml",
+            "max_concurrent_upload = 4\nmax_concurrent_download = 4\n",
+        );
+
+        let config = FileConfigSource::from_path(&path)
+            .unwrap()
+            .with_var_overrides([("OCI_CLIENT_MAX_CONCURRENT_UPLOAD", "2")])
+            .unwrap()
+            .client_config();
+
+        assert_eq!(config.max_concurrent_upload, 2);
+        assert_eq!(config.max_concurrent_download, 4);
+    }
+}
@@ -0,0 +1,569 @@
+//! A harness checking which parts of the [OCI distribution
+//! specification](https://github.com/opencontainers/distribution-spec/blob/main/spec.md)
+//! a registry supports, through the [`Client`]
+//!
+//! The harness runs the pull, push, content discovery and content management workflows
+//! of the specification against a repository, and reports the outcome of each feature:
+//!
+//! ```rust,no_run
+//! use oci_client::conformance;
+//! use oci_client::secrets::RegistryAuth;
+//! use oci_client::{Client, Reference};
+//!
+//! # async fn run() {
+//! let client = Client::default();
+//! let repository: Reference = "registry.example.com/conformance".parse().unwrap();
+//! let report = conformance::run(&client, &repository, &RegistryAuth::Anonymous).await;
+//! println!("{report}");
+//! # }
+//! ```
+//!
+//! The content pushed by the harness is tagged `conformance-<id>-*`, and deleted at the
+//! end of the run when the registry supports it.
+
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use futures_util::stream;
+use http::HeaderValue;
+
+use crate::client::{Client, Config, ImageLayer};
+use crate::errors::Result;
+use crate::manifest::{
+    OciDescriptor, OciImageManifest, OciManifest, IMAGE_CONFIG_MEDIA_TYPE, IMAGE_LAYER_MEDIA_TYPE,
+    OCI_IMAGE_MEDIA_TYPE,
+};
+use crate::secrets::RegistryAuth;
+use crate::{sha256_digest, Reference};
+
+/// The artifact type of the referrer pushed by the harness
+const REFERRER_ARTIFACT_TYPE: &str = "application/vnd.oci-client.conformance.v1+json";
+
+/// A feature of the distribution specification checked by the harness
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ConformanceFeature {
+    /// Pulling manifests, by tag and by digest, and blobs
+    Pull,
+    /// Pushing a blob with a single `PUT` request
+    MonolithicUpload,
+    /// Pushing a blob in several `PATCH` requests
+    ChunkedUpload,
+    /// Pushing a manifest
+    ManifestPush,
+    /// Mounting a blob from another repository
+    CrossMount,
+    /// Listing the tags of a repository
+    TagList,
+    /// Paginating the tags with the `n` and `last` parameters and the `Link` header
+    TagPagination,
+    /// Listing the manifests referring to a manifest with the referrers API
+    Referrers,
+    /// Deleting a tag without deleting its manifest
+    TagDelete,
+    /// Deleting a manifest by digest
+    ManifestDelete,
+}
+
+impl fmt::Display for ConformanceFeature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ConformanceFeature::Pull => "pull",
+            ConformanceFeature::MonolithicUpload => "monolithic upload",
+            ConformanceFeature::ChunkedUpload => "chunked upload",
+            ConformanceFeature::ManifestPush => "manifest push",
+            ConformanceFeature::CrossMount => "cross-repository mount",
+            ConformanceFeature::TagList => "tag list",
+            ConformanceFeature::TagPagination => "tag pagination",
+            ConformanceFeature::Referrers => "referrers API",
+            ConformanceFeature::TagDelete => "tag delete",
+            ConformanceFeature::ManifestDelete => "manifest delete",
+        })
+    }
+}
+
+/// The outcome of the check of a [`ConformanceFeature`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConformanceOutcome {
+    /// The registry supports the feature
+    Passed,
+    /// The registry doesn't support the feature, for the given reason
+    Failed(String),
+    /// The feature couldn't be checked, because a feature it depends on failed
+    Skipped(String),
+}
+
+/// The outcomes of a conformance run, in the order the features were checked
+#[derive(Debug, Clone, Default)]
+pub struct ConformanceReport {
+    /// The outcome of each feature
+    pub outcomes: Vec<(ConformanceFeature, ConformanceOutcome)>,
+}
+
+impl ConformanceReport {
+    /// The outcome of a feature, if it was checked
+    pub fn outcome(&self, feature: ConformanceFeature) -> Option<&ConformanceOutcome> {
+        self.outcomes
+            .iter()
+            .find(|(f, _)| *f == feature)
+            .map(|(_, outcome)| outcome)
+    }
+
+    /// Whether the registry supports a feature
+    pub fn passed(&self, feature: ConformanceFeature) -> bool {
+        self.outcome(feature) == Some(&ConformanceOutcome::Passed)
+    }
+
+    /// Whether the registry supports all the checked features
+    pub fn all_passed(&self) -> bool {
+        self.outcomes
+            .iter()
+            .all(|(_, outcome)| *outcome == ConformanceOutcome::Passed)
+    }
+
+    fn record(&mut self, feature: ConformanceFeature, result: Result<()>) -> bool {
+        let outcome = match result {
+            Ok(()) => ConformanceOutcome::Passed,
+            Err(e) => ConformanceOutcome::Failed(e.to_string()),
+        };
+        let passed = outcome == ConformanceOutcome::Passed;
+        self.outcomes.push((feature, outcome));
+        passed
+    }
+
+    fn skip(&mut self, feature: ConformanceFeature, dependency: ConformanceFeature) {
+        self.outcomes.push((
+            feature,
+            ConformanceOutcome::Skipped(format!("{dependency} failed")),
+        ));
+    }
+}
+
+impl fmt::Display for ConformanceReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (feature, outcome) in &self.outcomes {
+            match outcome {
+                ConformanceOutcome::Passed => writeln!(f, "{feature}: passed")?,
+                ConformanceOutcome::Failed(reason) => writeln!(f, "{feature}: failed ({reason})")?,
+                ConformanceOutcome::Skipped(reason) => {
+                    writeln!(f, "{feature}: skipped ({reason})")?
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Runs the conformance workflows against a repository, given as `registry/repository`.
+///
+/// The repository `<repository>-mount` of the registry is used as the target of the
+/// cross-repository mount. The credentials must allow to pull, push and delete in both
+/// repositories.
+pub async fn run(
+    client: &Client,
+    repository: &Reference,
+    auth: &RegistryAuth,
+) -> ConformanceReport {
+    let run = Run::new(client, repository);
+    client
+        .store_auth_if_needed(repository.resolve_registry(), auth)
+        .await;
+    run.execute(auth).await
+}
+
+/// The content and the references of a conformance run
+struct Run<'a> {
+    client: &'a Client,
+    registry: String,
+    repository: String,
+    prefix: String,
+    config: Config,
+    layer: ImageLayer,
+}
+
+impl<'a> Run<'a> {
+    fn new(client: &'a Client, repository: &Reference) -> Self {
+        let id = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        // Unique content, so that blobs pushed by previous runs aren't reused
+        let layer = format!("oci-client conformance layer {id}\n").repeat(64);
+        Run {
+            client,
+            registry: repository.registry().to_string(),
+            repository: repository.repository().to_string(),
+            prefix: format!("conformance-{id}"),
+            config: Config::new(
+                format!("{{\"conformance\":\"{id}\"}}").into_bytes(),
+                IMAGE_CONFIG_MEDIA_TYPE.to_string(),
+                None,
+            ),
+            layer: ImageLayer::new(layer.into_bytes(), IMAGE_LAYER_MEDIA_TYPE.to_string(), None),
+        }
+    }
+
+    fn tagged(&self, tag: &str) -> Reference {
+        Reference::with_tag(
+            self.registry.clone(),
+            self.repository.clone(),
+            format!("{}-{tag}", self.prefix),
+        )
+    }
+
+    fn digested(&self, digest: &str) -> Reference {
+        Reference::with_digest(
+            self.registry.clone(),
+            self.repository.clone(),
+            digest.to_string(),
+        )
+    }
+
+    async fn execute(&self, auth: &RegistryAuth) -> ConformanceReport {
+        let mut report = ConformanceReport::default();
+        let image = self.tagged("image");
+
+        // Push
+        let config_digest = sha256_digest(&self.config.data);
+        let config_pushed = report.record(
+            ConformanceFeature::MonolithicUpload,
+            self.push_monolithically(&image, &config_digest).await,
+        );
+        let layer_digest = self.layer.sha256_digest();
+        let layer_pushed = report.record(
+            ConformanceFeature::ChunkedUpload,
+            self.push_chunked(&image, &layer_digest).await,
+        );
+        let manifest = if !config_pushed {
+            report.skip(
+                ConformanceFeature::ManifestPush,
+                ConformanceFeature::MonolithicUpload,
+            );
+            None
+        } else if !layer_pushed {
+            report.skip(
+                ConformanceFeature::ManifestPush,
+                ConformanceFeature::ChunkedUpload,
+            );
+            None
+        } else {
+            let manifest =
+                OciImageManifest::build(std::slice::from_ref(&self.layer), &self.config, None);
+            let pushed = self.push_manifest(&image, &manifest).await;
+            match pushed {
+                Ok(descriptor) => {
+                    report.record(ConformanceFeature::ManifestPush, Ok(()));
+                    Some(descriptor)
+                }
+                Err(e) => {
+                    report.record(ConformanceFeature::ManifestPush, Err(e));
+                    None
+                }
+            }
+        };
+        let Some(manifest) = manifest else {
+            for feature in [
+                ConformanceFeature::Pull,
+                ConformanceFeature::CrossMount,
+                ConformanceFeature::TagList,
+                ConformanceFeature::TagPagination,
+                ConformanceFeature::Referrers,
+                ConformanceFeature::TagDelete,
+                ConformanceFeature::ManifestDelete,
+            ] {
+                report.skip(feature, ConformanceFeature::ManifestPush);
+            }
+            return report;
+        };
+
+        // Pull
+        report.record(
+            ConformanceFeature::Pull,
+            self.pull(&image, &manifest, auth).await,
+        );
+
+        // Cross-repository mount
+        let target = Reference::with_tag(
+            self.registry.clone(),
+            format!("{}-mount", self.repository),
+            format!("{}-image", self.prefix),
+        );
+        report.record(
+            ConformanceFeature::CrossMount,
+            self.mount(&target, &image, &layer_digest).await,
+        );
+
+        // Content discovery
+        let tags_listed = report.record(
+            ConformanceFeature::TagList,
+            self.list_tags(&image, auth).await,
+        );
+        if tags_listed {
+            report.record(
+                ConformanceFeature::TagPagination,
+                self.paginate_tags(&image, &manifest, auth).await,
+            );
+        } else {
+            report.skip(
+                ConformanceFeature::TagPagination,
+                ConformanceFeature::TagList,
+            );
+        }
+        let referrer = self.referrers(&manifest).await;
+        let referrer = match referrer {
+            Ok(referrer) => {
+                report.record(ConformanceFeature::Referrers, Ok(()));
+                Some(referrer)
+            }
+            Err(e) => {
+                report.record(ConformanceFeature::Referrers, Err(e));
+                None
+            }
+        };
+
+        // Content management
+        report.record(
+            ConformanceFeature::TagDelete,
+            self.delete_tag(&manifest, auth).await,
+        );
+        report.record(
+            ConformanceFeature::ManifestDelete,
+            self.delete_manifests(&manifest, referrer.as_ref(), auth)
+                .await,
+        );
+        report
+    }
+
+    async fn push_monolithically(&self, image: &Reference, digest: &str) -> Result<()> {
+        self.client
+            .push_blob_monolithically(image, self.config.data.clone(), digest)
+            .await?;
+        self.expect_blob(image, digest).await
+    }
+
+    async fn push_chunked(&self, image: &Reference, digest: &str) -> Result<()> {
+        // Each item of the stream is pushed as a chunk
+        let data = self.layer.data.clone();
+        let (first, second) = data.split_at(data.len() / 2);
+        let chunks = stream::iter([
+            Ok(bytes::Bytes::copy_from_slice(first)),
+            Ok(bytes::Bytes::copy_from_slice(second)),
+        ]);
+        self.client.push_blob_stream(image, chunks, digest).await?;
+        self.expect_blob(image, digest).await
+    }
+
+    async fn expect_blob(&self, image: &Reference, digest: &str) -> Result<()> {
+        if self.client.blob_exists(image, digest).await? {
+            Ok(())
+        } else {
+            Err(violation(format!(
+                "blob {digest} not found after its upload"
+            )))
+        }
+    }
+
+    /// Pushes an image manifest, returning its descriptor
+    async fn push_manifest(
+        &self,
+        image: &Reference,
+        manifest: &OciImageManifest,
+    ) -> Result<OciDescriptor> {
+        let body = serde_json::to_vec(&OciManifest::Image(manifest.clone()))?;
+        self.push_manifest_at(image, body).await
+    }
+
+    async fn pull(
+        &self,
+        image: &Reference,
+        manifest: &OciDescriptor,
+        auth: &RegistryAuth,
+    ) -> Result<()> {
+        let (_, digest) = self.client.pull_manifest(image, auth).await?;
+        if digest != manifest.digest {
+            return Err(violation(format!(
+                "pulled manifest {digest} instead of {}",
+                manifest.digest
+            )));
+        }
+        self.client
+            .pull_manifest(&self.digested(&manifest.digest), auth)
+            .await?;
+
+        let mut layer = Vec::new();
+        self.client
+            .pull_blob(image, self.layer.sha256_digest().as_str(), &mut layer)
+            .await?;
+        if layer != self.layer.data {
+            return Err(violation("the pulled blob differs from the pushed one"));
+        }
+        Ok(())
+    }
+
+    async fn mount(&self, target: &Reference, source: &Reference, digest: &str) -> Result<()> {
+        self.client.mount_blob(target, source, digest).await?;
+        self.expect_blob(target, digest).await
+    }
+
+    async fn list_tags(&self, image: &Reference, auth: &RegistryAuth) -> Result<()> {
+        let tags = self.client.list_tags(image, auth, None, None).await?;
+        match image.tag() {
+            Some(tag) if tags.tags.iter().any(|t| t == tag) => Ok(()),
+            _ => Err(violation("the pushed tag is not listed")),
+        }
+    }
+
+    /// Tags the manifest several times, and lists the tags one by one
+    async fn paginate_tags(
+        &self,
+        image: &Reference,
+        manifest: &OciDescriptor,
+        auth: &RegistryAuth,
+    ) -> Result<()> {
+        let (body, _) = self
+            .client
+            .pull_manifest_raw(
+                &self.digested(&manifest.digest),
+                auth,
+                &[OCI_IMAGE_MEDIA_TYPE],
+            )
+            .await?;
+        let mut expected = vec![image.tag().unwrap_or_default().to_string()];
+        for name in ["page-a", "page-b"] {
+            let tagged = self.tagged(name);
+            self.client
+                .push_manifest_raw(
+                    &tagged,
+                    body.clone(),
+                    HeaderValue::from_static(OCI_IMAGE_MEDIA_TYPE),
+                )
+                .await?;
+            expected.extend(tagged.tag().map(String::from));
+        }
+
+        let mut listed = Vec::new();
+        let mut last = None;
+        loop {
+            let page = self
+                .client
+                .list_tags(image, auth, Some(1), last.as_deref())
+                .await?;
+            if page.tags.len() > 1 {
+                return Err(violation(format!(
+                    "{} tags returned instead of 1",
+                    page.tags.len()
+                )));
+            }
+            listed.extend(page.tags);
+            match page.next {
+                Some(next) if listed.len() <= 1000 => last = Some(next),
+                Some(_) => return Err(violation("the pagination doesn't end")),
+                None if listed.len() > 1 => break,
+                None => return Err(violation("no Link header to the next page")),
+            }
+        }
+        match expected.iter().find(|tag| !listed.contains(tag)) {
+            Some(tag) => Err(violation(format!("tag {tag} missing from the pages"))),
+            None => Ok(()),
+        }
+    }
+
+    /// Pushes an artifact referring to the manifest, and lists the referrers of the
+    /// manifest. Returns the descriptor of the artifact.
+    async fn referrers(&self, subject: &OciDescriptor) -> Result<OciDescriptor> {
+        let mut artifact =
+            OciImageManifest::build(std::slice::from_ref(&self.layer), &self.config, None);
+        artifact.artifact_type = Some(REFERRER_ARTIFACT_TYPE.to_string());
+        artifact.subject = Some(subject.clone());
+        let body = serde_json::to_vec(&OciManifest::Image(artifact))?;
+        let digest = sha256_digest(&body);
+        let referrer = self.digested(&digest);
+        let descriptor = self.push_manifest_at(&referrer, body).await?;
+
+        let referrers = self
+            .client
+            .pull_referrers(
+                &self.digested(&subject.digest),
+                Some(REFERRER_ARTIFACT_TYPE),
+            )
+            .await?;
+        if !referrers.manifests.iter().any(|m| m.digest == digest) {
+            return Err(violation("the pushed referrer is not listed"));
+        }
+        Ok(descriptor)
+    }
+
+    /// Pushes a serialized image manifest, returning its descriptor
+    async fn push_manifest_at(
+        &self,
+        reference: &Reference,
+        body: Vec<u8>,
+    ) -> Result<OciDescriptor> {
+        let descriptor = OciDescriptor {
+            media_type: OCI_IMAGE_MEDIA_TYPE.to_string(),
+            digest: sha256_digest(&body),
+            size: body.len() as i64,
+            ..Default::default()
+        };
+        self.client
+            .push_manifest_raw(
+                reference,
+                body,
+                HeaderValue::from_static(OCI_IMAGE_MEDIA_TYPE),
+            )
+            .await?;
+        Ok(descriptor)
+    }
+
+    /// Deletes a tag, and checks that its manifest is still there
+    async fn delete_tag(&self, manifest: &OciDescriptor, auth: &RegistryAuth) -> Result<()> {
+        let tagged = self.tagged("delete");
+        let (body, _) = self
+            .client
+            .pull_manifest_raw(
+                &self.digested(&manifest.digest),
+                auth,
+                &[OCI_IMAGE_MEDIA_TYPE],
+            )
+            .await?;
+        self.client
+            .push_manifest_raw(
+                &tagged,
+                body,
+                HeaderValue::from_static(OCI_IMAGE_MEDIA_TYPE),
+            )
+            .await?;
+
+        self.client.delete_manifest(&tagged, auth).await?;
+        if self.client.pull_manifest(&tagged, auth).await.is_ok() {
+            return Err(violation("the tag is still there after its deletion"));
+        }
+        self.client
+            .pull_manifest(&self.digested(&manifest.digest), auth)
+            .await?;
+        Ok(())
+    }
+
+    /// Deletes the pushed manifests by digest
+    async fn delete_manifests(
+        &self,
+        manifest: &OciDescriptor,
+        referrer: Option<&OciDescriptor>,
+        auth: &RegistryAuth,
+    ) -> Result<()> {
+        for descriptor in referrer.into_iter().chain([manifest]) {
+            let reference = self.digested(&descriptor.digest);
+            self.client.delete_manifest(&reference, auth).await?;
+            if self.client.pull_manifest(&reference, auth).await.is_ok() {
+                return Err(violation(format!(
+                    "manifest {} is still there after its deletion",
+                    descriptor.digest
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn violation(message: impl Into<String>) -> crate::errors::OciDistributionError {
+    crate::errors::OciDistributionError::SpecViolationError(message.into())
+}
@@ -0,0 +1,229 @@
+//! The metadata that registries send along with their answers: rate limits, request
+//! IDs and warnings
+//!
+//! The diagnostics are available on the results of the operations, such as
+//! [`PushResponse`](crate::client::PushResponse), on the errors reported by the
+//! registries, see [`OciDistributionError::diagnostics`](crate::errors::OciDistributionError::diagnostics),
+//! and for each registry with [`Client::diagnostics`](crate::Client::diagnostics).
+
+use std::time::Duration;
+
+use reqwest::header::{HeaderMap, WARNING};
+
+/// The headers carrying the ID given to a request by a registry, in order of preference
+const REQUEST_ID_HEADERS: &[&str] = &[
+    "x-request-id",
+    "x-amzn-requestid",
+    "x-amz-request-id",
+    "x-github-request-id",
+    "x-ms-request-id",
+    "cf-ray",
+];
+
+/// The headers announcing the number of requests allowed by the rate limit
+const RATE_LIMIT_LIMIT_HEADERS: &[&str] = &["ratelimit-limit", "x-ratelimit-limit"];
+
+/// The headers announcing the number of requests left before the rate limit is reached
+const RATE_LIMIT_REMAINING_HEADERS: &[&str] = &["ratelimit-remaining", "x-ratelimit-remaining"];
+
+/// The headers announcing in how many seconds the rate limit is reset
+const RATE_LIMIT_RESET_HEADERS: &[&str] = &["ratelimit-reset", "x-ratelimit-reset"];
+
+/// Maximum number of distinct warnings kept for a registry
+const MAX_WARNINGS: usize = 16;
+
+/// The metadata of the answers of a registry
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ResponseDiagnostics {
+    /// The ID given by the registry to the request, to quote when reporting an issue
+    pub request_id: Option<String>,
+    /// The rate limit of the registry, as announced by its `RateLimit-*` headers
+    pub rate_limit: Option<RateLimit>,
+    /// The texts of the `Warning` headers sent by the registry, e.g. about deprecations
+    pub warnings: Vec<String>,
+}
+
+impl ResponseDiagnostics {
+    /// Reads the diagnostics from the headers of an answer
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let header = |names: &[&str]| {
+            names
+                .iter()
+                .find_map(|name| headers.get(*name)?.to_str().ok())
+                .map(str::trim)
+        };
+        let limit = header(RATE_LIMIT_LIMIT_HEADERS).and_then(parse_quota);
+        let remaining = header(RATE_LIMIT_REMAINING_HEADERS).and_then(parse_quota);
+        let reset = header(RATE_LIMIT_RESET_HEADERS)
+            .and_then(|value| value.parse().ok())
+            .map(Duration::from_secs);
+        let rate_limit =
+            (limit.is_some() || remaining.is_some() || reset.is_some()).then(|| RateLimit {
+                limit: limit.map(|(limit, _)| limit),
+                remaining: remaining.map(|(remaining, _)| remaining),
+                window: limit.or(remaining).and_then(|(_, window)| window),
+                reset,
+            });
+
+        ResponseDiagnostics {
+            request_id: header(REQUEST_ID_HEADERS).map(str::to_string),
+            rate_limit,
+            warnings: headers
+                .get_all(WARNING)
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .map(warning_text)
+                .collect(),
+        }
+    }
+
+    /// Whether the registry sent no diagnostics
+    pub fn is_empty(&self) -> bool {
+        self.request_id.is_none() && self.rate_limit.is_none() && self.warnings.is_empty()
+    }
+
+    /// Updates the diagnostics of a registry with the ones of its latest answer: the
+    /// request ID and the rate limit are replaced, and the new warnings are added
+    pub(crate) fn update(&mut self, latest: &ResponseDiagnostics) {
+        if latest.request_id.is_some() {
+            self.request_id.clone_from(&latest.request_id);
+        }
+        if latest.rate_limit.is_some() {
+            self.rate_limit = latest.rate_limit;
+        }
+        for warning in &latest.warnings {
+            if !self.warnings.contains(warning) {
+                self.warnings.push(warning.clone());
+            }
+        }
+        let excess = self.warnings.len().saturating_sub(MAX_WARNINGS);
+        self.warnings.drain(..excess);
+    }
+}
+
+/// The rate limit of a registry, such as the pull limit of Docker Hub
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    /// The number of requests allowed during the window
+    pub limit: Option<u64>,
+    /// The number of requests left during the current window
+    pub remaining: Option<u64>,
+    /// The duration of the window, e.g. 6 hours for Docker Hub
+    pub window: Option<Duration>,
+    /// The time left before the current window ends
+    pub reset: Option<Duration>,
+}
+
+/// Parses a quota such as `100;w=21600`, made of a number of requests and optionally of
+/// the duration of its window
+fn parse_quota(value: &str) -> Option<(u64, Option<Duration>)> {
+    let mut parts = value.split(';').map(str::trim);
+    let quota = parts.next()?.parse().ok()?;
+    let window = parts
+        .filter_map(|part| part.strip_prefix("w="))
+        .find_map(|window| window.parse().ok())
+        .map(Duration::from_secs);
+    Some((quota, window))
+}
+
+/// The text of a `Warning` header such as `299 - "Deprecated"`, or the whole value when
+/// it doesn't follow this format
+fn warning_text(value: &str) -> String {
+    let text = value
+        .splitn(3, ' ')
+        .nth(2)
+        .and_then(|text| text.trim().strip_prefix('"'))
+        .and_then(|text| text.split_once('"'))
+        .map(|(text, _)| text);
+    text.unwrap_or(value).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(headers: &[(&'static str, &'static str)]) -> HeaderMap {
+        headers
+            .iter()
+            .map(|(name, value)| (name.parse().unwrap(), value.parse().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn test_from_headers() {
+        let diagnostics = ResponseDiagnostics::from_headers(&headers(&[
+            ("ratelimit-limit", "100;w=21600"),
+            ("ratelimit-remaining", "76;w=21600"),
+            ("x-github-request-id", "abc-123"),
+            ("warning", r#"299 - "This endpoint is deprecated""#),
+            ("warning", "not a warning in the usual format"),
+        ]));
+        assert_eq!(diagnostics.request_id.as_deref(), Some("abc-123"));
+        assert_eq!(
+            diagnostics.rate_limit,
+            Some(RateLimit {
+                limit: Some(100),
+                remaining: Some(76),
+                window: Some(Duration::from_secs(21600)),
+                reset: None,
+            })
+        );
+        assert_eq!(
+            diagnostics.warnings,
+            vec![
+                "This endpoint is deprecated".to_string(),
+                "not a warning in the usual format".to_string(),
+            ]
+        );
+
+        let diagnostics = ResponseDiagnostics::from_headers(&headers(&[
+            ("x-ratelimit-remaining", "9"),
+            ("x-ratelimit-reset", "30"),
+        ]));
+        assert_eq!(
+            diagnostics.rate_limit,
+            Some(RateLimit {
+                limit: None,
+                remaining: Some(9),
+                window: None,
+                reset: Some(Duration::from_secs(30)),
+            })
+        );
+
+        assert!(ResponseDiagnostics::from_headers(&HeaderMap::new()).is_empty());
+    }
+
+    #[test]
+    fn test_update() {
+        let mut diagnostics = ResponseDiagnostics {
+            request_id: Some("1".to_string()),
+            rate_limit: None,
+            warnings: vec!["deprecated".to_string()],
+        };
+        diagnostics.update(&ResponseDiagnostics {
+            request_id: None,
+            rate_limit: Some(RateLimit {
+                limit: Some(100),
+                remaining: Some(99),
+                window: None,
+                reset: None,
+            }),
+            warnings: vec!["deprecated".to_string(), "slow down".to_string()],
+        });
+        assert_eq!(diagnostics.request_id.as_deref(), Some("1"));
+        assert_eq!(diagnostics.rate_limit.unwrap().remaining, Some(99));
+        assert_eq!(diagnostics.warnings, vec!["deprecated", "slow down"]);
+
+        for i in 0..2 * MAX_WARNINGS {
+            diagnostics.update(&ResponseDiagnostics {
+                warnings: vec![i.to_string()],
+                ..Default::default()
+            });
+        }
+        assert_eq!(diagnostics.warnings.len(), MAX_WARNINGS);
+        assert_eq!(
+            diagnostics.warnings.last().unwrap(),
+            &(2 * MAX_WARNINGS - 1).to_string()
+        );
+    }
+}
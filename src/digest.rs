@@ -49,23 +49,29 @@ impl<'a> Digest<'a> {
     }
 }
 
+/// Computes the digest of some content fed in pieces, with the algorithm of an expected
+/// digest, e.g. to verify a blob downloaded from the URL returned by
+/// [`Client::resolve_blob_url`](crate::Client::resolve_blob_url)
+pub struct Digester(Hasher);
+
 /// Helper wrapper around various digest algorithms to make it easier to use them with our blob
 /// utils. This has to be an enum because the digest algorithms aren't object safe so we can't box
 /// dynner them
-pub(crate) enum Digester {
+enum Hasher {
     Sha256(sha2::Sha256),
     Sha384(sha2::Sha384),
     Sha512(sha2::Sha512),
 }
 
 impl Digester {
+    /// Creates a digester using the algorithm of the given digest, such as `sha256:...`
     pub fn new(digest: &str) -> Result<Self> {
         let parsed_digest = Digest::new(digest)?;
 
         match parsed_digest.algorithm {
-            "sha256" => Ok(Digester::Sha256(sha2::Sha256::new())),
-            "sha384" => Ok(Digester::Sha384(sha2::Sha384::new())),
-            "sha512" => Ok(Digester::Sha512(sha2::Sha512::new())),
+            "sha256" => Ok(Digester(Hasher::Sha256(sha2::Sha256::new()))),
+            "sha384" => Ok(Digester(Hasher::Sha384(sha2::Sha384::new()))),
+            "sha512" => Ok(Digester(Hasher::Sha512(sha2::Sha512::new()))),
             // We already check this above when parsing, but just in case, we return the error as
             // well here
             _ => Err(DigestError::UnsupportedAlgorithm(
@@ -74,20 +80,35 @@ impl Digester {
         }
     }
 
+    /// Feeds the next piece of the content
     pub fn update(&mut self, data: impl AsRef<[u8]>) {
-        match self {
-            Self::Sha256(d) => d.update(data),
-            Self::Sha384(d) => d.update(data),
-            Self::Sha512(d) => d.update(data),
+        match &mut self.0 {
+            Hasher::Sha256(d) => d.update(data),
+            Hasher::Sha384(d) => d.update(data),
+            Hasher::Sha512(d) => d.update(data),
         }
     }
 
+    /// Returns the digest of the content fed so far, prefixed with its algorithm, and
+    /// starts over
     pub fn finalize(&mut self) -> String {
-        match self {
-            Self::Sha256(d) => format!("sha256:{:x}", d.finalize_reset()),
-            Self::Sha384(d) => format!("sha384:{:x}", d.finalize_reset()),
-            Self::Sha512(d) => format!("sha512:{:x}", d.finalize_reset()),
+        match &mut self.0 {
+            Hasher::Sha256(d) => format!("sha256:{:x}", d.finalize_reset()),
+            Hasher::Sha384(d) => format!("sha384:{:x}", d.finalize_reset()),
+            Hasher::Sha512(d) => format!("sha512:{:x}", d.finalize_reset()),
+        }
+    }
+
+    /// Checks that the content fed so far has the expected digest
+    pub fn verify(&mut self, expected: &str) -> Result<()> {
+        let actual = self.finalize();
+        if actual != expected {
+            return Err(DigestError::VerificationError {
+                expected: expected.to_string(),
+                actual,
+            });
         }
+        Ok(())
     }
 }
 
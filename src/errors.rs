@@ -1,8 +1,11 @@
 //! Errors related to interacting with an OCI compliant remote store
 
+use http::{Method, StatusCode};
 use thiserror::Error;
 
+use crate::diagnostics::ResponseDiagnostics;
 pub use crate::digest::DigestError;
+use crate::Reference;
 
 /// Errors that can be raised while interacting with an OCI registry
 #[derive(Error, Debug)]
@@ -10,6 +13,12 @@ pub enum OciDistributionError {
     /// Authentication error
     #[error("Authentication failure: {0}")]
     AuthenticationFailure(String),
+    /// The client configuration loaded from a file or the environment is not valid
+    #[error("Invalid client configuration: {0}")]
+    ClientConfigError(String),
+    /// The client identity used for mutual TLS authentication is not valid
+    #[error("Invalid client identity: {0}")]
+    ClientIdentityError(String),
     #[error("Failed to convert Config into ConfigFile: {0}")]
     /// Transparent wrapper around `std::string::FromUtf8Error`
     ConfigConversionError(String),
@@ -31,6 +40,11 @@ pub enum OciDistributionError {
     /// Registry returned a layer with an incompatible type
     #[error("Incompatible layer media type: {0}")]
     IncompatibleLayerMediaTypeError(String),
+    /// Credentials were about to be sent over plain HTTP to a host that isn't allowed to
+    /// receive them, see
+    /// [`ClientConfig::allow_credentials_over_http`](crate::client::ClientConfig::allow_credentials_over_http)
+    #[error("Refusing to send credentials over plain HTTP to {0}")]
+    InsecureCredentialsError(String),
     /// IO Error
     #[error(transparent)]
     IoError(#[from] std::io::Error),
@@ -59,6 +73,12 @@ pub enum OciDistributionError {
         envelope: OciEnvelope,
         /// Request URL
         url: String,
+        /// HTTP status code
+        status: u16,
+        /// The metadata of the answer of the registry
+        diagnostics: Box<ResponseDiagnostics>,
+        /// The request that failed
+        context: Box<ErrorContext>,
     },
     /// Registry didn't return a Digest object
     #[error("Registry did not return a digest header")]
@@ -81,6 +101,10 @@ pub enum OciDistributionError {
         url: String,
         /// Error message returned by the remote server
         message: String,
+        /// The metadata of the answer of the server
+        diagnostics: Box<ResponseDiagnostics>,
+        /// The request that failed
+        context: Box<ErrorContext>,
     },
     /// The [OCI distribution spec](https://github.com/opencontainers/distribution-spec/blob/main/spec.md)
     /// is not respected by the remote registry
@@ -91,6 +115,10 @@ pub enum OciDistributionError {
     UnauthorizedError {
         /// request URL
         url: String,
+        /// The metadata of the answer of the registry
+        diagnostics: Box<ResponseDiagnostics>,
+        /// The request that failed
+        context: Box<ErrorContext>,
     },
     /// Cannot parse URL
     #[error("Error parsing Url {0}")]
@@ -101,11 +129,163 @@ pub enum OciDistributionError {
     /// Schema version not supported
     #[error("Unsupported schema version: {0}")]
     UnsupportedSchemaVersionError(i32),
+    /// The registry doesn't delete tags, see
+    /// [`RegistryQuirks::no_tag_delete`](crate::quirks::RegistryQuirks::no_tag_delete)
+    #[error("Registry does not support deleting tag {0}")]
+    UnsupportedTagDeleteError(String),
     /// Versioned object: JSON deserialization error
     #[error("Failed to parse manifest: {0}")]
     VersionedParsingError(String),
 }
 
+impl OciDistributionError {
+    /// The metadata of the answer of the registry that caused the error, such as its
+    /// request ID, if the error was reported by the registry
+    pub fn diagnostics(&self) -> Option<&ResponseDiagnostics> {
+        match self {
+            OciDistributionError::RegistryError { diagnostics, .. }
+            | OciDistributionError::ServerError { diagnostics, .. }
+            | OciDistributionError::UnauthorizedError { diagnostics, .. } => Some(diagnostics),
+            _ => None,
+        }
+    }
+
+    /// The request that caused the error, if the error was reported by the registry
+    pub fn context(&self) -> Option<&ErrorContext> {
+        match self {
+            OciDistributionError::RegistryError { context, .. }
+            | OciDistributionError::ServerError { context, .. }
+            | OciDistributionError::UnauthorizedError { context, .. } => Some(context),
+            _ => None,
+        }
+    }
+
+    /// Whether the error reports that a manifest, a blob, an upload or a repository
+    /// doesn't exist
+    pub fn is_not_found(&self) -> bool {
+        match self {
+            OciDistributionError::ImageManifestNotFoundError(_) => true,
+            OciDistributionError::RegistryError { envelope, .. } if envelope.has_known_code() => {
+                envelope.has_code(|code| {
+                    matches!(
+                        code,
+                        OciErrorCode::BlobUnknown
+                            | OciErrorCode::BlobUploadUnknown
+                            | OciErrorCode::ManifestUnknown
+                            | OciErrorCode::NameUnknown
+                            | OciErrorCode::NotFound
+                    )
+                })
+            }
+            _ => self.status() == Some(StatusCode::NOT_FOUND),
+        }
+    }
+
+    /// Whether the error reports missing or rejected credentials, or a lack of
+    /// permission
+    pub fn is_auth(&self) -> bool {
+        match self {
+            OciDistributionError::AuthenticationFailure(_)
+            | OciDistributionError::UnauthorizedError { .. } => true,
+            OciDistributionError::RegistryError { envelope, .. } if envelope.has_known_code() => {
+                envelope.has_code(|code| {
+                    matches!(code, OciErrorCode::Unauthorized | OciErrorCode::Denied)
+                })
+            }
+            _ => matches!(
+                self.status(),
+                Some(StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN)
+            ),
+        }
+    }
+
+    /// Whether the error reports that too many requests were sent to the registry
+    pub fn is_rate_limited(&self) -> bool {
+        match self {
+            OciDistributionError::RegistryError { envelope, .. } if envelope.has_known_code() => {
+                envelope.has_code(|code| *code == OciErrorCode::Toomanyrequests)
+            }
+            _ => self.status() == Some(StatusCode::TOO_MANY_REQUESTS),
+        }
+    }
+
+    /// Whether the operation may succeed if tried again later: the registry was rate
+    /// limited, unavailable or too slow, or the connection failed
+    pub fn is_retryable(&self) -> bool {
+        if self.is_rate_limited() {
+            return true;
+        }
+        match self {
+            OciDistributionError::RegistryError { envelope, .. } if envelope.has_known_code() => {
+                false
+            }
+            OciDistributionError::RequestError(error) if error.is_timeout() => true,
+            #[cfg(not(target_arch = "wasm32"))]
+            OciDistributionError::RequestError(error) if error.is_connect() => true,
+            OciDistributionError::IoError(error) => matches!(
+                error.kind(),
+                std::io::ErrorKind::ConnectionReset
+                    | std::io::ErrorKind::ConnectionAborted
+                    | std::io::ErrorKind::BrokenPipe
+                    | std::io::ErrorKind::TimedOut
+                    | std::io::ErrorKind::UnexpectedEof
+                    | std::io::ErrorKind::Interrupted
+            ),
+            _ => matches!(
+                self.status(),
+                Some(
+                    StatusCode::REQUEST_TIMEOUT
+                        | StatusCode::INTERNAL_SERVER_ERROR
+                        | StatusCode::BAD_GATEWAY
+                        | StatusCode::SERVICE_UNAVAILABLE
+                        | StatusCode::GATEWAY_TIMEOUT
+                )
+            ),
+        }
+    }
+
+    /// The HTTP status of the answer reporting the error, when known
+    fn status(&self) -> Option<StatusCode> {
+        match self {
+            OciDistributionError::RegistryError { status: code, .. }
+            | OciDistributionError::ServerError { code, .. } => StatusCode::from_u16(*code).ok(),
+            OciDistributionError::UnauthorizedError { .. } => Some(StatusCode::UNAUTHORIZED),
+            OciDistributionError::RequestError(error) => error.status(),
+            _ => None,
+        }
+    }
+}
+
+/// The request of an operation that failed
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ErrorContext {
+    /// The HTTP method of the request
+    pub method: Option<Method>,
+    /// The reference of the image, or of the repository, the request was about
+    pub reference: Option<String>,
+    /// The digest of the manifest or of the blob the request was about
+    pub digest: Option<String>,
+}
+
+impl ErrorContext {
+    /// The context of a request about an image
+    pub(crate) fn new(method: Method, image: &Reference) -> Self {
+        ErrorContext {
+            method: Some(method),
+            reference: Some(image.whole()),
+            digest: image.digest().map(str::to_string),
+        }
+    }
+
+    /// Sets the digest of the blob the request was about
+    pub(crate) fn with_digest(self, digest: &str) -> Self {
+        ErrorContext {
+            digest: Some(digest.to_string()),
+            ..self
+        }
+    }
+}
+
 /// Helper type to declare `Result` objects that might return a `OciDistributionError`
 pub type Result<T> = std::result::Result<T, OciDistributionError>;
 
@@ -143,6 +323,19 @@ pub struct OciEnvelope {
     pub errors: Vec<OciError>,
 }
 
+impl OciEnvelope {
+    /// Whether one of the errors has a code matching the predicate
+    fn has_code(&self, predicate: impl Fn(&OciErrorCode) -> bool) -> bool {
+        self.errors.iter().any(|error| predicate(&error.code))
+    }
+
+    /// Whether one of the errors has a code defined by the spec, in which case the
+    /// codes rather than the HTTP status tell the kind of the error
+    fn has_known_code(&self) -> bool {
+        self.has_code(|code| !matches!(code, OciErrorCode::Unknown(_)))
+    }
+}
+
 impl std::fmt::Display for OciEnvelope {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let errors: Vec<String> = self.errors.iter().map(|e| e.to_string()).collect();
@@ -153,8 +346,8 @@ impl std::fmt::Display for OciEnvelope {
 /// OCI error codes
 ///
 /// Outlined [here](https://github.com/opencontainers/distribution-spec/blob/master/spec.md#errors-2)
-#[derive(serde::Deserialize, serde::Serialize, Debug, PartialEq, Eq)]
-#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(from = "String", into = "String")]
 pub enum OciErrorCode {
     /// Blob unknown to registry
     ///
@@ -207,6 +400,74 @@ pub enum OciErrorCode {
     Unsupported,
     /// Too many requests from client
     Toomanyrequests,
+    /// A code not defined by the OCI spec, as returned by the registry
+    Unknown(String),
+}
+
+impl OciErrorCode {
+    /// The code as returned by registries, e.g. `MANIFEST_UNKNOWN`
+    pub fn as_str(&self) -> &str {
+        match self {
+            OciErrorCode::BlobUnknown => "BLOB_UNKNOWN",
+            OciErrorCode::BlobUploadInvalid => "BLOB_UPLOAD_INVALID",
+            OciErrorCode::BlobUploadUnknown => "BLOB_UPLOAD_UNKNOWN",
+            OciErrorCode::DigestInvalid => "DIGEST_INVALID",
+            OciErrorCode::ManifestBlobUnknown => "MANIFEST_BLOB_UNKNOWN",
+            OciErrorCode::ManifestInvalid => "MANIFEST_INVALID",
+            OciErrorCode::ManifestUnknown => "MANIFEST_UNKNOWN",
+            OciErrorCode::ManifestUnverified => "MANIFEST_UNVERIFIED",
+            OciErrorCode::NameInvalid => "NAME_INVALID",
+            OciErrorCode::NameUnknown => "NAME_UNKNOWN",
+            OciErrorCode::NotFound => "NOT_FOUND",
+            OciErrorCode::SizeInvalid => "SIZE_INVALID",
+            OciErrorCode::TagInvalid => "TAG_INVALID",
+            OciErrorCode::Unauthorized => "UNAUTHORIZED",
+            OciErrorCode::Denied => "DENIED",
+            OciErrorCode::Unsupported => "UNSUPPORTED",
+            OciErrorCode::Toomanyrequests => "TOOMANYREQUESTS",
+            OciErrorCode::Unknown(code) => code,
+        }
+    }
+}
+
+impl From<String> for OciErrorCode {
+    fn from(code: String) -> Self {
+        match code.as_str() {
+            "BLOB_UNKNOWN" => OciErrorCode::BlobUnknown,
+            "BLOB_UPLOAD_INVALID" => OciErrorCode::BlobUploadInvalid,
+            "BLOB_UPLOAD_UNKNOWN" => OciErrorCode::BlobUploadUnknown,
+            "DIGEST_INVALID" => OciErrorCode::DigestInvalid,
+            "MANIFEST_BLOB_UNKNOWN" => OciErrorCode::ManifestBlobUnknown,
+            "MANIFEST_INVALID" => OciErrorCode::ManifestInvalid,
+            "MANIFEST_UNKNOWN" => OciErrorCode::ManifestUnknown,
+            "MANIFEST_UNVERIFIED" => OciErrorCode::ManifestUnverified,
+            "NAME_INVALID" => OciErrorCode::NameInvalid,
+            "NAME_UNKNOWN" => OciErrorCode::NameUnknown,
+            "NOT_FOUND" => OciErrorCode::NotFound,
+            "SIZE_INVALID" => OciErrorCode::SizeInvalid,
+            "TAG_INVALID" => OciErrorCode::TagInvalid,
+            "UNAUTHORIZED" => OciErrorCode::Unauthorized,
+            "DENIED" => OciErrorCode::Denied,
+            "UNSUPPORTED" => OciErrorCode::Unsupported,
+            "TOOMANYREQUESTS" => OciErrorCode::Toomanyrequests,
+            _ => OciErrorCode::Unknown(code),
+        }
+    }
+}
+
+impl From<OciErrorCode> for String {
+    fn from(code: OciErrorCode) -> Self {
+        match code {
+            OciErrorCode::Unknown(code) => code,
+            code => code.as_str().to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for OciErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
 }
 
 #[cfg(test)]
@@ -264,4 +525,94 @@ mod test {
         assert_eq!("authentication required", e.message);
         assert_eq!(serde_json::value::Value::Null, e.detail);
     }
+
+    const EXAMPLE_ERROR_UNKNOWN_CODE: &str = r#"
+      {"errors":[{"code":"QUOTA_EXCEEDED","message":"storage quota exceeded"},{"code":"DENIED"}]}
+      "#;
+    #[test]
+    fn test_deserialize_unknown_code() {
+        let envelope: OciEnvelope =
+            serde_json::from_str(EXAMPLE_ERROR_UNKNOWN_CODE).expect("parse example error");
+        assert_eq!(
+            OciErrorCode::Unknown("QUOTA_EXCEEDED".to_string()),
+            envelope.errors[0].code
+        );
+        assert_eq!(OciErrorCode::Denied, envelope.errors[1].code);
+
+        let serialized = serde_json::to_value(&envelope).unwrap();
+        assert_eq!(serialized["errors"][0]["code"], "QUOTA_EXCEEDED");
+        assert_eq!(serialized["errors"][1]["code"], "DENIED");
+    }
+
+    fn registry_error(code: OciErrorCode) -> OciDistributionError {
+        registry_error_with_status(code, 400)
+    }
+
+    fn registry_error_with_status(code: OciErrorCode, status: u16) -> OciDistributionError {
+        OciDistributionError::RegistryError {
+            envelope: OciEnvelope {
+                errors: vec![OciError {
+                    code,
+                    message: String::new(),
+                    detail: serde_json::Value::Null,
+                }],
+            },
+            url: String::new(),
+            status,
+            diagnostics: Box::default(),
+            context: Box::default(),
+        }
+    }
+
+    fn server_error(code: u16) -> OciDistributionError {
+        OciDistributionError::ServerError {
+            code,
+            url: String::new(),
+            message: String::new(),
+            diagnostics: Box::default(),
+            context: Box::default(),
+        }
+    }
+
+    #[test]
+    fn test_classification() {
+        assert!(registry_error(OciErrorCode::ManifestUnknown).is_not_found());
+        assert!(registry_error(OciErrorCode::NameUnknown).is_not_found());
+        assert!(server_error(404).is_not_found());
+        assert!(!registry_error(OciErrorCode::Denied).is_not_found());
+
+        assert!(registry_error(OciErrorCode::Denied).is_auth());
+        assert!(server_error(403).is_auth());
+        assert!(OciDistributionError::AuthenticationFailure(String::new()).is_auth());
+        assert!(!server_error(404).is_auth());
+
+        assert!(registry_error(OciErrorCode::Toomanyrequests).is_rate_limited());
+        assert!(server_error(429).is_rate_limited());
+        assert!(!server_error(503).is_rate_limited());
+
+        assert!(registry_error(OciErrorCode::Toomanyrequests).is_retryable());
+        assert!(server_error(503).is_retryable());
+        assert!(
+            OciDistributionError::IoError(std::io::ErrorKind::ConnectionReset.into())
+                .is_retryable()
+        );
+        assert!(!server_error(400).is_retryable());
+        assert!(
+            !registry_error(OciErrorCode::Unknown("QUOTA_EXCEEDED".to_string())).is_retryable()
+        );
+    }
+
+    #[test]
+    fn test_classification_falls_back_to_status() {
+        let unknown = || OciErrorCode::Unknown("QUOTA_EXCEEDED".to_string());
+        assert!(registry_error_with_status(unknown(), 404).is_not_found());
+        assert!(!registry_error_with_status(unknown(), 404).is_rate_limited());
+        assert!(registry_error_with_status(unknown(), 429).is_rate_limited());
+        assert!(registry_error_with_status(unknown(), 429).is_retryable());
+        assert!(registry_error_with_status(unknown(), 403).is_auth());
+
+        // A known code takes precedence over the status
+        assert!(!registry_error_with_status(OciErrorCode::Denied, 404).is_not_found());
+        assert!(!registry_error_with_status(OciErrorCode::Denied, 408).is_retryable());
+    }
 }
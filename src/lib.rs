@@ -5,20 +5,33 @@ use sha2::Digest;
 
 pub mod annotations;
 mod blob;
+pub mod capabilities;
 pub mod client;
 pub mod config;
+pub mod config_source;
+pub mod conformance;
+pub mod diagnostics;
 pub(crate) mod digest;
 pub mod errors;
 pub mod manifest;
+#[cfg(feature = "memory-registry")]
+pub mod memory_registry;
+pub mod quirks;
+mod rate_limit;
 pub mod secrets;
+mod throttle;
 mod token_cache;
 
 #[doc(inline)]
 pub use client::Client;
 #[doc(inline)]
+pub use digest::Digester;
+#[doc(inline)]
 pub use oci_spec::distribution::{ParseError, Reference};
 #[doc(inline)]
 pub use token_cache::RegistryOperation;
+#[doc(inline)]
+pub use token_cache::{FileTokenStore, StoredToken, TokenStore};
 
 /// Computes the SHA256 digest of a byte vector
 pub(crate) fn sha256_digest(bytes: &[u8]) -> String {
@@ -0,0 +1,1126 @@
+//! An in-memory OCI registry, served in-process, for tests
+//!
+//! The registry implements the [OCI distribution
+//! specification](https://github.com/opencontainers/distribution-spec/blob/main/spec.md):
+//! manifests, blobs, chunked and monolithic uploads, cross-repository mounts, tags and
+//! referrers, optionally protected by Basic or Bearer authentication. Everything is kept
+//! in memory and lost when the registry is dropped.
+//!
+//! ```rust
+//! use oci_client::client::{ClientConfig, ClientProtocol};
+//! use oci_client::memory_registry::{MemoryRegistry, MemoryRegistryOptions};
+//! use oci_client::secrets::RegistryAuth;
+//! use oci_client::Client;
+//!
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() {
+//! let registry = MemoryRegistry::start(MemoryRegistryOptions::default())
+//!     .await
+//!     .unwrap();
+//! let client = Client::new(ClientConfig {
+//!     protocol: ClientProtocol::Http,
+//!     ..Default::default()
+//! });
+//!
+//! let image = registry.reference("hello:v1");
+//! let tags = client
+//!     .list_tags(&image, &RegistryAuth::Anonymous, None, None)
+//!     .await;
+//! assert!(tags.is_err(), "the repository is empty");
+//! # }
+//! ```
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use axum::body::{Body, Bytes};
+use axum::extract::{DefaultBodyLimit, Form, Path, Query, State};
+use axum::http::{header, HeaderMap, HeaderValue, Method, StatusCode, Uri};
+use axum::response::Response;
+use axum::routing::get;
+use axum::Router;
+use base64::Engine;
+use serde_json::json;
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+
+use crate::digest::{Digester, DOCKER_DIGEST_HEADER};
+use crate::manifest::OCI_IMAGE_INDEX_MEDIA_TYPE;
+use crate::Reference;
+
+/// The service name of the registry, used by the Bearer authentication
+const SERVICE: &str = "memory-registry";
+
+/// How long the presigned URLs of the blobs are valid, in seconds
+const PRESIGNED_URL_EXPIRY_SECS: u64 = 900;
+
+/// The authentication required by a [`MemoryRegistry`]
+#[derive(Debug, Clone, Default)]
+pub enum MemoryRegistryAuth {
+    /// Anyone can pull and push
+    #[default]
+    Anonymous,
+    /// The requests must carry the given credentials with HTTP Basic authentication
+    Basic {
+        /// The user name
+        username: String,
+        /// The password
+        password: String,
+    },
+    /// The requests must carry a bearer token, obtained from the token endpoint of the
+    /// registry with the given credentials, with either the GET token flow or the OAuth2
+    /// password grant. Tokens only grant the scopes they were requested for.
+    Bearer {
+        /// The user name
+        username: String,
+        /// The password
+        password: String,
+    },
+}
+
+/// Options of a [`MemoryRegistry`]
+#[derive(Debug, Clone, Default)]
+pub struct MemoryRegistryOptions {
+    /// The authentication required by the registry.
+    ///
+    /// This defaults to [`MemoryRegistryAuth::Anonymous`].
+    pub auth: MemoryRegistryAuth,
+
+    /// Answer the pushed manifests without their `Location`, as AWS ECR does
+    pub omit_manifest_location: bool,
+
+    /// Answer the requests to the referrers API with `404 Not Found`, as the registries
+    /// without the API do
+    pub no_referrers_api: bool,
+
+    /// Reject the deletion of manifests by tag
+    pub no_tag_delete: bool,
+
+    /// The minimum size of the chunks of an upload but the last one, advertised with the
+    /// `OCI-Chunk-Min-Length` header. A chunk following a smaller one is rejected.
+    pub min_chunk_size: Option<usize>,
+
+    /// Headers added to all the answers of the registry, e.g. to announce a rate limit
+    pub response_headers: Vec<(String, String)>,
+
+    /// Redirect the downloads of blobs to presigned URLs of a storage valid for 15
+    /// minutes, as the registries backed by an object storage do. The storage is served
+    /// at `localhost:<port>`, a host distinct from the one of the registry, and rejects
+    /// the requests carrying credentials.
+    pub redirect_blobs: bool,
+}
+
+/// An OCI registry keeping its content in memory, listening on a random port of the
+/// loopback interface. It only speaks HTTP, so clients must use
+/// [`ClientProtocol::Http`](crate::client::ClientProtocol::Http) or an exception for
+/// its address.
+///
+/// The registry is stopped when dropped.
+pub struct MemoryRegistry {
+    addr: SocketAddr,
+    state: Arc<ServerState>,
+    handle: JoinHandle<()>,
+}
+
+impl Drop for MemoryRegistry {
+    fn drop(&mut self) {
+        self.handle.abort()
+    }
+}
+
+impl MemoryRegistry {
+    /// Starts a registry within the current tokio runtime
+    pub async fn start(options: MemoryRegistryOptions) -> std::io::Result<Self> {
+        let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0))).await?;
+        let addr = listener.local_addr()?;
+        let state = Arc::new(ServerState {
+            addr,
+            options,
+            store: Mutex::default(),
+            requests: Mutex::default(),
+        });
+
+        let app = Router::new()
+            .route("/token", get(get_token_handler).post(post_token_handler))
+            .route("/_storage/{digest}", get(storage_handler))
+            .fallback(registry_handler)
+            .layer(DefaultBodyLimit::disable())
+            .with_state(state.clone());
+        let handle = tokio::spawn(async move {
+            axum::serve(listener, app).await.unwrap();
+        });
+        Ok(Self {
+            addr,
+            state,
+            handle,
+        })
+    }
+
+    /// The address of the registry, `127.0.0.1:<port>`, to be used as the registry of the
+    /// references
+    pub fn registry(&self) -> String {
+        self.addr.to_string()
+    }
+
+    /// A reference to a repository of the registry, such as `hello:v1` or
+    /// `org/hello@sha256:...`
+    pub fn reference(&self, repository: &str) -> Reference {
+        format!("{}/{repository}", self.addr)
+            .parse()
+            .expect("invalid repository reference")
+    }
+
+    /// The requests received by the registry, as `METHOD path?query`, oldest first
+    pub fn requests(&self) -> Vec<String> {
+        self.state.requests.lock().unwrap().clone()
+    }
+
+    /// The content of the blob with the given digest, if it was uploaded to any repository
+    pub fn blob(&self, digest: &str) -> Option<Bytes> {
+        self.state.store.lock().unwrap().blobs.get(digest).cloned()
+    }
+
+    /// The tags of a repository, sorted
+    pub fn tags(&self, repository: &str) -> Vec<String> {
+        self.state
+            .store
+            .lock()
+            .unwrap()
+            .repositories
+            .get(repository)
+            .map(|repository| repository.tags.keys().cloned().collect())
+            .unwrap_or_default()
+    }
+}
+
+struct ServerState {
+    addr: SocketAddr,
+    options: MemoryRegistryOptions,
+    store: Mutex<Store>,
+    requests: Mutex<Vec<String>>,
+}
+
+#[derive(Default)]
+struct Store {
+    // Digest -> content, shared by all the repositories
+    blobs: HashMap<String, Bytes>,
+    repositories: HashMap<String, Repository>,
+    uploads: HashMap<String, Upload>,
+    next_id: u64,
+    // Token -> granted scopes, as repository -> actions
+    tokens: HashMap<String, HashMap<String, HashSet<String>>>,
+}
+
+#[derive(Default)]
+struct Repository {
+    blobs: HashSet<String>,
+    // Digest -> manifest
+    manifests: HashMap<String, Manifest>,
+    // Tag -> digest
+    tags: BTreeMap<String, String>,
+}
+
+struct Manifest {
+    media_type: String,
+    data: Bytes,
+    subject: Option<String>,
+    artifact_type: Option<String>,
+    annotations: Option<serde_json::Value>,
+}
+
+#[derive(Default)]
+struct Upload {
+    repository: String,
+    data: Vec<u8>,
+    // Whether a chunk smaller than the minimum size was received, which must be the last
+    short_chunk: bool,
+}
+
+/// The API endpoints, parsed from the path of a request
+enum Endpoint {
+    Base,
+    Extensions,
+    Manifest(String, String),
+    Blob(String, String),
+    StartUpload(String),
+    Upload(String, String),
+    Tags(String),
+    Referrers(String, String),
+}
+
+impl Endpoint {
+    fn parse(path: &str) -> Option<Self> {
+        let path = path.strip_prefix("/v2/")?;
+        if path.is_empty() {
+            return Some(Endpoint::Base);
+        }
+        if path == "_oci/ext/discover" {
+            return Some(Endpoint::Extensions);
+        }
+        let segments: Vec<&str> = path.split('/').collect();
+        let name = |len: usize| {
+            let name = segments[..segments.len() - len].join("/");
+            (!name.is_empty()).then_some(name)
+        };
+        let last = |i: usize| segments[segments.len() - i];
+        match segments.len() {
+            n if n >= 4 && last(3) == "blobs" && last(2) == "uploads" => {
+                let name = name(3)?;
+                Some(match last(1) {
+                    "" => Endpoint::StartUpload(name),
+                    id => Endpoint::Upload(name, id.to_string()),
+                })
+            }
+            n if n >= 3 && last(2) == "blobs" && last(1) == "uploads" => {
+                Some(Endpoint::StartUpload(name(2)?))
+            }
+            n if n >= 3 && last(2) == "tags" && last(1) == "list" => Some(Endpoint::Tags(name(2)?)),
+            n if n >= 3 && last(2) == "manifests" => {
+                Some(Endpoint::Manifest(name(2)?, last(1).to_string()))
+            }
+            n if n >= 3 && last(2) == "blobs" => {
+                Some(Endpoint::Blob(name(2)?, last(1).to_string()))
+            }
+            n if n >= 3 && last(2) == "referrers" => {
+                Some(Endpoint::Referrers(name(2)?, last(1).to_string()))
+            }
+            _ => None,
+        }
+    }
+
+    fn repository(&self) -> Option<&str> {
+        match self {
+            Endpoint::Base | Endpoint::Extensions => None,
+            Endpoint::Manifest(name, _)
+            | Endpoint::Blob(name, _)
+            | Endpoint::StartUpload(name)
+            | Endpoint::Upload(name, _)
+            | Endpoint::Tags(name)
+            | Endpoint::Referrers(name, _) => Some(name),
+        }
+    }
+}
+
+async fn registry_handler(
+    State(state): State<Arc<ServerState>>,
+    method: Method,
+    uri: Uri,
+    Query(query): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let mut response = handle_request(&state, method, uri, query, headers, body);
+    let headers = response.headers_mut();
+    headers.insert(
+        "Docker-Distribution-API-Version",
+        HeaderValue::from_static("registry/2.0"),
+    );
+    for (name, value) in &state.options.response_headers {
+        if let (Ok(name), Ok(value)) = (
+            header::HeaderName::try_from(name.as_str()),
+            HeaderValue::try_from(value.as_str()),
+        ) {
+            headers.append(name, value);
+        }
+    }
+    response
+}
+
+/// Serves the blobs from the storage the downloads are redirected to, see
+/// [`MemoryRegistryOptions::redirect_blobs`]
+async fn storage_handler(
+    State(state): State<Arc<ServerState>>,
+    Path(digest): Path<String>,
+    uri: Uri,
+    headers: HeaderMap,
+) -> Response {
+    state.requests.lock().unwrap().push(format!(
+        "GET {}",
+        uri.path_and_query().map(|p| p.as_str()).unwrap_or("/")
+    ));
+    if headers.contains_key(header::AUTHORIZATION) {
+        return error(
+            StatusCode::BAD_REQUEST,
+            "UNSUPPORTED",
+            "presigned URLs don't accept credentials",
+        );
+    }
+    match state.store.lock().unwrap().blobs.get(&digest) {
+        Some(data) => blob_response(&digest, data, &headers),
+        None => error(StatusCode::NOT_FOUND, "BLOB_UNKNOWN", "blob unknown"),
+    }
+}
+
+fn handle_request(
+    state: &ServerState,
+    method: Method,
+    uri: Uri,
+    query: HashMap<String, String>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    state.requests.lock().unwrap().push(format!(
+        "{method} {}",
+        uri.path_and_query().map(|p| p.as_str()).unwrap_or("/")
+    ));
+
+    let Some(endpoint) = Endpoint::parse(uri.path()) else {
+        return error(StatusCode::NOT_FOUND, "NOT_FOUND", "unknown endpoint");
+    };
+    let action = if method == Method::GET || method == Method::HEAD {
+        "pull"
+    } else if method == Method::DELETE {
+        "delete"
+    } else {
+        "push"
+    };
+    let scopes = match state.authorize(&headers, endpoint.repository(), action) {
+        Ok(scopes) => scopes,
+        Err(response) => return response,
+    };
+
+    let options = &state.options;
+    match (&method, &endpoint) {
+        (&Method::GET, Endpoint::Referrers(..)) if options.no_referrers_api => {
+            return error(StatusCode::NOT_FOUND, "NOT_FOUND", "unknown endpoint");
+        }
+        (&Method::DELETE, Endpoint::Manifest(_, reference))
+            if options.no_tag_delete && !reference.contains(':') =>
+        {
+            return error(
+                StatusCode::METHOD_NOT_ALLOWED,
+                "UNSUPPORTED",
+                "tags cannot be deleted",
+            );
+        }
+        _ => {}
+    }
+
+    let mut store = state.store.lock().unwrap();
+    let mut response = match (&method, &endpoint) {
+        (_, Endpoint::Base) => empty(StatusCode::OK),
+        (&Method::GET, Endpoint::Extensions) => json_response(
+            StatusCode::OK,
+            "application/json",
+            json!({
+                "extensions": [{
+                    "name": "_oci",
+                    "description": "Discovery of the extensions of the registry",
+                    "endpoints": ["_oci/ext/discover"],
+                }],
+            }),
+        ),
+        (&Method::GET | &Method::HEAD, Endpoint::Manifest(name, reference)) => {
+            store.get_manifest(name, reference)
+        }
+        (&Method::PUT, Endpoint::Manifest(name, reference)) => {
+            store.put_manifest(name, reference, &headers, body)
+        }
+        (&Method::DELETE, Endpoint::Manifest(name, reference)) => {
+            store.delete_manifest(name, reference)
+        }
+        (&Method::GET | &Method::HEAD, Endpoint::Blob(name, digest)) => {
+            store.get_blob(name, digest, &headers)
+        }
+        (&Method::DELETE, Endpoint::Blob(name, digest)) => store.delete_blob(name, digest),
+        (&Method::POST, Endpoint::StartUpload(name)) => {
+            let can_mount = |from: &str| {
+                scopes
+                    .as_ref()
+                    .is_none_or(|scopes| has_scope(scopes, from, "pull"))
+            };
+            store.start_upload(name, &query, body, can_mount)
+        }
+        (&Method::GET, Endpoint::Upload(name, id)) => store.upload_status(name, id),
+        (&Method::PATCH, Endpoint::Upload(name, id)) => {
+            store.patch_upload(name, id, &headers, body, options.min_chunk_size)
+        }
+        (&Method::PUT, Endpoint::Upload(name, id)) => store.finish_upload(name, id, &query, body),
+        (&Method::DELETE, Endpoint::Upload(name, id)) => store.cancel_upload(name, id),
+        (&Method::GET, Endpoint::Tags(name)) => store.list_tags(name, &query),
+        (&Method::GET, Endpoint::Referrers(name, digest)) => store.referrers(name, digest, &query),
+        _ => error(
+            StatusCode::METHOD_NOT_ALLOWED,
+            "UNSUPPORTED",
+            "unsupported method",
+        ),
+    };
+
+    if let (true, &Method::GET, true, Endpoint::Blob(_, digest)) = (
+        options.redirect_blobs,
+        &method,
+        response.status().is_success(),
+        &endpoint,
+    ) {
+        let date = chrono::Utc::now().format("%Y%m%dT%H%M%SZ");
+        let location = format!(
+            "http://localhost:{port}/_storage/{digest}?X-Amz-Date={date}\
+             &X-Amz-Expires={PRESIGNED_URL_EXPIRY_SECS}&X-Amz-Signature=memory-registry",
+            port = state.addr.port(),
+        );
+        response = Response::builder()
+            .status(StatusCode::TEMPORARY_REDIRECT)
+            .header(header::LOCATION, location)
+            .body(Body::empty())
+            .unwrap();
+    }
+    if options.omit_manifest_location && matches!(endpoint, Endpoint::Manifest(..)) {
+        response.headers_mut().remove(header::LOCATION);
+    }
+    if let (Some(min_chunk_size), Endpoint::StartUpload(_)) = (options.min_chunk_size, &endpoint) {
+        response
+            .headers_mut()
+            .insert("OCI-Chunk-Min-Length", min_chunk_size.into());
+    }
+
+    if method == Method::HEAD {
+        let (parts, _) = response.into_parts();
+        return Response::from_parts(parts, Body::empty());
+    }
+    response
+}
+
+impl ServerState {
+    /// Checks the credentials of a request. Returns the scopes granted by the bearer
+    /// token of the request, if any, or the response rejecting it.
+    #[allow(clippy::result_large_err)]
+    fn authorize(
+        &self,
+        headers: &HeaderMap,
+        repository: Option<&str>,
+        action: &str,
+    ) -> Result<Option<HashMap<String, HashSet<String>>>, Response> {
+        let authorization = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok());
+        match &self.options.auth {
+            MemoryRegistryAuth::Anonymous => Ok(None),
+            MemoryRegistryAuth::Basic { username, password } => {
+                if authorization.and_then(basic_credentials)
+                    == Some((username.clone(), password.clone()))
+                {
+                    Ok(None)
+                } else {
+                    Err(unauthorized(format!("Basic realm=\"{SERVICE}\"")))
+                }
+            }
+            MemoryRegistryAuth::Bearer { .. } => {
+                let scopes = authorization
+                    .and_then(|value| value.strip_prefix("Bearer "))
+                    .and_then(|token| self.store.lock().unwrap().tokens.get(token).cloned());
+                let granted = match (&scopes, repository) {
+                    (Some(_), None) => true,
+                    (Some(scopes), Some(repository)) => has_scope(scopes, repository, action),
+                    (None, _) => false,
+                };
+                if granted {
+                    return Ok(scopes);
+                }
+                let mut challenge = format!(
+                    "Bearer realm=\"http://{}/token\",service=\"{SERVICE}\"",
+                    self.addr
+                );
+                if let Some(repository) = repository {
+                    let actions = match action {
+                        "pull" => "pull".to_string(),
+                        action => format!("pull,{action}"),
+                    };
+                    challenge.push_str(&format!(",scope=\"repository:{repository}:{actions}\""));
+                }
+                Err(unauthorized(challenge))
+            }
+        }
+    }
+
+    /// Issues a token granting the requested scopes, if the credentials are valid
+    fn issue_token(&self, username: &str, password: &str, scopes: &[&str]) -> Response {
+        let MemoryRegistryAuth::Bearer {
+            username: expected_username,
+            password: expected_password,
+        } = &self.options.auth
+        else {
+            return error(StatusCode::NOT_FOUND, "NOT_FOUND", "no token endpoint");
+        };
+        if username != expected_username || password != expected_password {
+            return error(
+                StatusCode::UNAUTHORIZED,
+                "UNAUTHORIZED",
+                "invalid credentials",
+            );
+        }
+
+        let mut granted: HashMap<String, HashSet<String>> = HashMap::new();
+        for scope in scopes.iter().flat_map(|scope| scope.split(' ')) {
+            let mut parts = scope.splitn(3, ':');
+            if let (Some("repository"), Some(repository), Some(actions)) =
+                (parts.next(), parts.next(), parts.next())
+            {
+                granted
+                    .entry(repository.to_string())
+                    .or_default()
+                    .extend(actions.split(',').map(String::from));
+            }
+        }
+
+        let mut store = self.store.lock().unwrap();
+        store.next_id += 1;
+        let token = format!("token-{}", store.next_id);
+        store.tokens.insert(token.clone(), granted);
+        json_response(
+            StatusCode::OK,
+            "application/json",
+            json!({
+                "token": token,
+                "access_token": token,
+                "expires_in": 300,
+                "issued_at": chrono::Utc::now().to_rfc3339(),
+            }),
+        )
+    }
+}
+
+async fn get_token_handler(
+    State(state): State<Arc<ServerState>>,
+    uri: Uri,
+    headers: HeaderMap,
+) -> Response {
+    state.requests.lock().unwrap().push(format!(
+        "GET {}",
+        uri.path_and_query().map(|p| p.as_str()).unwrap_or("/")
+    ));
+    let Some((username, password)) = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(basic_credentials)
+    else {
+        return error(
+            StatusCode::UNAUTHORIZED,
+            "UNAUTHORIZED",
+            "missing credentials",
+        );
+    };
+    let scopes: Vec<String> = reqwest::Url::parse(&format!("http://localhost{uri}"))
+        .map(|url| {
+            url.query_pairs()
+                .filter(|(key, _)| key == "scope")
+                .map(|(_, value)| value.into_owned())
+                .collect()
+        })
+        .unwrap_or_default();
+    let scopes: Vec<&str> = scopes.iter().map(String::as_str).collect();
+    state.issue_token(&username, &password, &scopes)
+}
+
+async fn post_token_handler(
+    State(state): State<Arc<ServerState>>,
+    Form(form): Form<HashMap<String, String>>,
+) -> Response {
+    state
+        .requests
+        .lock()
+        .unwrap()
+        .push("POST /token".to_string());
+    if form.get("grant_type").map(String::as_str) != Some("password") {
+        return error(
+            StatusCode::BAD_REQUEST,
+            "UNSUPPORTED",
+            "unsupported grant type",
+        );
+    }
+    let field = |name: &str| form.get(name).map(String::as_str).unwrap_or_default();
+    let scopes = [field("scope")];
+    state.issue_token(field("username"), field("password"), &scopes)
+}
+
+impl Store {
+    fn repository(&mut self, name: &str) -> &mut Repository {
+        self.repositories.entry(name.to_string()).or_default()
+    }
+
+    fn get_manifest(&self, name: &str, reference: &str) -> Response {
+        let manifest = self.repositories.get(name).and_then(|repository| {
+            let digest = repository
+                .tags
+                .get(reference)
+                .map(String::as_str)
+                .unwrap_or(reference);
+            Some((digest, repository.manifests.get(digest)?))
+        });
+        let Some((digest, manifest)) = manifest else {
+            return error(
+                StatusCode::NOT_FOUND,
+                "MANIFEST_UNKNOWN",
+                "manifest unknown",
+            );
+        };
+        Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, &manifest.media_type)
+            .header(header::CONTENT_LENGTH, manifest.data.len())
+            .header(DOCKER_DIGEST_HEADER, digest)
+            .body(Body::from(manifest.data.clone()))
+            .unwrap()
+    }
+
+    fn put_manifest(
+        &mut self,
+        name: &str,
+        reference: &str,
+        headers: &HeaderMap,
+        body: Bytes,
+    ) -> Response {
+        let algorithm = if reference.contains(':') {
+            reference
+        } else {
+            "sha256:"
+        };
+        let Ok(mut digester) = Digester::new(algorithm) else {
+            return error(
+                StatusCode::BAD_REQUEST,
+                "DIGEST_INVALID",
+                "unsupported digest algorithm",
+            );
+        };
+        digester.update(&body);
+        let digest = digester.finalize();
+        if reference.contains(':') && reference != digest {
+            return error(
+                StatusCode::BAD_REQUEST,
+                "DIGEST_INVALID",
+                "the manifest doesn't match its digest",
+            );
+        }
+        let Ok(value) = serde_json::from_slice::<serde_json::Value>(&body) else {
+            return error(
+                StatusCode::BAD_REQUEST,
+                "MANIFEST_INVALID",
+                "the manifest is not valid JSON",
+            );
+        };
+        let media_type = headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .or_else(|| value["mediaType"].as_str())
+            .unwrap_or(crate::manifest::OCI_IMAGE_MEDIA_TYPE)
+            .to_string();
+        let subject = value["subject"]["digest"].as_str().map(String::from);
+        let artifact_type = value["artifactType"]
+            .as_str()
+            .or_else(|| value["config"]["mediaType"].as_str())
+            .map(String::from);
+        let annotations = value.get("annotations").cloned();
+
+        let repository = self.repository(name);
+        repository.manifests.insert(
+            digest.clone(),
+            Manifest {
+                media_type,
+                data: body,
+                subject: subject.clone(),
+                artifact_type,
+                annotations,
+            },
+        );
+        if !reference.contains(':') {
+            repository
+                .tags
+                .insert(reference.to_string(), digest.clone());
+        }
+
+        let mut response = Response::builder()
+            .status(StatusCode::CREATED)
+            .header(header::LOCATION, format!("/v2/{name}/manifests/{digest}"))
+            .header(DOCKER_DIGEST_HEADER, &digest);
+        if let Some(subject) = subject {
+            response = response.header("OCI-Subject", subject);
+        }
+        response.body(Body::empty()).unwrap()
+    }
+
+    fn delete_manifest(&mut self, name: &str, reference: &str) -> Response {
+        let Some(repository) = self.repositories.get_mut(name) else {
+            return error(
+                StatusCode::NOT_FOUND,
+                "MANIFEST_UNKNOWN",
+                "manifest unknown",
+            );
+        };
+        let deleted = if reference.contains(':') {
+            repository.tags.retain(|_, digest| digest != reference);
+            repository.manifests.remove(reference).is_some()
+        } else {
+            repository.tags.remove(reference).is_some()
+        };
+        if deleted {
+            empty(StatusCode::ACCEPTED)
+        } else {
+            error(
+                StatusCode::NOT_FOUND,
+                "MANIFEST_UNKNOWN",
+                "manifest unknown",
+            )
+        }
+    }
+
+    fn get_blob(&self, name: &str, digest: &str, headers: &HeaderMap) -> Response {
+        let data = self
+            .repositories
+            .get(name)
+            .filter(|repository| repository.blobs.contains(digest))
+            .and_then(|_| self.blobs.get(digest));
+        match data {
+            Some(data) => blob_response(digest, data, headers),
+            None => error(StatusCode::NOT_FOUND, "BLOB_UNKNOWN", "blob unknown"),
+        }
+    }
+
+    fn delete_blob(&mut self, name: &str, digest: &str) -> Response {
+        let removed = self
+            .repositories
+            .get_mut(name)
+            .is_some_and(|repository| repository.blobs.remove(digest));
+        if removed {
+            empty(StatusCode::ACCEPTED)
+        } else {
+            error(StatusCode::NOT_FOUND, "BLOB_UNKNOWN", "blob unknown")
+        }
+    }
+
+    fn start_upload(
+        &mut self,
+        name: &str,
+        query: &HashMap<String, String>,
+        body: Bytes,
+        can_mount: impl Fn(&str) -> bool,
+    ) -> Response {
+        if let (Some(digest), Some(from)) = (query.get("mount"), query.get("from")) {
+            let mountable = can_mount(from)
+                && self
+                    .repositories
+                    .get(from)
+                    .is_some_and(|repository| repository.blobs.contains(digest));
+            if mountable {
+                self.repository(name).blobs.insert(digest.clone());
+                return blob_created(name, digest);
+            }
+        } else if let Some(digest) = query.get("digest") {
+            return self.store_blob(name, digest, body.to_vec());
+        }
+
+        self.next_id += 1;
+        let id = format!("upload-{}", self.next_id);
+        self.uploads.insert(
+            id.clone(),
+            Upload {
+                repository: name.to_string(),
+                data: body.to_vec(),
+                short_chunk: false,
+            },
+        );
+        upload_accepted(name, &id, 0)
+    }
+
+    fn upload(&mut self, name: &str, id: &str) -> Option<&mut Upload> {
+        self.uploads
+            .get_mut(id)
+            .filter(|upload| upload.repository == name)
+    }
+
+    fn upload_status(&mut self, name: &str, id: &str) -> Response {
+        match self.upload(name, id) {
+            Some(upload) => {
+                let len = upload.data.len();
+                let mut response = upload_accepted(name, id, len);
+                *response.status_mut() = StatusCode::NO_CONTENT;
+                response
+            }
+            None => upload_unknown(),
+        }
+    }
+
+    fn patch_upload(
+        &mut self,
+        name: &str,
+        id: &str,
+        headers: &HeaderMap,
+        body: Bytes,
+        min_chunk_size: Option<usize>,
+    ) -> Response {
+        let Some(upload) = self.upload(name, id) else {
+            return upload_unknown();
+        };
+        if upload.short_chunk {
+            return error(
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                "BLOB_UPLOAD_INVALID",
+                "only the last chunk may be smaller than the minimum size",
+            );
+        }
+        let start = headers
+            .get(header::CONTENT_RANGE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|range| range.split_once('-'))
+            .map(|(start, _)| start.parse::<usize>());
+        match start {
+            Some(Ok(start)) if start == upload.data.len() => {}
+            None => {}
+            _ => {
+                let len = upload.data.len();
+                return Response::builder()
+                    .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                    .header(header::LOCATION, format!("/v2/{name}/blobs/uploads/{id}"))
+                    .header(header::RANGE, format!("0-{}", len.saturating_sub(1)))
+                    .body(Body::empty())
+                    .unwrap();
+            }
+        }
+        upload.short_chunk = min_chunk_size.is_some_and(|min| body.len() < min);
+        upload.data.extend_from_slice(&body);
+        let len = upload.data.len();
+        upload_accepted(name, id, len)
+    }
+
+    fn finish_upload(
+        &mut self,
+        name: &str,
+        id: &str,
+        query: &HashMap<String, String>,
+        body: Bytes,
+    ) -> Response {
+        let Some(digest) = query.get("digest") else {
+            return error(StatusCode::BAD_REQUEST, "DIGEST_INVALID", "missing digest");
+        };
+        let Some(mut upload) = self.upload(name, id).map(std::mem::take) else {
+            return upload_unknown();
+        };
+        upload.data.extend_from_slice(&body);
+        self.uploads.remove(id);
+        self.store_blob(name, digest, upload.data)
+    }
+
+    fn cancel_upload(&mut self, name: &str, id: &str) -> Response {
+        if self.upload(name, id).is_none() {
+            return upload_unknown();
+        }
+        self.uploads.remove(id);
+        empty(StatusCode::NO_CONTENT)
+    }
+
+    fn store_blob(&mut self, name: &str, digest: &str, data: Vec<u8>) -> Response {
+        let Ok(mut digester) = Digester::new(digest) else {
+            return error(
+                StatusCode::BAD_REQUEST,
+                "DIGEST_INVALID",
+                "unsupported digest algorithm",
+            );
+        };
+        digester.update(&data);
+        if digester.finalize() != *digest {
+            return error(
+                StatusCode::BAD_REQUEST,
+                "DIGEST_INVALID",
+                "the blob doesn't match its digest",
+            );
+        }
+        self.blobs.insert(digest.to_string(), data.into());
+        self.repository(name).blobs.insert(digest.to_string());
+        blob_created(name, digest)
+    }
+
+    fn list_tags(&self, name: &str, query: &HashMap<String, String>) -> Response {
+        let Some(repository) = self.repositories.get(name) else {
+            return error(
+                StatusCode::NOT_FOUND,
+                "NAME_UNKNOWN",
+                "repository name not known to registry",
+            );
+        };
+        let last = query.get("last");
+        let n = query.get("n").and_then(|n| n.parse::<usize>().ok());
+        let mut tags = repository
+            .tags
+            .keys()
+            .filter(|tag| last.is_none_or(|last| *tag > last));
+        let page: Vec<&String> = match n {
+            Some(n) => tags.by_ref().take(n).collect(),
+            None => tags.by_ref().collect(),
+        };
+
+        let mut response = json_response(
+            StatusCode::OK,
+            "application/json",
+            json!({ "name": name, "tags": page }),
+        );
+        if let (Some(n), Some(last), Some(_)) = (n, page.last(), tags.next()) {
+            let link = format!("</v2/{name}/tags/list?n={n}&last={last}>; rel=\"next\"");
+            response
+                .headers_mut()
+                .insert(header::LINK, link.parse().unwrap());
+        }
+        response
+    }
+
+    fn referrers(&self, name: &str, digest: &str, query: &HashMap<String, String>) -> Response {
+        let artifact_type = query.get("artifactType");
+        let manifests: Vec<serde_json::Value> = self
+            .repositories
+            .get(name)
+            .into_iter()
+            .flat_map(|repository| &repository.manifests)
+            .filter(|(_, manifest)| manifest.subject.as_deref() == Some(digest))
+            .filter(|(_, manifest)| {
+                artifact_type.is_none_or(|t| manifest.artifact_type.as_ref() == Some(t))
+            })
+            .map(|(digest, manifest)| {
+                let mut descriptor = json!({
+                    "mediaType": manifest.media_type,
+                    "digest": digest,
+                    "size": manifest.data.len(),
+                });
+                if let Some(artifact_type) = &manifest.artifact_type {
+                    descriptor["artifactType"] = json!(artifact_type);
+                }
+                if let Some(annotations) = &manifest.annotations {
+                    descriptor["annotations"] = annotations.clone();
+                }
+                descriptor
+            })
+            .collect();
+
+        let mut response = json_response(
+            StatusCode::OK,
+            OCI_IMAGE_INDEX_MEDIA_TYPE,
+            json!({
+                "schemaVersion": 2,
+                "mediaType": OCI_IMAGE_INDEX_MEDIA_TYPE,
+                "manifests": manifests,
+            }),
+        );
+        if artifact_type.is_some() {
+            response
+                .headers_mut()
+                .insert("OCI-Filters-Applied", "artifactType".parse().unwrap());
+        }
+        response
+    }
+}
+
+fn has_scope(scopes: &HashMap<String, HashSet<String>>, repository: &str, action: &str) -> bool {
+    scopes
+        .get(repository)
+        .is_some_and(|actions| actions.contains(action) || actions.contains("*"))
+}
+
+/// Decodes the user name and password of a Basic `Authorization` header
+fn basic_credentials(authorization: &str) -> Option<(String, String)> {
+    let encoded = authorization.strip_prefix("Basic ")?;
+    let decoded = base64::engine::general_purpose::STANDARD
+        .decode(encoded)
+        .ok()?;
+    let (username, password) = std::str::from_utf8(&decoded).ok()?.split_once(':')?;
+    Some((username.to_string(), password.to_string()))
+}
+
+/// The answer to the download of a blob, honoring the `Range` header of the request
+fn blob_response(digest: &str, data: &Bytes, headers: &HeaderMap) -> Response {
+    let range = headers
+        .get(header::RANGE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("bytes="))
+        .and_then(|range| range.split_once('-'));
+    let response = Response::builder()
+        .header(header::CONTENT_TYPE, "application/octet-stream")
+        .header(DOCKER_DIGEST_HEADER, digest);
+    let Some((start, end)) = range else {
+        return response
+            .status(StatusCode::OK)
+            .header(header::CONTENT_LENGTH, data.len())
+            .body(Body::from(data.clone()))
+            .unwrap();
+    };
+    let len = data.len() as u64;
+    let start: u64 = start.parse().unwrap_or(0);
+    let end: u64 = end
+        .parse()
+        .map(|end: u64| end.min(len.saturating_sub(1)))
+        .unwrap_or(len.saturating_sub(1));
+    if start >= len || start > end {
+        return Response::builder()
+            .status(StatusCode::RANGE_NOT_SATISFIABLE)
+            .header(header::CONTENT_RANGE, format!("bytes */{len}"))
+            .body(Body::empty())
+            .unwrap();
+    }
+    response
+        .status(StatusCode::PARTIAL_CONTENT)
+        .header(header::CONTENT_LENGTH, end - start + 1)
+        .header(header::CONTENT_RANGE, format!("bytes {start}-{end}/{len}"))
+        .body(Body::from(data.slice(start as usize..=end as usize)))
+        .unwrap()
+}
+
+fn empty(status: StatusCode) -> Response {
+    Response::builder()
+        .status(status)
+        .body(Body::empty())
+        .unwrap()
+}
+
+fn json_response(status: StatusCode, content_type: &str, body: serde_json::Value) -> Response {
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, content_type)
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+/// A response with the error envelope of the distribution specification
+fn error(status: StatusCode, code: &str, message: &str) -> Response {
+    json_response(
+        status,
+        "application/json",
+        json!({ "errors": [{ "code": code, "message": message, "detail": null }] }),
+    )
+}
+
+fn unauthorized(challenge: String) -> Response {
+    let mut response = error(
+        StatusCode::UNAUTHORIZED,
+        "UNAUTHORIZED",
+        "authentication required",
+    );
+    response
+        .headers_mut()
+        .insert(header::WWW_AUTHENTICATE, challenge.parse().unwrap());
+    response
+}
+
+fn upload_unknown() -> Response {
+    error(
+        StatusCode::NOT_FOUND,
+        "BLOB_UPLOAD_UNKNOWN",
+        "blob upload unknown",
+    )
+}
+
+fn upload_accepted(name: &str, id: &str, len: usize) -> Response {
+    Response::builder()
+        .status(StatusCode::ACCEPTED)
+        .header(header::LOCATION, format!("/v2/{name}/blobs/uploads/{id}"))
+        .header(header::RANGE, format!("0-{}", len.saturating_sub(1)))
+        .header("Docker-Upload-UUID", id)
+        .header(header::CONTENT_LENGTH, 0)
+        .body(Body::empty())
+        .unwrap()
+}
+
+fn blob_created(name: &str, digest: &str) -> Response {
+    Response::builder()
+        .status(StatusCode::CREATED)
+        .header(header::LOCATION, format!("/v2/{name}/blobs/{digest}"))
+        .header(DOCKER_DIGEST_HEADER, digest)
+        .header(header::CONTENT_LENGTH, 0)
+        .body(Body::empty())
+        .unwrap()
+}
@@ -0,0 +1,195 @@
+//! Workarounds for the registries departing from the distribution specification
+//!
+//! Each registry gets a [`RegistryQuirks`] profile, either configured in
+//! [`ClientConfig::registry_quirks`](crate::client::ClientConfig::registry_quirks) or
+//! detected from its host name, telling the client which workarounds to apply.
+
+use crate::manifest::{
+    IMAGE_CONFIG_MEDIA_TYPE, IMAGE_DOCKER_CONFIG_MEDIA_TYPE, IMAGE_DOCKER_LAYER_GZIP_MEDIA_TYPE,
+    IMAGE_DOCKER_LAYER_TAR_MEDIA_TYPE, IMAGE_LAYER_GZIP_MEDIA_TYPE, IMAGE_LAYER_MEDIA_TYPE,
+    IMAGE_MANIFEST_LIST_MEDIA_TYPE, IMAGE_MANIFEST_MEDIA_TYPE, OCI_IMAGE_INDEX_MEDIA_TYPE,
+    OCI_IMAGE_MEDIA_TYPE,
+};
+
+/// Minimum size of the parts of a layer upload to AWS ECR, but the last one
+const ECR_MIN_CHUNK_SIZE: usize = 5 * 1024 * 1024;
+
+/// The behaviors of a registry that the client works around.
+///
+/// The [`Default`] profile enables the workarounds that are harmless with compliant
+/// registries, and that the client has always applied. Other profiles are obtained by
+/// changing the fields of one of the constructors, since new workarounds may be added.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct RegistryQuirks {
+    /// The registry may answer a pushed manifest without its `Location`, as AWS ECR
+    /// does. The URL of the manifest by digest is then returned instead of an error.
+    ///
+    /// This defaults to `true`.
+    pub missing_manifest_location: bool,
+
+    /// The registry may not follow the specification during chunked uploads. Blobs are
+    /// then uploaded again as a whole.
+    ///
+    /// This defaults to `true`.
+    pub monolithic_fallback: bool,
+
+    /// The registry requires a `Content-Length: 0` header on the requests starting and
+    /// closing uploads, which carry no content.
+    ///
+    /// This defaults to `true`.
+    pub empty_upload_content_length: bool,
+
+    /// Blobs are always uploaded as a whole, as with
+    /// [`ClientConfig::use_monolithic_push`](crate::client::ClientConfig::use_monolithic_push).
+    ///
+    /// This defaults to `false`.
+    pub monolithic_push: bool,
+
+    /// The minimum size of the chunks of an upload, but the last one. The size required
+    /// by the registry with the `OCI-Chunk-Min-Length` header is used when larger.
+    ///
+    /// This defaults to `None`.
+    pub min_chunk_size: Option<usize>,
+
+    /// The registry doesn't implement the referrers API. The referrers of a manifest are
+    /// then read from the image index tagged `<algorithm>-<digest>`, as described by the
+    /// referrers tag schema, which the client doesn't update when pushing a manifest
+    /// with a subject.
+    ///
+    /// This defaults to `false`.
+    pub no_referrers_api: bool,
+
+    /// The registry doesn't delete tags. Deleting a manifest by tag then fails without
+    /// trying, the manifest can still be deleted by digest, which removes all its tags.
+    ///
+    /// This defaults to `false`.
+    pub no_tag_delete: bool,
+
+    /// The registry only knows the Docker media types. Manifests are then pulled with
+    /// Docker media types only, and pushed with the Docker media types equivalent to
+    /// their OCI ones.
+    ///
+    /// This defaults to `false`.
+    pub docker_media_types_only: bool,
+}
+
+impl Default for RegistryQuirks {
+    fn default() -> Self {
+        RegistryQuirks {
+            missing_manifest_location: true,
+            monolithic_fallback: true,
+            empty_upload_content_length: true,
+            monolithic_push: false,
+            min_chunk_size: None,
+            no_referrers_api: false,
+            no_tag_delete: false,
+            docker_media_types_only: false,
+        }
+    }
+}
+
+impl RegistryQuirks {
+    /// The profile of a registry following the specification, without any workaround,
+    /// so that any departure from the specification is reported as an error
+    pub fn strict() -> Self {
+        RegistryQuirks {
+            missing_manifest_location: false,
+            monolithic_fallback: false,
+            empty_upload_content_length: false,
+            ..Default::default()
+        }
+    }
+
+    /// The profile of AWS ECR, which requires chunks of at least 5 MiB and doesn't
+    /// return the location of pushed manifests
+    pub fn ecr() -> Self {
+        RegistryQuirks {
+            min_chunk_size: Some(ECR_MIN_CHUNK_SIZE),
+            ..Default::default()
+        }
+    }
+
+    /// The profile of a registry, detected from its host name, such as
+    /// `123456789012.dkr.ecr.us-east-1.amazonaws.com`. Unknown registries get the
+    /// [`Default`] profile.
+    pub fn detect(registry: &str) -> Self {
+        let host = registry.rsplit_once(':').map_or(registry, |(host, _)| host);
+        let is_ecr = host.split('.').nth(1) == Some("dkr")
+            && host.split('.').nth(2) == Some("ecr")
+            && (host.ends_with(".amazonaws.com") || host.ends_with(".amazonaws.com.cn"));
+        if is_ecr {
+            Self::ecr()
+        } else {
+            Self::default()
+        }
+    }
+}
+
+/// The Docker media type equivalent to an OCI one, if any
+pub(crate) fn docker_media_type(media_type: &str) -> &str {
+    match media_type {
+        OCI_IMAGE_MEDIA_TYPE => IMAGE_MANIFEST_MEDIA_TYPE,
+        OCI_IMAGE_INDEX_MEDIA_TYPE => IMAGE_MANIFEST_LIST_MEDIA_TYPE,
+        IMAGE_CONFIG_MEDIA_TYPE => IMAGE_DOCKER_CONFIG_MEDIA_TYPE,
+        IMAGE_LAYER_GZIP_MEDIA_TYPE => IMAGE_DOCKER_LAYER_GZIP_MEDIA_TYPE,
+        IMAGE_LAYER_MEDIA_TYPE => IMAGE_DOCKER_LAYER_TAR_MEDIA_TYPE,
+        media_type => media_type,
+    }
+}
+
+/// The tag of the image index listing the referrers of a manifest, according to the
+/// referrers tag schema
+pub(crate) fn referrers_tag(digest: &str) -> String {
+    digest.replacen(':', "-", 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(
+            RegistryQuirks::detect("123456789012.dkr.ecr.us-east-1.amazonaws.com"),
+            RegistryQuirks::ecr()
+        );
+        assert_eq!(
+            RegistryQuirks::detect("123456789012.dkr.ecr.cn-north-1.amazonaws.com.cn:443"),
+            RegistryQuirks::ecr()
+        );
+        assert_eq!(
+            RegistryQuirks::detect("public.ecr.aws"),
+            RegistryQuirks::default()
+        );
+        assert_eq!(
+            RegistryQuirks::detect("dkr.ecr.example.com"),
+            RegistryQuirks::default()
+        );
+        assert_eq!(
+            RegistryQuirks::detect("localhost:5000"),
+            RegistryQuirks::default()
+        );
+    }
+
+    #[test]
+    fn test_docker_media_type() {
+        assert_eq!(
+            docker_media_type(OCI_IMAGE_MEDIA_TYPE),
+            IMAGE_MANIFEST_MEDIA_TYPE
+        );
+        assert_eq!(
+            docker_media_type(IMAGE_LAYER_GZIP_MEDIA_TYPE),
+            IMAGE_DOCKER_LAYER_GZIP_MEDIA_TYPE
+        );
+        assert_eq!(
+            docker_media_type(crate::manifest::WASM_LAYER_MEDIA_TYPE),
+            crate::manifest::WASM_LAYER_MEDIA_TYPE
+        );
+    }
+
+    #[test]
+    fn test_referrers_tag() {
+        assert_eq!(referrers_tag("sha256:abcd"), "sha256-abcd");
+    }
+}
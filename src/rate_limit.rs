@@ -0,0 +1,414 @@
+//! Limits on the requests sent by a client to each registry
+
+use std::collections::HashMap;
+#[cfg(not(target_arch = "wasm32"))]
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+#[cfg(not(target_arch = "wasm32"))]
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+#[cfg(not(target_arch = "wasm32"))]
+use futures_util::stream::BoxStream;
+use futures_util::Stream;
+use http::StatusCode;
+use reqwest::header::RETRY_AFTER;
+use reqwest::Response;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tracing::debug;
+
+/// Interval between the requests to a registry that has asked to slow down, when no
+/// request rate is configured. It doubles each time the registry asks again.
+const ADAPTIVE_BASE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Maximum number of times the interval between requests is doubled
+const MAX_SLOWDOWN: u32 = 6;
+
+/// Number of successive answers after which the interval between requests is halved
+const RECOVERY_ANSWERS: u32 = 10;
+
+/// Longest pause honoured from a `Retry-After` header
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+/// Limits the requests sent to each registry, shared by the clones of a client
+#[derive(Clone, Default)]
+pub(crate) struct RateLimiter {
+    inner: Arc<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    max_concurrent: Option<usize>,
+    interval: Option<Duration>,
+    adaptive: bool,
+    // Registry -> limits of the registry
+    registries: Mutex<HashMap<String, Arc<RegistryLimiter>>>,
+}
+
+struct RegistryLimiter {
+    requests: Option<Arc<Semaphore>>,
+    pace: Mutex<Pace>,
+}
+
+#[derive(Default)]
+struct Pace {
+    // When the next request may be sent
+    next: Option<Instant>,
+    // The interval between requests is doubled this many times
+    slowdown: u32,
+    // Answers received since the last change of `slowdown`
+    answers: u32,
+}
+
+impl RateLimiter {
+    /// Creates a limiter allowing at most `max_concurrent` requests in flight and
+    /// `requests_per_second` requests per second to each registry. When `adaptive`, the
+    /// requests to a registry are spaced out further each time it answers with
+    /// `429 Too Many Requests`, and brought back to their rate as it recovers.
+    pub(crate) fn new(
+        max_concurrent: Option<usize>,
+        requests_per_second: Option<f64>,
+        adaptive: bool,
+    ) -> Self {
+        let interval = requests_per_second
+            .filter(|rps| *rps > 0.0)
+            .map(|rps| Duration::from_secs_f64(1.0 / rps));
+        // There are no timers to wait for on WebAssembly
+        let (interval, adaptive) = if cfg!(target_arch = "wasm32") {
+            (None, false)
+        } else {
+            (interval, adaptive)
+        };
+        Self {
+            inner: Arc::new(Inner {
+                max_concurrent: max_concurrent.map(|max| max.max(1)),
+                interval,
+                adaptive,
+                registries: Mutex::default(),
+            }),
+        }
+    }
+
+    fn registry(&self, registry: &str) -> Arc<RegistryLimiter> {
+        self.inner
+            .registries
+            .lock()
+            .unwrap()
+            .entry(registry.to_string())
+            .or_insert_with(|| {
+                Arc::new(RegistryLimiter {
+                    requests: self
+                        .inner
+                        .max_concurrent
+                        .map(|max| Arc::new(Semaphore::new(max))),
+                    pace: Mutex::default(),
+                })
+            })
+            .clone()
+    }
+
+    /// Waits until a request can be sent to the registry. The request counts as in
+    /// flight until the returned permit is dropped.
+    pub(crate) async fn acquire(&self, registry: &str) -> Option<OwnedSemaphorePermit> {
+        let limiter = self.registry(registry);
+        let permit = match &limiter.requests {
+            // The semaphore is never closed
+            Some(requests) => requests.clone().acquire_owned().await.ok(),
+            None => None,
+        };
+        if let Some(delay) = limiter.reserve(self.inner.interval) {
+            debug!(%registry, ?delay, "Delaying request to registry");
+            tokio::time::sleep(delay).await;
+        }
+        permit
+    }
+
+    /// Records the answer of the registry to a request
+    pub(crate) fn answered(&self, registry: &str, response: &Response) {
+        if !self.inner.adaptive {
+            return;
+        }
+        if response.status() == StatusCode::TOO_MANY_REQUESTS {
+            self.throttled(registry, retry_after(response));
+        } else {
+            self.registry(registry).recover();
+        }
+    }
+
+    /// Slows down the requests to a registry that asked for it, for at least
+    /// `retry_after` when given
+    pub(crate) fn throttled(&self, registry: &str, retry_after: Option<Duration>) {
+        if !self.inner.adaptive {
+            return;
+        }
+        let limiter = self.registry(registry);
+        let mut pace = limiter.pace.lock().unwrap();
+        pace.slowdown = (pace.slowdown + 1).min(MAX_SLOWDOWN);
+        pace.answers = 0;
+        let pause = retry_after
+            .map(|pause| pause.min(MAX_RETRY_AFTER))
+            .or_else(|| interval(self.inner.interval, pace.slowdown))
+            .unwrap_or_default();
+        let resume = Instant::now() + pause;
+        pace.next = Some(pace.next.map_or(resume, |next| next.max(resume)));
+        debug!(%registry, slowdown = pace.slowdown, ?pause, "Registry asked to slow down");
+    }
+}
+
+impl RegistryLimiter {
+    /// Reserves the next slot to send a request, returning how long to wait for it
+    fn reserve(&self, base: Option<Duration>) -> Option<Duration> {
+        let mut pace = self.pace.lock().unwrap();
+        let interval = interval(base, pace.slowdown);
+        if interval.is_none() && pace.next.is_none() {
+            return None;
+        }
+        let now = Instant::now();
+        let start = pace.next.map_or(now, |next| next.max(now));
+        pace.next = interval.map(|interval| start + interval);
+        Some(start - now).filter(|delay| !delay.is_zero())
+    }
+
+    /// Speeds the requests back up once the registry has answered enough of them
+    fn recover(&self) {
+        let mut pace = self.pace.lock().unwrap();
+        if pace.slowdown == 0 {
+            return;
+        }
+        pace.answers += 1;
+        if pace.answers >= RECOVERY_ANSWERS {
+            pace.slowdown -= 1;
+            pace.answers = 0;
+        }
+    }
+}
+
+/// Keeps a request counted as in flight, carried by the extensions of its answer
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Clone)]
+struct RequestPermit(#[allow(dead_code)] Arc<OwnedSemaphorePermit>);
+
+/// Keeps the request counted as in flight until the body of its answer has been read
+/// with [`body_stream`], or the answer is dropped
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn hold_permit(response: &mut Response, permit: OwnedSemaphorePermit) {
+    response
+        .extensions_mut()
+        .insert(RequestPermit(Arc::new(permit)));
+}
+
+/// The body of an answer, releasing the permit of its request once read or dropped
+#[cfg(not(target_arch = "wasm32"))]
+struct InFlightBody {
+    stream: BoxStream<'static, reqwest::Result<Bytes>>,
+    permit: Option<RequestPermit>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Stream for InFlightBody {
+    type Item = reqwest::Result<Bytes>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let item = self.stream.as_mut().poll_next(cx);
+        if let Poll::Ready(None) = item {
+            self.permit = None;
+        }
+        item
+    }
+}
+
+/// The body of an answer as a stream, during the reading of which the request still
+/// counts as in flight, see [`hold_permit`]
+pub(crate) fn body_stream(
+    #[cfg_attr(target_arch = "wasm32", allow(unused_mut))] mut response: Response,
+) -> impl Stream<Item = reqwest::Result<Bytes>> {
+    match () {
+        #[cfg(not(target_arch = "wasm32"))]
+        () => InFlightBody {
+            permit: response.extensions_mut().remove::<RequestPermit>(),
+            stream: Box::pin(response.bytes_stream()),
+        },
+        #[cfg(target_arch = "wasm32")]
+        () => response.bytes_stream(),
+    }
+}
+
+/// The interval between requests, given the configured one and the slowdown
+fn interval(base: Option<Duration>, slowdown: u32) -> Option<Duration> {
+    match (base, slowdown) {
+        (base, 0) => base,
+        (base, slowdown) => Some(base.unwrap_or(ADAPTIVE_BASE_INTERVAL) * 2u32.pow(slowdown)),
+    }
+}
+
+/// The pause requested by the `Retry-After` header, as seconds or as a date
+fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    (date.with_timezone(&Utc) - Utc::now()).to_std().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interval() {
+        assert_eq!(interval(None, 0), None);
+        assert_eq!(
+            interval(Some(Duration::from_millis(10)), 0),
+            Some(Duration::from_millis(10))
+        );
+        assert_eq!(interval(None, 2), Some(ADAPTIVE_BASE_INTERVAL * 4));
+        assert_eq!(
+            interval(Some(Duration::from_millis(10)), 3),
+            Some(Duration::from_millis(80))
+        );
+    }
+
+    #[test]
+    fn test_unlimited() {
+        let limiter = RateLimiter::new(None, None, true);
+        let registry = limiter.registry("registry.io");
+        assert!(registry.requests.is_none());
+        for _ in 0..10 {
+            assert_eq!(registry.reserve(None), None);
+        }
+    }
+
+    #[test]
+    fn test_requests_per_second() {
+        let limiter = RateLimiter::new(None, Some(10.0), false);
+        let registry = limiter.registry("registry.io");
+        let base = limiter.inner.interval;
+        assert_eq!(base, Some(Duration::from_millis(100)));
+
+        assert_eq!(registry.reserve(base), None);
+        let delay = registry.reserve(base).unwrap();
+        assert!(delay > Duration::from_millis(90), "{delay:?}");
+        let delay = registry.reserve(base).unwrap();
+        assert!(delay > Duration::from_millis(190), "{delay:?}");
+
+        // Registries are limited separately
+        assert_eq!(limiter.registry("other.io").reserve(base), None);
+    }
+
+    #[test]
+    fn test_adaptive_slowdown() {
+        let limiter = RateLimiter::new(None, None, true);
+        limiter.throttled("registry.io", Some(Duration::from_secs(2)));
+        let registry = limiter.registry("registry.io");
+        assert_eq!(registry.pace.lock().unwrap().slowdown, 1);
+        let delay = registry.reserve(None).unwrap();
+        assert!(delay > Duration::from_millis(1900), "{delay:?}");
+        assert_eq!(limiter.registry("other.io").reserve(None), None);
+
+        limiter.throttled("registry.io", None);
+        assert_eq!(registry.pace.lock().unwrap().slowdown, 2);
+        for _ in 0..2 * RECOVERY_ANSWERS {
+            registry.recover();
+        }
+        assert_eq!(registry.pace.lock().unwrap().slowdown, 0);
+    }
+
+    #[test]
+    fn test_not_adaptive() {
+        let limiter = RateLimiter::new(None, None, false);
+        limiter.throttled("registry.io", Some(Duration::from_secs(2)));
+        assert_eq!(limiter.registry("registry.io").reserve(None), None);
+    }
+
+    #[test]
+    fn test_retry_after() {
+        let response = |value: &str| {
+            Response::from(
+                http::Response::builder()
+                    .status(429)
+                    .header(RETRY_AFTER, value)
+                    .body("")
+                    .unwrap(),
+            )
+        };
+        assert_eq!(
+            retry_after(&response("120")),
+            Some(Duration::from_secs(120))
+        );
+        let date = (Utc::now() + chrono::Duration::seconds(30)).to_rfc2822();
+        let pause = retry_after(&response(&date)).unwrap();
+        assert!(pause > Duration::from_secs(25), "{pause:?}");
+        assert_eq!(
+            retry_after(&response("Wed, 21 Oct 2015 07:28:00 GMT")),
+            None
+        );
+        assert_eq!(retry_after(&response("soon")), None);
+    }
+
+    #[tokio::test]
+    async fn test_max_concurrent() {
+        let limiter = RateLimiter::new(Some(2), None, true);
+        let first = limiter.acquire("registry.io").await;
+        let _second = limiter.acquire("registry.io").await;
+        assert!(first.is_some());
+        assert_eq!(
+            limiter
+                .registry("registry.io")
+                .requests
+                .as_ref()
+                .unwrap()
+                .available_permits(),
+            0
+        );
+        // Other registries have their own permits
+        assert!(limiter.acquire("other.io").await.is_some());
+
+        let third =
+            tokio::time::timeout(Duration::from_millis(50), limiter.acquire("registry.io")).await;
+        assert!(third.is_err());
+        drop(first);
+        assert!(limiter.acquire("registry.io").await.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_permit_held_while_reading_body() {
+        use futures_util::StreamExt;
+
+        let limiter = RateLimiter::new(Some(1), None, false);
+        let available = || {
+            limiter
+                .registry("registry.io")
+                .requests
+                .as_ref()
+                .unwrap()
+                .available_permits()
+        };
+        let response = || {
+            let chunks: Vec<std::io::Result<Bytes>> =
+                vec![Ok(Bytes::from("a")), Ok(Bytes::from("b"))];
+            Response::from(http::Response::new(reqwest::Body::wrap_stream(
+                futures_util::stream::iter(chunks),
+            )))
+        };
+
+        let mut answer = response();
+        hold_permit(&mut answer, limiter.acquire("registry.io").await.unwrap());
+        let mut body = Box::pin(body_stream(answer));
+        assert!(body.next().await.is_some());
+        assert_eq!(available(), 0);
+        while body.next().await.is_some() {}
+        assert_eq!(available(), 1);
+
+        // Dropping the body before its end releases the permit too
+        let mut answer = response();
+        hold_permit(&mut answer, limiter.acquire("registry.io").await.unwrap());
+        let mut body = Box::pin(body_stream(answer));
+        assert!(body.next().await.is_some());
+        assert_eq!(available(), 0);
+        drop(body);
+        assert_eq!(available(), 1);
+    }
+}
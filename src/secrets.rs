@@ -1,7 +1,18 @@
 //! Types for working with registry access secrets
+//!
+//! Secrets are never printed: the `Debug` and `Display` implementations of the types
+//! holding them redact them. The copies kept by the client are also wiped from memory
+//! when dropped, and the types implement [`Zeroize`] for callers to do the same.
+
+use std::fmt;
+
+use futures_util::future::BoxFuture;
+use zeroize::Zeroize;
+
+use crate::errors::Result;
 
 /// A method for authenticating to a registry
-#[derive(Eq, PartialEq, Debug, Clone)]
+#[derive(Eq, PartialEq, Clone)]
 pub enum RegistryAuth {
     /// Access the registry anonymously
     Anonymous,
@@ -9,6 +20,94 @@ pub enum RegistryAuth {
     Basic(String, String),
     /// Access the registry using Bearer token authentication
     Bearer(String),
+    /// Access the registry using an OAuth2 refresh token, such as the `identitytoken`
+    /// saved by `docker login`. The token is exchanged for a bearer token at the
+    /// authorization server of the registry.
+    IdentityToken(String),
+}
+
+impl fmt::Debug for RegistryAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RegistryAuth::Anonymous => f.write_str("Anonymous"),
+            RegistryAuth::Basic(username, _) => f
+                .debug_tuple("Basic")
+                .field(username)
+                .field(&"<redacted>")
+                .finish(),
+            RegistryAuth::Bearer(_) => f.debug_tuple("Bearer").field(&"<redacted>").finish(),
+            RegistryAuth::IdentityToken(_) => {
+                f.debug_tuple("IdentityToken").field(&"<redacted>").finish()
+            }
+        }
+    }
+}
+
+impl fmt::Display for RegistryAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RegistryAuth::Anonymous => f.write_str("anonymous"),
+            RegistryAuth::Basic(username, _) => write!(f, "basic authentication as {username}"),
+            RegistryAuth::Bearer(_) => f.write_str("bearer token"),
+            RegistryAuth::IdentityToken(_) => f.write_str("identity token"),
+        }
+    }
+}
+
+impl Zeroize for RegistryAuth {
+    fn zeroize(&mut self) {
+        match self {
+            RegistryAuth::Anonymous => {}
+            RegistryAuth::Basic(_, password) => password.zeroize(),
+            RegistryAuth::Bearer(token) | RegistryAuth::IdentityToken(token) => token.zeroize(),
+        }
+    }
+}
+
+/// The user name sent alongside an identity token when the authorization server doesn't
+/// support the OAuth2 flow, following the convention used by `docker login`.
+pub(crate) const IDENTITY_TOKEN_USERNAME: &str = "<token>";
+
+/// A source of credentials, queried by the [`Client`](crate::Client) when it needs to
+/// authenticate against a repository.
+///
+/// This allows short-lived credentials to be rotated without re-creating the client.
+/// The provider is called lazily, each time a new token has to be obtained for a
+/// repository the client has no credentials for, other than anonymous ones, and
+/// again when the registry rejects the credentials in use.
+///
+/// ```rust
+/// use futures_util::future::BoxFuture;
+/// use oci_client::errors::Result;
+/// use oci_client::secrets::{CredentialProvider, RegistryAuth};
+///
+/// struct EnvProvider;
+///
+/// impl CredentialProvider for EnvProvider {
+///     fn credentials<'a>(
+///         &'a self,
+///         _registry: &'a str,
+///         _repository: &'a str,
+///     ) -> BoxFuture<'a, Result<Option<RegistryAuth>>> {
+///         Box::pin(async move { Ok(std::env::var("REGISTRY_TOKEN").ok().map(RegistryAuth::Bearer)) })
+///     }
+/// }
+/// ```
+pub trait CredentialProvider: Send + Sync {
+    /// Returns the credentials to use for the given registry and repository.
+    ///
+    /// Returning `None` makes the client fall back to the credentials it has stored for
+    /// the registry, if any.
+    fn credentials<'a>(
+        &'a self,
+        registry: &'a str,
+        repository: &'a str,
+    ) -> BoxFuture<'a, Result<Option<RegistryAuth>>>;
+
+    /// Called when the credentials previously returned for the given registry and
+    /// repository have been rejected, right before new ones are requested. Providers
+    /// caching credentials should drop them.
+    fn credentials_rejected(&self, _registry: &str, _repository: &str) {}
 }
 
 pub(crate) trait Authenticable {
@@ -21,6 +120,44 @@ impl Authenticable for reqwest::RequestBuilder {
             RegistryAuth::Anonymous => self,
             RegistryAuth::Basic(username, password) => self.basic_auth(username, Some(password)),
             RegistryAuth::Bearer(token) => self.bearer_auth(token),
+            RegistryAuth::IdentityToken(token) => {
+                self.basic_auth(IDENTITY_TOKEN_USERNAME, Some(token))
+            }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_secrets_are_redacted() {
+        let auths = [
+            RegistryAuth::Basic("user".to_string(), "hunter2".to_string()),
+            RegistryAuth::Bearer("hunter2".to_string()),
+            RegistryAuth::IdentityToken("hunter2".to_string()),
+        ];
+        for auth in &auths {
+            for printed in [format!("{auth:?}"), format!("{auth:#?}"), auth.to_string()] {
+                assert!(!printed.contains("hunter2"), "{printed}");
+            }
+        }
+        assert_eq!(format!("{:?}", auths[0]), r#"Basic("user", "<redacted>")"#);
+        assert_eq!(auths[0].to_string(), "basic authentication as user");
+    }
+
+    #[test]
+    fn test_secrets_can_be_moved_out_and_zeroized() {
+        let RegistryAuth::Basic(_, password) =
+            RegistryAuth::Basic("user".to_string(), "hunter2".to_string())
+        else {
+            unreachable!()
+        };
+        assert_eq!(password, "hunter2");
+
+        let mut auth = RegistryAuth::Bearer("hunter2".to_string());
+        auth.zeroize();
+        assert_eq!(auth, RegistryAuth::Bearer(String::new()));
+    }
+}
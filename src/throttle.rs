@@ -0,0 +1,187 @@
+//! Caps on the bandwidth used by the blob transfers of a client
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures_util::{Stream, TryStreamExt};
+
+/// Size of the slices in which the bodies of throttled uploads are sent
+#[cfg(not(target_arch = "wasm32"))]
+const UPLOAD_SLICE_SIZE: usize = 16 * 1024;
+
+/// A cap on the number of bytes transferred per second, shared by the transfers it
+/// applies to
+struct Bandwidth {
+    bytes_per_second: f64,
+    // When the bytes reserved so far have been transferred at the capped rate
+    next: Mutex<Option<Instant>>,
+}
+
+impl Bandwidth {
+    fn new(bytes_per_second: u64) -> Arc<Self> {
+        Arc::new(Bandwidth {
+            bytes_per_second: bytes_per_second.max(1) as f64,
+            next: Mutex::default(),
+        })
+    }
+
+    /// Reserves the transfer of `bytes`, returning how long to wait before it may go on
+    fn reserve(&self, bytes: usize) -> Duration {
+        let mut next = self.next.lock().unwrap();
+        let now = Instant::now();
+        let start = next.map_or(now, |next| next.max(now));
+        *next = Some(start + Duration::from_secs_f64(bytes as f64 / self.bytes_per_second));
+        start - now
+    }
+}
+
+/// The bandwidth caps of a client, global and per registry
+#[derive(Clone, Default)]
+pub(crate) struct Bandwidths {
+    global: Option<Arc<Bandwidth>>,
+    // Registry -> cap of the registry
+    by_registry: Arc<HashMap<String, Arc<Bandwidth>>>,
+}
+
+impl Bandwidths {
+    /// Creates the caps on the bytes transferred per second with all the registries
+    /// combined, and with each of the given registries
+    pub(crate) fn new(global: Option<u64>, by_registry: &HashMap<String, u64>) -> Self {
+        // There are no timers to wait for on WebAssembly
+        if cfg!(target_arch = "wasm32") {
+            return Self::default();
+        }
+        Bandwidths {
+            global: global.map(Bandwidth::new),
+            by_registry: Arc::new(
+                by_registry
+                    .iter()
+                    .map(|(registry, limit)| (registry.clone(), Bandwidth::new(*limit)))
+                    .collect(),
+            ),
+        }
+    }
+
+    /// The throttle of the transfers with the registry, if they are capped.
+    ///
+    /// Registries are matched with their port, and then without it.
+    pub(crate) fn for_registry(&self, registry: &str) -> Option<Throttle> {
+        let by_registry = self.by_registry.get(registry).or_else(|| {
+            let (host, _) = registry.rsplit_once(':')?;
+            self.by_registry.get(host)
+        });
+        let caps = self
+            .global
+            .iter()
+            .chain(by_registry)
+            .cloned()
+            .collect::<Vec<_>>();
+        (!caps.is_empty()).then_some(Throttle { caps })
+    }
+}
+
+/// Throttles transfers so that they stay within all the bandwidth caps that apply
+#[derive(Clone)]
+pub(crate) struct Throttle {
+    caps: Vec<Arc<Bandwidth>>,
+}
+
+impl Throttle {
+    /// Waits until `bytes` may be transferred
+    async fn consume(&self, bytes: usize) {
+        let delay = self
+            .caps
+            .iter()
+            .map(|cap| cap.reserve(bytes))
+            .max()
+            .unwrap_or_default();
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    /// Throttles a stream of bytes, holding each chunk back until it may be passed on.
+    /// The chunks themselves are passed on unchanged.
+    pub(crate) fn stream<S, E>(self, stream: S) -> impl Stream<Item = Result<bytes::Bytes, E>>
+    where
+        S: Stream<Item = Result<bytes::Bytes, E>>,
+    {
+        stream.and_then(move |bytes| {
+            let throttle = self.clone();
+            async move {
+                throttle.consume(bytes.len()).await;
+                Ok(bytes)
+            }
+        })
+    }
+
+    /// A request body sending `data` in slices, as fast as the throttle allows
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) fn body(self, data: bytes::Bytes) -> reqwest::Body {
+        let slices = (0..data.len())
+            .step_by(UPLOAD_SLICE_SIZE)
+            .map(move |start| {
+                Ok::<_, std::io::Error>(
+                    data.slice(start..data.len().min(start + UPLOAD_SLICE_SIZE)),
+                )
+            });
+        reqwest::Body::wrap_stream(self.stream(futures_util::stream::iter(slices)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures_util::StreamExt;
+
+    #[test]
+    fn test_for_registry() {
+        let by_registry = HashMap::from([
+            ("registry.io".to_string(), 100),
+            ("localhost:5000".to_string(), 100),
+        ]);
+        let bandwidths = Bandwidths::new(None, &by_registry);
+        assert!(bandwidths.for_registry("registry.io").is_some());
+        assert!(bandwidths.for_registry("registry.io:443").is_some());
+        assert!(bandwidths.for_registry("localhost:5000").is_some());
+        assert!(bandwidths.for_registry("localhost:5001").is_none());
+        assert!(bandwidths.for_registry("other.io").is_none());
+
+        let bandwidths = Bandwidths::new(Some(100), &by_registry);
+        assert_eq!(
+            bandwidths.for_registry("registry.io").unwrap().caps.len(),
+            2
+        );
+        assert_eq!(bandwidths.for_registry("other.io").unwrap().caps.len(), 1);
+    }
+
+    #[test]
+    fn test_reserve() {
+        let bandwidth = Bandwidth::new(1000);
+        assert_eq!(bandwidth.reserve(500), Duration::ZERO);
+        let delay = bandwidth.reserve(500);
+        assert!(delay > Duration::from_millis(450), "{delay:?}");
+        let delay = bandwidth.reserve(10);
+        assert!(delay > Duration::from_millis(950), "{delay:?}");
+    }
+
+    #[tokio::test]
+    async fn test_stream() {
+        let throttle = Bandwidths::new(Some(10_000), &HashMap::new())
+            .for_registry("registry.io")
+            .unwrap();
+        let chunks = (0..4).map(|_| Ok::<_, std::io::Error>(bytes::Bytes::from(vec![1; 1000])));
+
+        let start = Instant::now();
+        let received = throttle
+            .stream(futures_util::stream::iter(chunks))
+            .map(|chunk| chunk.unwrap().len())
+            .collect::<Vec<_>>()
+            .await;
+
+        assert_eq!(received, vec![1000; 4]);
+        assert!(start.elapsed() >= Duration::from_millis(300));
+    }
+}
@@ -1,62 +1,177 @@
+use chrono::{DateTime, Utc};
 use oci_spec::distribution::Reference;
-use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer, Serialize};
 use std::collections::BTreeMap;
 use std::fmt;
-use std::sync::Arc;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Duration;
 use std::time::{SystemTime, UNIX_EPOCH};
-use tokio::sync::RwLock;
+use tokio::sync::{Mutex as AsyncMutex, OnceCell, OwnedMutexGuard, RwLock};
 use tracing::{debug, warn};
+use zeroize::{Zeroize, Zeroizing};
 
-/// A token granted during the OAuth2-like workflow for OCI registries.
+use crate::errors::Result;
+
+/// The value of a token granted during the OAuth2-like workflow for OCI registries.
 #[derive(Deserialize, Clone)]
 #[serde(untagged)]
 #[serde(rename_all = "snake_case")]
-pub(crate) enum RegistryToken {
+enum TokenValue {
     Token { token: String },
     AccessToken { access_token: String },
 }
 
+impl Drop for TokenValue {
+    fn drop(&mut self) {
+        match self {
+            TokenValue::Token { token } => token.zeroize(),
+            TokenValue::AccessToken { access_token } => access_token.zeroize(),
+        }
+    }
+}
+
+/// A token granted during the OAuth2-like workflow for OCI registries.
+///
+/// See https://distribution.github.io/distribution/spec/auth/token/#token-response-fields
+#[derive(Deserialize, Clone)]
+pub(crate) struct RegistryToken {
+    #[serde(flatten)]
+    value: TokenValue,
+    /// The number of seconds the token remains valid, since `issued_at`
+    #[serde(default, deserialize_with = "deserialize_lenient")]
+    expires_in: Option<u64>,
+    /// When the token was issued
+    #[serde(default, deserialize_with = "deserialize_lenient")]
+    issued_at: Option<DateTime<Utc>>,
+    /// A token that can be exchanged for new tokens once this one expires
+    #[serde(default, deserialize_with = "deserialize_lenient")]
+    refresh_token: Option<String>,
+}
+
+/// Ignores malformed optional fields rather than rejecting the whole token response.
+fn deserialize_lenient<'de, D, T>(deserializer: D) -> std::result::Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: DeserializeOwned,
+{
+    let value = serde_json::Value::deserialize(deserializer)?;
+    Ok(serde_json::from_value(value).ok())
+}
+
+impl Drop for RegistryToken {
+    fn drop(&mut self) {
+        self.refresh_token.zeroize();
+    }
+}
+
 impl fmt::Debug for RegistryToken {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let redacted = String::from("<redacted>");
-        match self {
-            RegistryToken::Token { .. } => {
-                f.debug_struct("Token").field("token", &redacted).finish()
+        let mut f = match self.value {
+            TokenValue::Token { .. } => {
+                let mut f = f.debug_struct("Token");
+                f.field("token", &redacted);
+                f
             }
-            RegistryToken::AccessToken { .. } => f
-                .debug_struct("AccessToken")
-                .field("access_token", &redacted)
-                .finish(),
-        }
+            TokenValue::AccessToken { .. } => {
+                let mut f = f.debug_struct("AccessToken");
+                f.field("access_token", &redacted);
+                f
+            }
+        };
+        f.field("expires_in", &self.expires_in)
+            .field("issued_at", &self.issued_at)
+            .field(
+                "refresh_token",
+                &self.refresh_token.as_ref().map(|_| &redacted),
+            )
+            .finish()
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 pub(crate) enum RegistryTokenType {
     Bearer(RegistryToken),
     Basic(String, String),
 }
 
+impl fmt::Debug for RegistryTokenType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RegistryTokenType::Bearer(token) => f.debug_tuple("Bearer").field(token).finish(),
+            RegistryTokenType::Basic(username, _) => f
+                .debug_tuple("Basic")
+                .field(username)
+                .field(&"<redacted>")
+                .finish(),
+        }
+    }
+}
+
+impl Drop for RegistryTokenType {
+    fn drop(&mut self) {
+        if let RegistryTokenType::Basic(_, password) = self {
+            password.zeroize();
+        }
+    }
+}
+
 impl RegistryToken {
+    /// Creates a token without any expiration or refresh token.
+    pub fn new(token: String) -> Self {
+        RegistryToken {
+            value: TokenValue::Token { token },
+            expires_in: None,
+            issued_at: None,
+            refresh_token: None,
+        }
+    }
+
     pub fn bearer_token(&self) -> String {
         format!("Bearer {}", self.token())
     }
 
     pub fn token(&self) -> &str {
-        match self {
-            RegistryToken::Token { token } => token,
-            RegistryToken::AccessToken { access_token } => access_token,
+        match &self.value {
+            TokenValue::Token { token } => token,
+            TokenValue::AccessToken { access_token } => access_token,
         }
     }
+
+    /// The expiration stated by the `expires_in` field of the token response, in seconds
+    /// since the Unix epoch.
+    ///
+    /// The lifetime starts at `issued_at`, or at `now` when it is missing or lies in
+    /// the future because of a clock skew.
+    pub(crate) fn stated_expiration(&self, now: u64) -> Option<u64> {
+        let expires_in = self.expires_in?;
+        let issued_at = self
+            .issued_at
+            .and_then(|t| u64::try_from(t.timestamp()).ok())
+            .map_or(now, |t| t.min(now));
+        Some(issued_at.saturating_add(expires_in))
+    }
 }
 
 /// Desired operation for registry authentication
-#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+///
+/// More operations may be added, so matches must have a wildcard arm.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[non_exhaustive]
 pub enum RegistryOperation {
     /// Authenticate for push operations
     Push,
     /// Authenticate for pull operations
     Pull,
+    /// Authenticate for delete operations
+    Delete,
 }
 
 #[derive(Debug, Deserialize)]
@@ -69,84 +184,696 @@ struct TokenCacheKey {
     registry: String,
     repository: String,
     operation: RegistryOperation,
+    /// Other repositories of the registry the token grants pull access to, sorted
+    sources: Vec<String>,
+}
+
+impl TokenCacheKey {
+    fn new(reference: &Reference, operation: RegistryOperation, sources: &[String]) -> Self {
+        let mut sources = sources.to_vec();
+        sources.sort();
+        sources.dedup();
+        TokenCacheKey {
+            registry: reference.resolve_registry().to_string(),
+            repository: reference.repository().to_string(),
+            operation,
+            sources,
+        }
+    }
+
+    /// Whether the token grants access to the given repository
+    fn covers(&self, registry: &str, repository: &str) -> bool {
+        self.registry == registry
+            && (self.repository == repository || self.sources.iter().any(|s| s == repository))
+    }
 }
 
 struct TokenCacheValue {
     token: RegistryTokenType,
     expiration: u64,
+    /// When the token should be renewed, in seconds since the Unix epoch
+    refresh_at: u64,
+    /// Logical time of the last use of the token
+    last_used: AtomicU64,
+}
+
+/// A bearer token persisted by a [`TokenStore`]
+#[derive(Clone, Serialize, Deserialize)]
+pub struct StoredToken {
+    /// The registry the token has been issued for
+    pub registry: String,
+    /// The repository the token has been issued for
+    pub repository: String,
+    /// The operation the token grants access to
+    pub operation: RegistryOperation,
+    /// Other repositories of the registry the token grants pull access to, such as
+    /// the source of a cross-repository blob mount
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sources: Vec<String>,
+    /// The bearer token
+    pub token: String,
+    /// Expiration of the token, in seconds since the Unix epoch
+    pub expiration: u64,
+}
+
+/// Wipes the bearer token, such as before dropping a token read out of a store
+impl Zeroize for StoredToken {
+    fn zeroize(&mut self) {
+        self.token.zeroize();
+    }
+}
+
+impl fmt::Debug for StoredToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StoredToken")
+            .field("registry", &self.registry)
+            .field("repository", &self.repository)
+            .field("operation", &self.operation)
+            .field("sources", &self.sources)
+            .field("token", &"<redacted>")
+            .field("expiration", &self.expiration)
+            .finish()
+    }
+}
+
+/// A persistent backend for the bearer tokens obtained by a [`Client`](crate::Client).
+///
+/// When a store is set on the [`ClientConfig`](crate::client::ClientConfig), tokens are
+/// loaded from it once, the first time the client needs a token, and each newly obtained
+/// bearer token is written to it. This allows tokens to be reused by the next clients, even across
+/// processes.
+///
+/// The methods of the store may block, they are called outside of the async runtime
+/// worker threads. HTTP Basic credentials are never handed to the store.
+pub trait TokenStore: Send + Sync {
+    /// Returns all the tokens held by the store.
+    ///
+    /// Expired tokens may be returned, they are discarded by the client.
+    fn load(&self) -> Result<Vec<StoredToken>>;
+
+    /// Saves a token, replacing any token previously held for the same registry,
+    /// repository, operation and sources.
+    fn store(&self, token: StoredToken) -> Result<()>;
+
+    /// Removes the tokens granting access to the given registry and repository, for all
+    /// the operations, including the ones for which it is one of the sources.
+    fn remove(&self, registry: &str, repository: &str) -> Result<()>;
+
+    /// Removes the tokens granting access to the repositories of the registry whose path
+    /// starts with the given prefix, or to all of them when the prefix is empty.
+    ///
+    /// The default implementation calls [`TokenStore::remove`] for each of the
+    /// matching repositories of the tokens returned by [`TokenStore::load`].
+    fn remove_prefix(&self, registry: &str, prefix: &str) -> Result<()> {
+        let mut repositories: Vec<String> = Zeroizing::new(self.load()?)
+            .iter()
+            .filter(|t| t.registry == registry)
+            .flat_map(|t| std::iter::once(&t.repository).chain(&t.sources))
+            .filter(|repository| under_prefix(repository, prefix))
+            .cloned()
+            .collect();
+        repositories.sort();
+        repositories.dedup();
+        repositories
+            .iter()
+            .try_for_each(|repository| self.remove(registry, repository))
+    }
+}
+
+/// Whether the repository path is the given prefix or lies under it. All the paths are
+/// under the empty prefix.
+fn under_prefix(repository: &str, prefix: &str) -> bool {
+    prefix.is_empty()
+        || repository
+            .strip_prefix(prefix)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// A [`TokenStore`] keeping the tokens inside of a JSON file.
+///
+/// The file is locked while being read or written, so it can be shared by several
+/// processes, on Unix and Windows systems. On Unix systems it is only readable and
+/// writable by its owner.
+#[derive(Debug, Clone)]
+pub struct FileTokenStore {
+    path: PathBuf,
+}
+
+impl FileTokenStore {
+    /// Creates a store backed by the file at the given path.
+    ///
+    /// The file and its parent directories are created when the first token is saved.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        FileTokenStore { path: path.into() }
+    }
+
+    /// The path of the file backing this store
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn open(&self, create: bool) -> std::io::Result<File> {
+        let mut options = OpenOptions::new();
+        options.read(true).write(create).create(create);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        options.open(&self.path)
+    }
+
+    /// Applies the given change to the content of the file, while holding an exclusive
+    /// lock on it. Expired tokens are dropped along the way.
+    fn update(&self, change: impl FnOnce(&mut Vec<StoredToken>)) -> Result<()> {
+        self.create_parent_dir()?;
+        let mut file = self.open(true)?;
+        lock_file(&file, true)?;
+        #[cfg(unix)]
+        {
+            // The file might have been created by someone else with a broader mode
+            use std::os::unix::fs::PermissionsExt;
+            file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+        }
+
+        let now = now_epoch_secs();
+        let mut tokens = Zeroizing::new(read_stored_tokens(&mut file).unwrap_or_else(|error| {
+            warn!(?error, path = ?self.path, "Discarding unreadable token store content");
+            Vec::new()
+        }));
+        tokens.retain(|t| t.expiration >= now);
+        change(&mut tokens);
+
+        file.set_len(0)?;
+        file.seek(SeekFrom::Start(0))?;
+        file.write_all(&Zeroizing::new(serde_json::to_vec(&*tokens)?))?;
+        file.sync_all()?;
+        Ok(())
+    }
+
+    fn create_parent_dir(&self) -> std::io::Result<()> {
+        let parent = match self.path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => return Ok(()),
+        };
+        let mut builder = std::fs::DirBuilder::new();
+        builder.recursive(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::DirBuilderExt;
+            builder.mode(0o700);
+        }
+        builder.create(parent)
+    }
+}
+
+/// Locks the file until it is closed, for writing when `exclusive`. Files are left
+/// unlocked on the systems other than Unix and Windows.
+fn lock_file(file: &File, exclusive: bool) -> std::io::Result<()> {
+    match () {
+        #[cfg(any(unix, windows))]
+        () => {
+            use fs4::fs_std::FileExt;
+            if exclusive {
+                FileExt::lock_exclusive(file)
+            } else {
+                FileExt::lock_shared(file)
+            }
+        }
+        #[cfg(not(any(unix, windows)))]
+        () => {
+            let _ = (file, exclusive);
+            Ok(())
+        }
+    }
+}
+
+fn read_stored_tokens(file: &mut File) -> Result<Vec<StoredToken>> {
+    let mut content = Zeroizing::new(String::new());
+    file.read_to_string(&mut content)?;
+    if content.trim().is_empty() {
+        return Ok(Vec::new());
+    }
+    Ok(serde_json::from_str(&content)?)
+}
+
+impl TokenStore for FileTokenStore {
+    fn load(&self) -> Result<Vec<StoredToken>> {
+        let mut file = match self.open(false) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        lock_file(&file, false)?;
+        read_stored_tokens(&mut file)
+    }
+
+    fn store(&self, token: StoredToken) -> Result<()> {
+        self.update(|tokens| {
+            tokens.retain(|t| {
+                !(t.registry == token.registry
+                    && t.repository == token.repository
+                    && t.operation == token.operation
+                    && t.sources == token.sources)
+            });
+            tokens.push(token);
+        })
+    }
+
+    fn remove(&self, registry: &str, repository: &str) -> Result<()> {
+        self.update(|tokens| {
+            tokens.retain(|t| {
+                !(t.registry == registry
+                    && (t.repository == repository || t.sources.iter().any(|s| s == repository)))
+            });
+        })
+    }
+
+    fn remove_prefix(&self, registry: &str, prefix: &str) -> Result<()> {
+        self.update(|tokens| {
+            tokens.retain(|t| {
+                !(t.registry == registry
+                    && std::iter::once(&t.repository)
+                        .chain(&t.sources)
+                        .any(|r| under_prefix(r, prefix)))
+            });
+        })
+    }
 }
 
 #[derive(Clone)]
 pub(crate) struct TokenCache {
     // (registry, repository, scope) -> (token, expiration)
-    tokens: Arc<RwLock<BTreeMap<TokenCacheKey, TokenCacheValue>>>,
+    tokens: Arc<TokenMap>,
     /// Default token expiration in seconds, to use when claim doesn't specify a value
     pub default_expiration_secs: usize,
+    /// Number of seconds before their expiration at which tokens are renewed
+    refresh_margin_secs: usize,
+    /// Maximum number of entries, the least recently used ones are evicted first
+    max_entries: Option<usize>,
+    /// Logical clock used to track the last use of the entries
+    clock: Arc<AtomicU64>,
+    /// Optional persistent backend
+    store: Option<Arc<dyn TokenStore>>,
+    /// Set once the tokens of the store have been loaded
+    loaded: Arc<OnceCell<()>>,
+    /// Locks held while fetching a token, so concurrent fetches of the same token are
+    /// performed only once
+    in_flight: Arc<Mutex<BTreeMap<TokenCacheKey, Arc<AsyncMutex<()>>>>>,
+    /// The tokens of this cache and of its scoped caches, pruned by the same task, see
+    /// [`TokenCache::spawn_pruning`]
+    pruned: Arc<Mutex<Vec<Weak<TokenMap>>>>,
+}
+
+type TokenMap = RwLock<BTreeMap<TokenCacheKey, TokenCacheValue>>;
+
+/// Held while fetching a token, see [`TokenCache::lock`].
+pub(crate) struct TokenFetchGuard {
+    key: TokenCacheKey,
+    in_flight: Arc<Mutex<BTreeMap<TokenCacheKey, Arc<AsyncMutex<()>>>>>,
+    guard: Option<OwnedMutexGuard<()>>,
+}
+
+impl Drop for TokenFetchGuard {
+    fn drop(&mut self) {
+        let mut in_flight = self.in_flight.lock().unwrap();
+        // Release the lock before checking whether other tasks are waiting for it
+        drop(self.guard.take());
+        if let Some(lock) = in_flight.get(&self.key) {
+            if Arc::strong_count(lock) == 1 {
+                in_flight.remove(&self.key);
+            }
+        }
+    }
 }
 
 impl TokenCache {
     pub(crate) fn new(default_expiration_secs: usize) -> Self {
+        let tokens = Arc::new(RwLock::new(BTreeMap::new()));
         TokenCache {
-            tokens: Arc::new(RwLock::new(BTreeMap::new())),
+            pruned: Arc::new(Mutex::new(vec![Arc::downgrade(&tokens)])),
+            tokens,
             default_expiration_secs,
+            refresh_margin_secs: 0,
+            max_entries: None,
+            clock: Arc::new(AtomicU64::new(0)),
+            store: None,
+            loaded: Arc::default(),
+            in_flight: Arc::default(),
+        }
+    }
+
+    /// Renews tokens the given number of seconds before they expire.
+    ///
+    /// The margin is capped to half of the lifetime of each token, so short-lived
+    /// tokens are still used.
+    pub(crate) fn with_refresh_margin(mut self, refresh_margin_secs: usize) -> Self {
+        self.refresh_margin_secs = refresh_margin_secs;
+        self
+    }
+
+    /// Bounds the number of cached tokens.
+    pub(crate) fn with_max_entries(mut self, max_entries: Option<usize>) -> Self {
+        self.max_entries = max_entries;
+        self
+    }
+
+    /// Backs the cache with the given store, whose valid tokens are loaded into the cache
+    /// the first time it is used.
+    ///
+    /// The store is only read then, the cache is the reference afterwards.
+    pub(crate) fn with_store(mut self, store: Arc<dyn TokenStore>) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    /// An empty cache with the same settings and without store, whose expired tokens are
+    /// removed by the task pruning this cache, if any.
+    pub(crate) fn scoped(&self) -> Self {
+        let tokens = Arc::new(RwLock::new(BTreeMap::new()));
+        let mut pruned = self.pruned.lock().unwrap();
+        pruned.retain(|tokens| tokens.strong_count() > 0);
+        pruned.push(Arc::downgrade(&tokens));
+        TokenCache {
+            tokens,
+            default_expiration_secs: self.default_expiration_secs,
+            refresh_margin_secs: self.refresh_margin_secs,
+            max_entries: self.max_entries,
+            clock: Arc::new(AtomicU64::new(0)),
+            store: None,
+            loaded: Arc::default(),
+            in_flight: Arc::default(),
+            pruned: self.pruned.clone(),
+        }
+    }
+
+    /// Loads the valid tokens of the store into the cache, unless already done. The
+    /// tokens obtained in the meantime are kept.
+    async fn load_store(&self) {
+        if self.store.is_none() {
+            return;
+        }
+        self.loaded
+            .get_or_init(|| async {
+                let stored = match self.run_store(|store| store.load()).await {
+                    Some(Ok(stored)) => stored,
+                    Some(Err(error)) => {
+                        warn!(?error, "Cannot load tokens from the token store");
+                        return;
+                    }
+                    None => return,
+                };
+                let loaded = self.entries_of(stored);
+                let mut tokens = self.tokens.write().await;
+                for (key, value) in loaded {
+                    tokens.entry(key).or_insert(value);
+                }
+                self.evict(&mut tokens);
+            })
+            .await;
+    }
+
+    /// Runs an operation of the persistent store, if any, on a thread where blocking is
+    /// allowed.
+    async fn run_store<T, F>(&self, operation: F) -> Option<Result<T>>
+    where
+        T: Send + 'static,
+        F: FnOnce(&dyn TokenStore) -> Result<T> + Send + 'static,
+    {
+        let store = self.store.clone()?;
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Ok(handle) = tokio::runtime::Handle::try_current() {
+            return Some(
+                handle
+                    .spawn_blocking(move || operation(store.as_ref()))
+                    .await
+                    .unwrap_or_else(|error| Err(std::io::Error::other(error).into())),
+            );
+        }
+        Some(operation(store.as_ref()))
+    }
+
+    /// Periodically removes the expired tokens of the cache and of its scoped caches,
+    /// until they are all dropped.
+    ///
+    /// Nothing is done when called outside of a tokio runtime.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) fn spawn_pruning(&self, interval: Duration) {
+        let handle = match tokio::runtime::Handle::try_current() {
+            Ok(handle) => handle,
+            Err(_) => {
+                warn!(
+                    "No tokio runtime available, expired tokens won't be pruned in the background"
+                );
+                return;
+            }
+        };
+        let pruned = self.pruned.clone();
+        handle.spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            // The first tick completes immediately
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                let caches: Vec<_> = {
+                    let mut pruned = pruned.lock().unwrap();
+                    pruned.retain(|tokens| tokens.strong_count() > 0);
+                    pruned.iter().filter_map(Weak::upgrade).collect()
+                };
+                if caches.is_empty() {
+                    break;
+                }
+                let now = now_epoch_secs();
+                for tokens in caches {
+                    prune_expired(&mut *tokens.write().await, now);
+                }
+            }
+        });
+    }
+
+    fn new_value(&self, token: RegistryTokenType, expiration: u64, now: u64) -> TokenCacheValue {
+        let lifetime = expiration.saturating_sub(now);
+        let margin = (self.refresh_margin_secs as u64).min(lifetime / 2);
+        TokenCacheValue {
+            token,
+            expiration,
+            refresh_at: expiration - margin,
+            last_used: AtomicU64::new(self.clock.fetch_add(1, Ordering::Relaxed)),
         }
     }
 
+    /// Evicts entries until the cache fits within its bounds, expired ones first.
+    fn evict(&self, tokens: &mut BTreeMap<TokenCacheKey, TokenCacheValue>) {
+        let max_entries = match self.max_entries {
+            Some(max_entries) if tokens.len() > max_entries => max_entries,
+            _ => return,
+        };
+        prune_expired(tokens, now_epoch_secs());
+        while tokens.len() > max_entries {
+            let lru = tokens
+                .iter()
+                .min_by_key(|(_, value)| value.last_used.load(Ordering::Relaxed))
+                .map(|(key, _)| key.clone());
+            match lru {
+                Some(key) => {
+                    debug!(%key.registry, %key.repository, ?key.operation, "Evicting token");
+                    tokens.remove(&key);
+                }
+                None => break,
+            }
+        }
+    }
+
+    /// The cache entries of the valid tokens read out of the persistent store.
+    ///
+    /// For JWT tokens, the earliest of the stored and the claimed expiration is kept.
+    fn entries_of(&self, stored: Vec<StoredToken>) -> Vec<(TokenCacheKey, TokenCacheValue)> {
+        let now = now_epoch_secs();
+        stored
+            .into_iter()
+            .filter_map(|mut t| {
+                let expiration = match parse_expiration_from_jwt(&t.token) {
+                    Some(claimed) => claimed.min(t.expiration),
+                    None => t.expiration,
+                };
+                let value = self.new_value(
+                    RegistryTokenType::Bearer(RegistryToken::new(std::mem::take(&mut t.token))),
+                    expiration,
+                    now,
+                );
+                if now > value.refresh_at {
+                    return None;
+                }
+                Some((
+                    TokenCacheKey {
+                        registry: std::mem::take(&mut t.registry),
+                        repository: std::mem::take(&mut t.repository),
+                        operation: t.operation,
+                        sources: std::mem::take(&mut t.sources),
+                    },
+                    value,
+                ))
+            })
+            .collect()
+    }
+
+    /// The expiration of a bearer token, in seconds since the Unix epoch.
+    ///
+    /// The `expires_in` field of the token response is preferred over the `exp` claim of
+    /// JWT tokens.
+    fn expiration_of(&self, token: &RegistryToken, now: u64) -> u64 {
+        if let Some(expiration) = token
+            .stated_expiration(now)
+            .or_else(|| parse_expiration_from_jwt(token.token()))
+        {
+            return expiration;
+        }
+        // The token doesn't state a value for the expiration. We assume it has a 60
+        // seconds validity as indicated here:
+        // https://docs.docker.com/reference/api/registry/auth/#token-response-fields
+        // > (Optional) The duration in seconds since the token was issued
+        // > that it will remain valid. When omitted, this defaults to 60 seconds.
+        // > For compatibility with older clients, a token should never be returned
+        // > with less than 60 seconds to live.
+        debug!(
+            "Cannot extract expiration from token, assuming a {} seconds validity",
+            self.default_expiration_secs
+        );
+        now + self.default_expiration_secs as u64
+    }
+
     pub(crate) async fn insert(
         &self,
         reference: &Reference,
         op: RegistryOperation,
         token: RegistryTokenType,
     ) {
+        self.insert_with_sources(reference, op, &[], token).await
+    }
+
+    /// Inserts a token also granting pull access to the given source repositories.
+    pub(crate) async fn insert_with_sources(
+        &self,
+        reference: &Reference,
+        op: RegistryOperation,
+        sources: &[String],
+        mut token: RegistryTokenType,
+    ) {
+        self.load_store().await;
+        let now = now_epoch_secs();
         let expiration = match token {
             RegistryTokenType::Basic(_, _) => u64::MAX,
-            RegistryTokenType::Bearer(ref t) => {
-                match parse_expiration_from_jwt(t.token(), self.default_expiration_secs) {
-                    Some(value) => value,
-                    None => return,
-                }
-            }
+            RegistryTokenType::Bearer(ref t) => self.expiration_of(t, now),
         };
-        let registry = reference.resolve_registry().to_string();
-        let repository = reference.repository().to_string();
-        debug!(%registry, %repository, ?op, %expiration, "Inserting token");
-        self.tokens.write().await.insert(
-            TokenCacheKey {
-                registry,
-                repository,
+        let key = TokenCacheKey::new(reference, op, sources);
+        debug!(%key.registry, %key.repository, ?key.operation, ?key.sources, %expiration, "Inserting token");
+        let stored = match (&self.store, &token) {
+            (Some(_), RegistryTokenType::Bearer(t)) => Some(StoredToken {
+                registry: key.registry.clone(),
+                repository: key.repository.clone(),
                 operation: op,
-            },
-            TokenCacheValue { token, expiration },
-        );
+                sources: key.sources.clone(),
+                token: t.token().to_string(),
+                expiration,
+            }),
+            _ => None,
+        };
+        let mut tokens = self.tokens.write().await;
+        if let RegistryTokenType::Bearer(t) = &mut token {
+            // Keep the refresh token of the previous token, unless a new one was issued
+            if t.refresh_token.is_none() {
+                t.refresh_token = tokens.get(&key).and_then(|v| match &v.token {
+                    RegistryTokenType::Bearer(previous) => previous.refresh_token.clone(),
+                    RegistryTokenType::Basic(_, _) => None,
+                });
+            }
+        }
+        let value = self.new_value(token, expiration, now);
+        tokens.insert(key, value);
+        self.evict(&mut tokens);
+        drop(tokens);
+
+        if let Some(stored) = stored {
+            if let Some(Err(error)) = self.run_store(move |store| store.store(stored)).await {
+                warn!(?error, "Cannot save token to the token store");
+            }
+        }
     }
 
+    /// Gets a token for `op` on `reference`, also granting pull access to the given
+    /// source repositories.
     pub(crate) async fn get(
         &self,
         reference: &Reference,
         op: RegistryOperation,
+        sources: &[String],
     ) -> Option<RegistryTokenType> {
-        let registry = reference.resolve_registry().to_string();
-        let repository = reference.repository().to_string();
-        let key = TokenCacheKey {
-            registry,
-            repository,
-            operation: op,
-        };
-        match self.tokens.read().await.get(&key) {
+        self.load_store().await;
+        self.get_cached(&TokenCacheKey::new(reference, op, sources))
+            .await
+    }
+
+    /// Waits until no other task is fetching the token for `op` on `reference`, and
+    /// prevents other tasks from doing so until the returned guard is dropped.
+    ///
+    /// The cache should be checked again once the lock is acquired, since the token
+    /// might have been obtained in the meantime.
+    pub(crate) async fn lock(
+        &self,
+        reference: &Reference,
+        op: RegistryOperation,
+        sources: &[String],
+    ) -> TokenFetchGuard {
+        let key = TokenCacheKey::new(reference, op, sources);
+        let lock = self
+            .in_flight
+            .lock()
+            .unwrap()
+            .entry(key.clone())
+            .or_default()
+            .clone();
+        let guard = lock.lock_owned().await;
+        TokenFetchGuard {
+            key,
+            in_flight: self.in_flight.clone(),
+            guard: Some(guard),
+        }
+    }
+
+    /// Gets the refresh token issued along with the cached token for `op` on `reference`,
+    /// even when the token itself has to be renewed.
+    pub(crate) async fn refresh_token(
+        &self,
+        reference: &Reference,
+        op: RegistryOperation,
+        sources: &[String],
+    ) -> Option<String> {
+        self.load_store().await;
+        let key = TokenCacheKey::new(reference, op, sources);
+        match &self.tokens.read().await.get(&key)?.token {
+            RegistryTokenType::Bearer(t) => t.refresh_token.clone(),
+            RegistryTokenType::Basic(_, _) => None,
+        }
+    }
+
+    async fn get_cached(&self, key: &TokenCacheKey) -> Option<RegistryTokenType> {
+        match self.tokens.read().await.get(key) {
             Some(TokenCacheValue {
                 ref token,
                 expiration,
+                refresh_at,
+                last_used,
             }) => {
-                let now = SystemTime::now();
-                let epoch = now
-                    .duration_since(UNIX_EPOCH)
-                    .expect("Time went backwards")
-                    .as_secs();
-                if epoch > *expiration {
+                let epoch = now_epoch_secs();
+                if epoch > *refresh_at {
                     debug!(%key.registry, %key.repository, ?key.operation, %expiration, miss=false, expired=true, "Fetching token");
                     None
                 } else {
                     debug!(%key.registry, %key.repository, ?key.operation, %expiration, miss=false, expired=false, "Fetching token");
+                    last_used.store(
+                        self.clock.fetch_add(1, Ordering::Relaxed),
+                        Ordering::Relaxed,
+                    );
                     Some(token.clone())
                 }
             }
@@ -156,9 +883,60 @@ impl TokenCache {
             }
         }
     }
+
+    /// Drops the tokens granting access to the given repository, for all the operations.
+    pub(crate) async fn invalidate(&self, registry: &str, repository: &str) {
+        debug!(%registry, %repository, "Invalidating tokens");
+        self.load_store().await;
+        self.tokens
+            .write()
+            .await
+            .retain(|key, _| !key.covers(registry, repository));
+        let (registry, repository) = (registry.to_string(), repository.to_string());
+        if let Some(Err(error)) = self
+            .run_store(move |store| store.remove(&registry, &repository))
+            .await
+        {
+            warn!(?error, "Cannot remove tokens from the token store");
+        }
+    }
+
+    /// Drops the tokens granting access to the repositories of the registry whose path
+    /// starts with the given prefix, or to all of them when the prefix is empty.
+    pub(crate) async fn invalidate_prefix(&self, registry: &str, prefix: &str) {
+        debug!(%registry, %prefix, "Invalidating tokens");
+        self.load_store().await;
+        self.tokens.write().await.retain(|key, _| {
+            !(key.registry == registry
+                && std::iter::once(&key.repository)
+                    .chain(&key.sources)
+                    .any(|r| under_prefix(r, prefix)))
+        });
+        // The store may hold tokens which were never loaded into the cache
+        let (registry, prefix) = (registry.to_string(), prefix.to_string());
+        if let Some(Err(error)) = self
+            .run_store(move |store| store.remove_prefix(&registry, &prefix))
+            .await
+        {
+            warn!(?error, "Cannot remove tokens from the token store");
+        }
+    }
+}
+
+/// Removes the entries which cannot be used anymore.
+fn prune_expired(tokens: &mut BTreeMap<TokenCacheKey, TokenCacheValue>, now: u64) {
+    tokens.retain(|_, value| now <= value.refresh_at);
+}
+
+fn now_epoch_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_secs()
 }
 
-fn parse_expiration_from_jwt(token_str: &str, default_expiration_secs: usize) -> Option<u64> {
+/// Reads the `exp` claim of a JWT token, returns `None` for opaque tokens.
+fn parse_expiration_from_jwt(token_str: &str) -> Option<u64> {
     // This might be able to change if/when jsonwebtoken provides a simpler API for
     // looking through jwt claims without validating the token.
     // See the following GitHub issue for more details:
@@ -175,34 +953,465 @@ fn parse_expiration_from_jwt(token_str: &str, default_expiration_secs: usize) ->
         &jsonwebtoken::DecodingKey::from_secret(&[]),
         &validation,
     ) {
-        Ok(token) => {
-            let token_exp = match token.claims.exp {
-                Some(exp) => exp,
-                None => {
-                    // the token doesn't have a claim that states a
-                    // value for the expiration. We assume it has a 60
-                    // seconds validity as indicated here:
-                    // https://docs.docker.com/reference/api/registry/auth/#token-response-fields
-                    // > (Optional) The duration in seconds since the token was issued
-                    // > that it will remain valid. When omitted, this defaults to 60 seconds.
-                    // > For compatibility with older clients, a token should never be returned
-                    // > with less than 60 seconds to live.
-                    let now = SystemTime::now();
-                    let epoch = now
-                        .duration_since(UNIX_EPOCH)
-                        .expect("Time went backwards")
-                        .as_secs();
-                    let expiration = epoch + default_expiration_secs as u64;
-                    debug!(?token, "Cannot extract expiration from token's claims, assuming a {} seconds validity", default_expiration_secs);
-                    expiration
-                }
-            };
-
-            Some(token_exp)
-        }
+        Ok(token) => token.claims.exp,
         Err(error) => {
-            warn!(?error, "Invalid bearer token");
+            debug!(?error, "Bearer token is not a JWT");
             None
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[derive(Serialize)]
+    struct Claims {
+        exp: u64,
+    }
+
+    fn jwt(exp: u64) -> String {
+        jsonwebtoken::encode(
+            &jsonwebtoken::Header::default(),
+            &Claims { exp },
+            &jsonwebtoken::EncodingKey::from_secret(b"some-secret"),
+        )
+        .unwrap()
+    }
+
+    fn stored(repository: &str, token: String, expiration: u64) -> StoredToken {
+        StoredToken {
+            registry: "registry.example.com".to_string(),
+            repository: repository.to_string(),
+            operation: RegistryOperation::Pull,
+            sources: Vec::new(),
+            token,
+            expiration,
+        }
+    }
+
+    #[test]
+    fn file_token_store_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FileTokenStore::new(dir.path().join("nested").join("tokens.json"));
+        assert!(store.load().unwrap().is_empty());
+
+        let exp = now_epoch_secs() + 300;
+        store.store(stored("a", jwt(exp), exp)).unwrap();
+        store.store(stored("b", jwt(exp), exp)).unwrap();
+        // Replaces the previous token of `a`
+        store.store(stored("a", jwt(exp + 1), exp + 1)).unwrap();
+
+        let mut tokens = store.load().unwrap();
+        tokens.sort_by(|l, r| l.repository.cmp(&r.repository));
+        assert_eq!(tokens.len(), 2);
+        assert_eq!(tokens[0].repository, "a");
+        assert_eq!(tokens[0].expiration, exp + 1);
+        assert_eq!(tokens[1].repository, "b");
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(store.path())
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+    }
+
+    #[tokio::test]
+    async fn token_cache_loads_valid_tokens_from_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = Arc::new(FileTokenStore::new(dir.path().join("tokens.json")));
+        let now = now_epoch_secs();
+        store
+            .store(stored("valid", jwt(now + 300), now + 300))
+            .unwrap();
+        // The JWT claim wins over a stale stored expiration
+        store
+            .store(stored("expired-claim", jwt(now - 10), now + 300))
+            .unwrap();
+        store
+            .store(stored("not-a-jwt", "opaque".into(), now + 300))
+            .unwrap();
+
+        let cache = TokenCache::new(60).with_store(store.clone());
+        assert!(cache
+            .get(&reference("valid"), RegistryOperation::Pull, &[])
+            .await
+            .is_some());
+        assert!(cache
+            .get(&reference("expired-claim"), RegistryOperation::Pull, &[])
+            .await
+            .is_none());
+        // Opaque tokens rely on the stored expiration
+        assert!(cache
+            .get(&reference("not-a-jwt"), RegistryOperation::Pull, &[])
+            .await
+            .is_some());
+
+        // Tokens obtained by another cache sharing the store are picked up by the next
+        // caches, the existing ones don't read the store again
+        let other = TokenCache::new(60).with_store(store.clone());
+        other
+            .insert(
+                &reference("late"),
+                RegistryOperation::Pull,
+                bearer(now + 300),
+            )
+            .await;
+        assert!(cache
+            .get(&reference("late"), RegistryOperation::Pull, &[])
+            .await
+            .is_none());
+        assert!(TokenCache::new(60)
+            .with_store(store)
+            .get(&reference("late"), RegistryOperation::Pull, &[])
+            .await
+            .is_some());
+    }
+
+    #[tokio::test]
+    async fn token_cache_loads_store_when_first_used() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = Arc::new(FileTokenStore::new(dir.path().join("tokens.json")));
+        let cache = TokenCache::new(60).with_store(store.clone());
+
+        // The store isn't read when the cache is created
+        let exp = now_epoch_secs() + 300;
+        store.store(stored("a", jwt(exp), exp)).unwrap();
+        assert!(cache
+            .get(&reference("a"), RegistryOperation::Pull, &[])
+            .await
+            .is_some());
+    }
+
+    fn bearer(exp: u64) -> RegistryTokenType {
+        RegistryTokenType::Bearer(RegistryToken::new(jwt(exp)))
+    }
+
+    fn reference(repository: &str) -> Reference {
+        Reference::try_from(format!("registry.example.com/{repository}:latest")).unwrap()
+    }
+
+    #[tokio::test]
+    async fn token_cache_renews_tokens_within_refresh_margin() {
+        let cache = TokenCache::new(60).with_refresh_margin(30);
+        let now = now_epoch_secs();
+        cache
+            .insert(
+                &reference("soon"),
+                RegistryOperation::Pull,
+                bearer(now + 20),
+            )
+            .await;
+        cache
+            .insert(
+                &reference("later"),
+                RegistryOperation::Pull,
+                bearer(now + 600),
+            )
+            .await;
+
+        // The margin is capped to half of the token lifetime
+        assert!(cache
+            .get(&reference("soon"), RegistryOperation::Pull, &[])
+            .await
+            .is_some());
+        let tokens = cache.tokens.read().await;
+        let soon = tokens.values().find(|v| v.expiration == now + 20).unwrap();
+        assert_eq!(soon.refresh_at, now + 10);
+        let later = tokens.values().find(|v| v.expiration == now + 600).unwrap();
+        assert_eq!(later.refresh_at, now + 570);
+    }
+
+    fn token_response(json: serde_json::Value) -> RegistryTokenType {
+        RegistryTokenType::Bearer(serde_json::from_value(json).unwrap())
+    }
+
+    async fn cached_expiration(cache: &TokenCache, repository: &str) -> u64 {
+        let key = TokenCacheKey::new(&reference(repository), RegistryOperation::Pull, &[]);
+        cache.tokens.read().await[&key].expiration
+    }
+
+    #[tokio::test]
+    async fn token_cache_expiration_sources() {
+        let cache = TokenCache::new(60);
+        let now = now_epoch_secs();
+        let issued_at = chrono::DateTime::from_timestamp(now as i64 - 100, 0).unwrap();
+        let tokens = [
+            (
+                "issued",
+                json!({ "token": "opaque", "expires_in": 300, "issued_at": issued_at }),
+            ),
+            // `expires_in` wins over the JWT claim
+            (
+                "jwt-and-expires-in",
+                json!({ "token": jwt(now + 600), "expires_in": 300 }),
+            ),
+            ("jwt", json!({ "token": jwt(now + 600) })),
+            ("opaque", json!({ "access_token": "opaque" })),
+        ];
+        for (repository, response) in tokens {
+            cache
+                .insert(
+                    &reference(repository),
+                    RegistryOperation::Pull,
+                    token_response(response),
+                )
+                .await;
+        }
+
+        assert_eq!(cached_expiration(&cache, "issued").await, now + 200);
+        assert_eq!(
+            cached_expiration(&cache, "jwt-and-expires-in").await,
+            now + 300
+        );
+        assert_eq!(cached_expiration(&cache, "jwt").await, now + 600);
+        assert_eq!(cached_expiration(&cache, "opaque").await, now + 60);
+    }
+
+    #[tokio::test]
+    async fn token_cache_keeps_refresh_token() {
+        let cache = TokenCache::new(60);
+        let op = RegistryOperation::Pull;
+        cache
+            .insert(
+                &reference("a"),
+                op,
+                token_response(json!({ "access_token": "first", "refresh_token": "refresh" })),
+            )
+            .await;
+        // A renewed token without a new refresh token keeps the previous one
+        cache
+            .insert(
+                &reference("a"),
+                op,
+                token_response(json!({ "access_token": "second" })),
+            )
+            .await;
+        assert_eq!(
+            cache
+                .refresh_token(&reference("a"), op, &[])
+                .await
+                .as_deref(),
+            Some("refresh")
+        );
+        assert!(cache
+            .refresh_token(&reference("b"), op, &[])
+            .await
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn token_cache_evicts_least_recently_used() {
+        let cache = TokenCache::new(60).with_max_entries(Some(2));
+        let exp = now_epoch_secs() + 300;
+        cache
+            .insert(&reference("a"), RegistryOperation::Pull, bearer(exp))
+            .await;
+        cache
+            .insert(&reference("b"), RegistryOperation::Pull, bearer(exp))
+            .await;
+        // Make `b` the least recently used entry
+        assert!(cache
+            .get(&reference("a"), RegistryOperation::Pull, &[])
+            .await
+            .is_some());
+        cache
+            .insert(&reference("c"), RegistryOperation::Pull, bearer(exp))
+            .await;
+
+        assert_eq!(cache.tokens.read().await.len(), 2);
+        assert!(cache
+            .get(&reference("a"), RegistryOperation::Pull, &[])
+            .await
+            .is_some());
+        assert!(cache
+            .get(&reference("b"), RegistryOperation::Pull, &[])
+            .await
+            .is_none());
+        assert!(cache
+            .get(&reference("c"), RegistryOperation::Pull, &[])
+            .await
+            .is_some());
+    }
+
+    #[tokio::test]
+    async fn token_cache_invalidate() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = Arc::new(FileTokenStore::new(dir.path().join("tokens.json")));
+        let cache = TokenCache::new(60).with_store(store.clone());
+        let exp = now_epoch_secs() + 300;
+        for op in [RegistryOperation::Pull, RegistryOperation::Push] {
+            cache.insert(&reference("a"), op, bearer(exp)).await;
+        }
+        cache
+            .insert(&reference("b"), RegistryOperation::Pull, bearer(exp))
+            .await;
+        // A mount token also covers its source repository
+        let sources = ["a".to_string()];
+        cache
+            .insert_with_sources(
+                &reference("c"),
+                RegistryOperation::Push,
+                &sources,
+                bearer(exp),
+            )
+            .await;
+        assert!(cache
+            .get(&reference("c"), RegistryOperation::Push, &[])
+            .await
+            .is_none());
+
+        cache.invalidate("registry.example.com", "a").await;
+
+        // The store must not bring the invalidated tokens back
+        let reloaded = TokenCache::new(60).with_store(store.clone());
+        for cache in [&cache, &reloaded] {
+            for op in [RegistryOperation::Pull, RegistryOperation::Push] {
+                assert!(cache.get(&reference("a"), op, &[]).await.is_none());
+            }
+            assert!(cache
+                .get(&reference("c"), RegistryOperation::Push, &sources)
+                .await
+                .is_none());
+            assert!(cache
+                .get(&reference("b"), RegistryOperation::Pull, &[])
+                .await
+                .is_some());
+        }
+        assert_eq!(store.load().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn token_cache_invalidate_prefix() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = Arc::new(FileTokenStore::new(dir.path().join("tokens.json")));
+        let cache = TokenCache::new(60).with_store(store.clone());
+        let exp = now_epoch_secs() + 300;
+        for repository in ["org/a", "org/b/c", "organization/d"] {
+            cache
+                .insert(&reference(repository), RegistryOperation::Pull, bearer(exp))
+                .await;
+        }
+        // Persisted by another client, and never loaded into the cache
+        store.store(stored("org/e", jwt(exp), exp)).unwrap();
+
+        cache.invalidate_prefix("registry.example.com", "org").await;
+
+        assert!(cache
+            .get(&reference("org/a"), RegistryOperation::Pull, &[])
+            .await
+            .is_none());
+        let remaining: Vec<_> = store
+            .load()
+            .unwrap()
+            .into_iter()
+            .map(|t| t.repository.clone())
+            .collect();
+        assert_eq!(remaining, vec!["organization/d".to_string()]);
+
+        // Through the default implementation as well
+        struct Tokens(Mutex<Vec<StoredToken>>);
+        impl TokenStore for Tokens {
+            fn load(&self) -> Result<Vec<StoredToken>> {
+                Ok(self.0.lock().unwrap().clone())
+            }
+            fn store(&self, token: StoredToken) -> Result<()> {
+                self.0.lock().unwrap().push(token);
+                Ok(())
+            }
+            fn remove(&self, registry: &str, repository: &str) -> Result<()> {
+                self.0.lock().unwrap().retain(|t| {
+                    !(t.registry == registry
+                        && (t.repository == repository
+                            || t.sources.iter().any(|s| s == repository)))
+                });
+                Ok(())
+            }
+        }
+        let store = Tokens(Mutex::new(vec![
+            stored("org/a", jwt(exp), exp),
+            StoredToken {
+                sources: vec!["org/b".to_string()],
+                ..stored("other", jwt(exp), exp)
+            },
+            stored("organization/d", jwt(exp), exp),
+        ]));
+        store.remove_prefix("registry.example.com", "org").unwrap();
+        let remaining = store.load().unwrap();
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining[0].repository, "organization/d");
+    }
+
+    #[tokio::test]
+    async fn token_cache_lock_is_released() {
+        let cache = TokenCache::new(60);
+        let op = RegistryOperation::Pull;
+        let guard = cache.lock(&reference("a"), op, &[]).await;
+        let waiter = {
+            let cache = cache.clone();
+            tokio::spawn(async move {
+                let _guard = cache.lock(&reference("a"), op, &[]).await;
+            })
+        };
+        // Other keys are not blocked
+        drop(cache.lock(&reference("b"), op, &[]).await);
+        assert!(!waiter.is_finished());
+
+        drop(guard);
+        waiter.await.unwrap();
+        assert!(cache.in_flight.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn prune_expired_tokens() {
+        let cache = TokenCache::new(60);
+        let now = now_epoch_secs();
+        let mut tokens = BTreeMap::new();
+        for (repository, expiration) in [("expired", now - 1), ("valid", now + 300)] {
+            tokens.insert(
+                TokenCacheKey {
+                    registry: "registry.example.com".to_string(),
+                    repository: repository.to_string(),
+                    operation: RegistryOperation::Pull,
+                    sources: Vec::new(),
+                },
+                cache.new_value(bearer(expiration), expiration, now),
+            );
+        }
+
+        prune_expired(&mut tokens, now);
+
+        assert_eq!(tokens.len(), 1);
+        assert!(tokens.keys().all(|key| key.repository == "valid"));
+    }
+
+    #[tokio::test]
+    async fn scoped_caches_share_the_pruning_task() {
+        let cache = TokenCache::new(60);
+        let dropped = cache.scoped();
+        drop(dropped);
+        let scoped = cache.scoped();
+        // The dropped cache is forgotten when another one is scoped
+        assert_eq!(cache.pruned.lock().unwrap().len(), 2);
+
+        let now = now_epoch_secs();
+        scoped.tokens.write().await.insert(
+            TokenCacheKey {
+                registry: "registry.example.com".to_string(),
+                repository: "expired".to_string(),
+                operation: RegistryOperation::Pull,
+                sources: Vec::new(),
+            },
+            scoped.new_value(bearer(now - 1), now - 1, now),
+        );
+        cache.spawn_pruning(Duration::from_millis(10));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        assert!(scoped.tokens.read().await.is_empty());
+    }
+}
@@ -0,0 +1,585 @@
+// Tests for the token flows, run against a mock registry and authorization server
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use axum::{
+    extract::{Form, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::IntoResponse,
+    routing::get,
+    Json, Router,
+};
+use futures_util::future::BoxFuture;
+use oci_client::{
+    client::{ClientConfig, ClientProtocol, DnsResolver},
+    secrets::{CredentialProvider, RegistryAuth},
+    Client, Reference,
+};
+use serde_json::json;
+use tokio::{net::TcpListener, task::JoinHandle};
+
+const IDENTITY_TOKEN: &str = "identity-token";
+const ACCESS_TOKEN: &str = "access-token";
+
+/// The query parameters of a request
+type QueryParams = Vec<(String, String)>;
+
+#[derive(Clone, Default)]
+struct ServerState {
+    /// Whether the authorization server supports the OAuth2 POST flow
+    oauth2: bool,
+    addr: String,
+    /// The token requests received, as `<method> <grant_type or user>`
+    token_requests: Arc<Mutex<Vec<String>>>,
+    /// The query parameters of the GET token requests
+    token_queries: Arc<Mutex<Vec<QueryParams>>>,
+    /// The number of requests to `/v2/`
+    pings: Arc<AtomicUsize>,
+}
+
+async fn ping_handler(State(state): State<ServerState>) -> impl IntoResponse {
+    state.pings.fetch_add(1, Ordering::SeqCst);
+    (
+        StatusCode::UNAUTHORIZED,
+        [(
+            header::WWW_AUTHENTICATE,
+            format!(
+                r#"Bearer realm="http://{}/token",service="mock-registry""#,
+                state.addr
+            ),
+        )],
+    )
+}
+
+async fn get_token_handler(
+    State(state): State<ServerState>,
+    Query(query): Query<QueryParams>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let authorization = headers
+        .get(header::AUTHORIZATION)
+        .map(|h| h.to_str().unwrap().to_string())
+        .unwrap_or_default();
+    state
+        .token_requests
+        .lock()
+        .unwrap()
+        .push(format!("GET {authorization}"));
+    state.token_queries.lock().unwrap().push(query.clone());
+    assert!(query.contains(&("service".to_string(), "mock-registry".to_string())));
+    let scopes: Vec<&str> = query
+        .iter()
+        .filter(|(name, _)| name == "scope")
+        .map(|(_, value)| value.as_str())
+        .collect();
+    Json(json!({
+        "token": format!("{ACCESS_TOKEN}:{}", scopes.join(" ")),
+        "expires_in": 300,
+    }))
+}
+
+async fn post_token_handler(
+    State(state): State<ServerState>,
+    Form(form): Form<HashMap<String, String>>,
+) -> impl IntoResponse {
+    if !state.oauth2 {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    state
+        .token_requests
+        .lock()
+        .unwrap()
+        .push(format!("POST {}", form["grant_type"]));
+    assert_eq!(form["service"], "mock-registry");
+    assert_eq!(form["client_id"], "oci-client");
+    let valid = match form["grant_type"].as_str() {
+        "refresh_token" => form["refresh_token"] == IDENTITY_TOKEN,
+        "password" => form["username"] == "user" && form["password"] == "pass",
+        _ => false,
+    };
+    if !valid {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    Json(json!({
+        "access_token": format!("{ACCESS_TOKEN}:{}", form["scope"]),
+        "refresh_token": IDENTITY_TOKEN,
+    }))
+    .into_response()
+}
+
+async fn tags_handler(headers: HeaderMap) -> impl IntoResponse {
+    match headers.get(header::AUTHORIZATION) {
+        Some(h)
+            if h.to_str()
+                .unwrap()
+                .starts_with(&format!("Bearer {ACCESS_TOKEN}")) =>
+        {
+            Json(json!({ "name": "busybox", "tags": ["latest"] })).into_response()
+        }
+        _ => StatusCode::UNAUTHORIZED.into_response(),
+    }
+}
+
+/// Requires a token for a scope the client cannot guess, from a realm of its own
+async fn scoped_tags_handler(
+    State(state): State<ServerState>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let granted = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix(&format!("Bearer {ACCESS_TOKEN}:")))
+        .is_some_and(|scopes| {
+            scopes
+                .split(' ')
+                .any(|s| s == "repository:scoped:pull,list")
+        });
+    if granted {
+        return Json(json!({ "name": "scoped", "tags": ["latest"] })).into_response();
+    }
+    (
+        StatusCode::UNAUTHORIZED,
+        [(
+            header::WWW_AUTHENTICATE,
+            format!(
+                r#"Bearer realm="http://{}/token?realm=scoped",service="mock-registry",scope="repository:scoped:pull,list",error="insufficient_scope""#,
+                state.addr
+            ),
+        )],
+    )
+        .into_response()
+}
+
+struct MockRegistry {
+    handle: JoinHandle<()>,
+    state: ServerState,
+}
+
+impl Drop for MockRegistry {
+    fn drop(&mut self) {
+        self.handle.abort()
+    }
+}
+
+impl MockRegistry {
+    async fn new(oauth2: bool) -> Self {
+        let addr = SocketAddr::from(([127, 0, 0, 1], 0));
+        let listener = TcpListener::bind(addr).await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let state = ServerState {
+            oauth2,
+            addr: format!("127.0.0.1:{port}"),
+            ..Default::default()
+        };
+
+        let app = Router::new()
+            .route("/v2/", get(ping_handler))
+            .route("/token", get(get_token_handler).post(post_token_handler))
+            .route("/v2/busybox/tags/list", get(tags_handler))
+            .route("/v2/scoped/tags/list", get(scoped_tags_handler))
+            .with_state(state.clone());
+        let handle = tokio::spawn(async move {
+            axum::serve(listener, app).await.unwrap();
+        });
+        Self { handle, state }
+    }
+
+    fn reference(&self) -> Reference {
+        self.reference_to("busybox")
+    }
+
+    fn reference_to(&self, repository: &str) -> Reference {
+        Reference::try_from(format!("{}/{repository}:latest", self.state.addr)).unwrap()
+    }
+
+    fn token_requests(&self) -> Vec<String> {
+        self.state.token_requests.lock().unwrap().clone()
+    }
+
+    fn token_queries(&self) -> Vec<QueryParams> {
+        self.state.token_queries.lock().unwrap().clone()
+    }
+}
+
+fn http_client(oauth2_password_grant: bool) -> Client {
+    Client::new(ClientConfig {
+        protocol: ClientProtocol::Http,
+        oauth2_password_grant,
+        allow_credentials_over_http: vec!["127.0.0.1".to_string()],
+        ..Default::default()
+    })
+}
+
+#[tokio::test]
+async fn test_reauthenticate_with_challenge_scope() {
+    let registry = MockRegistry::new(false).await;
+    let client = http_client(false);
+    let auth = RegistryAuth::Basic("user".to_string(), "pass".to_string());
+
+    let tags = client
+        .list_tags(&registry.reference_to("scoped"), &auth, None, None)
+        .await
+        .expect("list tags after re-authentication");
+
+    assert_eq!(tags.name, "scoped");
+    assert_eq!(registry.token_requests().len(), 2);
+
+    // The scope of the challenge is requested along with the scope of the operation
+    let scopes: Vec<_> = registry.token_queries()[1]
+        .iter()
+        .filter(|(name, _)| name == "scope")
+        .map(|(_, value)| value.clone())
+        .collect();
+    assert_eq!(
+        scopes,
+        vec!["repository:scoped:pull", "repository:scoped:pull,list"]
+    );
+}
+
+#[tokio::test]
+async fn test_challenges_are_cached_per_repository() {
+    let registry = MockRegistry::new(false).await;
+    let client = http_client(false);
+    let auth = RegistryAuth::Basic("user".to_string(), "pass".to_string());
+
+    for repository in ["scoped", "busybox"] {
+        client
+            .list_tags(&registry.reference_to(repository), &auth, None, None)
+            .await
+            .expect("list tags");
+    }
+
+    // The realm of the `scoped` repository isn't used for the other repositories
+    let from_scoped_realm: Vec<bool> = registry
+        .token_queries()
+        .iter()
+        .map(|query| query.contains(&("realm".to_string(), "scoped".to_string())))
+        .collect();
+    assert_eq!(from_scoped_realm, vec![false, true, false]);
+}
+
+#[tokio::test]
+async fn test_opaque_tokens_are_cached() {
+    let registry = MockRegistry::new(false).await;
+    let client = http_client(false);
+    let auth = RegistryAuth::Basic("user".to_string(), "pass".to_string());
+
+    for _ in 0..2 {
+        client
+            .list_tags(&registry.reference(), &auth, None, None)
+            .await
+            .expect("list tags");
+    }
+
+    assert_eq!(registry.token_requests().len(), 1);
+}
+
+#[tokio::test]
+async fn test_concurrent_requests_share_token() {
+    let registry = MockRegistry::new(false).await;
+    let client = http_client(false);
+    let auth = RegistryAuth::Basic("user".to_string(), "pass".to_string());
+    let reference = registry.reference();
+
+    let results = futures_util::future::join_all(
+        (0..8).map(|_| client.list_tags(&reference, &auth, None, None)),
+    )
+    .await;
+
+    assert!(results.iter().all(|r| r.is_ok()));
+    assert_eq!(registry.token_requests().len(), 1);
+    assert_eq!(registry.state.pings.load(Ordering::SeqCst), 1);
+
+    // The challenge of the registry is reused for other scopes
+    client
+        .list_tags(&registry.reference_to("scoped"), &auth, None, None)
+        .await
+        .expect("list tags of another repository");
+    assert_eq!(registry.state.pings.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn test_new_credentials_take_effect() {
+    let registry = MockRegistry::new(false).await;
+    let client = http_client(false);
+    let user = RegistryAuth::Basic("user".to_string(), "pass".to_string());
+    let robot = RegistryAuth::Basic("robot".to_string(), "secret".to_string());
+
+    for auth in [&user, &user, &robot] {
+        client
+            .list_tags(&registry.reference(), auth, None, None)
+            .await
+            .expect("list tags");
+    }
+
+    // The token obtained with the previous credentials is dropped
+    let requests = registry.token_requests();
+    assert_eq!(requests.len(), 2);
+    assert_ne!(requests[0], requests[1]);
+}
+
+#[tokio::test]
+async fn test_scoped_clients_are_isolated() {
+    let registry = MockRegistry::new(false).await;
+    let client = http_client(false);
+    let tenant = client.scoped();
+    let auth = RegistryAuth::Basic("user".to_string(), "pass".to_string());
+
+    client
+        .list_tags(&registry.reference(), &auth, None, None)
+        .await
+        .expect("list tags with the client");
+    tenant
+        .list_tags(&registry.reference(), &RegistryAuth::Anonymous, None, None)
+        .await
+        .expect("list tags with the scoped view");
+    client
+        .list_tags(&registry.reference(), &auth, None, None)
+        .await
+        .expect("list tags with the client");
+
+    // The view neither uses the credentials nor the token of the client
+    let requests = registry.token_requests();
+    assert_eq!(requests.len(), 2);
+    assert!(requests[0].starts_with("GET Basic "));
+    assert_eq!(requests[1], "GET ");
+    // Nor the challenge of the registry cached by the client
+    assert_eq!(registry.state.pings.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn test_https_with_http_fallback() {
+    let registry = MockRegistry::new(false).await;
+    let client = Client::new(ClientConfig {
+        protocol: ClientProtocol::HttpsWithHttpFallback(Vec::new()),
+        allow_credentials_over_http: vec!["127.0.0.1".to_string()],
+        ..Default::default()
+    });
+    let auth = RegistryAuth::Basic("user".to_string(), "pass".to_string());
+
+    let tags = client
+        .list_tags(&registry.reference(), &auth, None, None)
+        .await
+        .expect("list tags over HTTP");
+
+    assert_eq!(tags.tags, vec!["latest"]);
+}
+
+#[tokio::test]
+async fn test_dns_overrides() {
+    let registry = MockRegistry::new(false).await;
+    let port = registry.state.addr.rsplit_once(':').unwrap().1;
+    let reference = Reference::try_from(format!("registry.test:{port}/busybox:latest")).unwrap();
+    let auth = RegistryAuth::Basic("user".to_string(), "pass".to_string());
+
+    let client = Client::new(ClientConfig {
+        protocol: ClientProtocol::Http,
+        dns_overrides: HashMap::from([(
+            "registry.test".to_string(),
+            vec![SocketAddr::from(([127, 0, 0, 1], 0))],
+        )]),
+        allow_credentials_over_http: vec!["registry.test".to_string(), "127.0.0.1".to_string()],
+        ..Default::default()
+    });
+    let tags = client
+        .list_tags(&reference, &auth, None, None)
+        .await
+        .expect("list tags through the override");
+    assert_eq!(tags.tags, vec!["latest"]);
+
+    struct LoopbackResolver(AtomicUsize);
+
+    impl DnsResolver for LoopbackResolver {
+        fn resolve<'a>(
+            &'a self,
+            host: &'a str,
+        ) -> BoxFuture<'a, oci_client::errors::Result<Vec<SocketAddr>>> {
+            Box::pin(async move {
+                assert_eq!(host, "registry.test");
+                self.0.fetch_add(1, Ordering::SeqCst);
+                Ok(vec![SocketAddr::from(([127, 0, 0, 1], 0))])
+            })
+        }
+    }
+
+    let resolver = Arc::new(LoopbackResolver(AtomicUsize::new(0)));
+    let client = Client::new(ClientConfig {
+        protocol: ClientProtocol::Http,
+        dns_resolver: Some(resolver.clone()),
+        allow_credentials_over_http: vec!["registry.test".to_string(), "127.0.0.1".to_string()],
+        ..Default::default()
+    });
+    let tags = client
+        .list_tags(&reference, &auth, None, None)
+        .await
+        .expect("list tags through the resolver");
+    assert_eq!(tags.tags, vec!["latest"]);
+    assert!(resolver.0.load(Ordering::SeqCst) > 0);
+}
+
+#[tokio::test]
+async fn test_identity_token_oauth2_flow() {
+    let registry = MockRegistry::new(true).await;
+    let client = http_client(false);
+
+    let tags = client
+        .list_tags(
+            &registry.reference(),
+            &RegistryAuth::IdentityToken(IDENTITY_TOKEN.to_string()),
+            None,
+            None,
+        )
+        .await
+        .expect("list tags with an identity token");
+
+    assert_eq!(tags.tags, vec!["latest"]);
+    assert_eq!(registry.token_requests(), vec!["POST refresh_token"]);
+}
+
+#[tokio::test]
+async fn test_identity_token_falls_back_to_get() {
+    let registry = MockRegistry::new(false).await;
+    let client = http_client(false);
+
+    client
+        .list_tags(
+            &registry.reference(),
+            &RegistryAuth::IdentityToken(IDENTITY_TOKEN.to_string()),
+            None,
+            None,
+        )
+        .await
+        .expect("list tags with an identity token");
+
+    let requests = registry.token_requests();
+    assert_eq!(requests.len(), 1);
+    assert!(requests[0].starts_with("GET Basic "));
+}
+
+#[tokio::test]
+async fn test_basic_auth_password_grant() {
+    let registry = MockRegistry::new(true).await;
+    let auth = RegistryAuth::Basic("user".to_string(), "pass".to_string());
+
+    http_client(true)
+        .list_tags(&registry.reference(), &auth, None, None)
+        .await
+        .expect("list tags with the password grant");
+    assert_eq!(registry.token_requests(), vec!["POST password"]);
+
+    // Basic credentials keep using the GET flow by default
+    http_client(false)
+        .list_tags(&registry.reference(), &auth, None, None)
+        .await
+        .expect("list tags with the GET flow");
+    let requests = registry.token_requests();
+    assert_eq!(requests.len(), 2);
+    assert!(requests[1].starts_with("GET Basic "));
+}
+
+/// Hands out an expired token until told it has been rejected
+#[derive(Default)]
+struct RotatingProvider {
+    calls: AtomicUsize,
+    rejections: AtomicUsize,
+}
+
+impl CredentialProvider for RotatingProvider {
+    fn credentials<'a>(
+        &'a self,
+        _registry: &'a str,
+        repository: &'a str,
+    ) -> BoxFuture<'a, oci_client::errors::Result<Option<RegistryAuth>>> {
+        Box::pin(async move {
+            assert_eq!(repository, "busybox");
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            let token = if self.rejections.load(Ordering::SeqCst) == 0 {
+                "expired-token"
+            } else {
+                ACCESS_TOKEN
+            };
+            Ok(Some(RegistryAuth::Bearer(token.to_string())))
+        })
+    }
+
+    fn credentials_rejected(&self, _registry: &str, _repository: &str) {
+        self.rejections.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+#[tokio::test]
+async fn test_credential_provider_rotation() {
+    let registry = MockRegistry::new(true).await;
+    let provider = Arc::new(RotatingProvider::default());
+    let client = Client::new(ClientConfig {
+        protocol: ClientProtocol::Http,
+        credential_provider: Some(provider.clone()),
+        allow_credentials_over_http: vec!["127.0.0.1".to_string()],
+        ..Default::default()
+    });
+
+    let tags = client
+        .list_tags(&registry.reference(), &RegistryAuth::Anonymous, None, None)
+        .await
+        .expect("list tags with rotated credentials");
+
+    assert_eq!(tags.tags, vec!["latest"]);
+    assert_eq!(provider.calls.load(Ordering::SeqCst), 2);
+    assert_eq!(provider.rejections.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn test_explicit_credentials_take_precedence_over_provider() {
+    let registry = MockRegistry::new(false).await;
+    let provider = Arc::new(RotatingProvider::default());
+    let client = Client::new(ClientConfig {
+        protocol: ClientProtocol::Http,
+        credential_provider: Some(provider.clone()),
+        allow_credentials_over_http: vec!["127.0.0.1".to_string()],
+        ..Default::default()
+    });
+    let auth = RegistryAuth::Basic("user".to_string(), "pass".to_string());
+
+    client
+        .list_tags(&registry.reference(), &auth, None, None)
+        .await
+        .expect("list tags with the explicit credentials");
+
+    assert_eq!(provider.calls.load(Ordering::SeqCst), 0);
+    let requests = registry.token_requests();
+    assert_eq!(requests.len(), 1);
+    assert!(requests[0].starts_with("GET Basic "));
+}
+
+#[tokio::test]
+async fn test_scoped_clients_dont_use_the_credential_provider() {
+    let registry = MockRegistry::new(false).await;
+    let provider = Arc::new(RotatingProvider::default());
+    let client = Client::new(ClientConfig {
+        protocol: ClientProtocol::Http,
+        credential_provider: Some(provider.clone()),
+        allow_credentials_over_http: vec!["127.0.0.1".to_string()],
+        ..Default::default()
+    });
+
+    client
+        .scoped()
+        .list_tags(&registry.reference(), &RegistryAuth::Anonymous, None, None)
+        .await
+        .expect("list tags anonymously with the scoped view");
+    assert_eq!(provider.calls.load(Ordering::SeqCst), 0);
+    assert_eq!(registry.token_requests(), vec!["GET "]);
+
+    // A view can have a provider of its own
+    let own = Arc::new(RotatingProvider::default());
+    client
+        .scoped()
+        .with_credential_provider(own.clone())
+        .list_tags(&registry.reference(), &RegistryAuth::Anonymous, None, None)
+        .await
+        .expect("list tags with the provider of the scoped view");
+    assert_eq!(own.calls.load(Ordering::SeqCst), 2);
+    assert_eq!(provider.calls.load(Ordering::SeqCst), 0);
+}
@@ -0,0 +1,1263 @@
+// Tests running the client against the in-memory registry
+#![cfg(feature = "memory-registry")]
+
+use futures_util::TryStreamExt;
+use oci_client::{
+    capabilities::AuthScheme,
+    client::{BlobResponse, ClientConfig, ClientProtocol, Config, ImageLayer},
+    errors::OciDistributionError,
+    manifest::{self, OciDescriptor, OciImageManifest, OciManifest},
+    memory_registry::{MemoryRegistry, MemoryRegistryAuth, MemoryRegistryOptions},
+    quirks::RegistryQuirks,
+    secrets::RegistryAuth,
+    Client, Digester, RegistryOperation,
+};
+
+/// A client talking HTTP to the registries, which may receive credentials as they
+/// listen on the loopback interface
+fn http_client() -> Client {
+    Client::new(ClientConfig {
+        protocol: ClientProtocol::Http,
+        allow_credentials_over_http: vec!["127.0.0.1".to_string()],
+        ..Default::default()
+    })
+}
+
+fn image_layers() -> (Vec<ImageLayer>, Config) {
+    // Larger than a push chunk, so that it is pushed in several chunks
+    let large = (0..5 * 1024 * 1024)
+        .map(|i| (i % 251) as u8)
+        .collect::<Vec<_>>();
+    let layers = vec![
+        ImageLayer::new(large, manifest::WASM_LAYER_MEDIA_TYPE.to_string(), None),
+        ImageLayer::new(
+            b"hello".to_vec(),
+            manifest::WASM_LAYER_MEDIA_TYPE.to_string(),
+            None,
+        ),
+    ];
+    let config = Config::new(
+        b"{}".to_vec(),
+        manifest::WASM_CONFIG_MEDIA_TYPE.to_string(),
+        None,
+    );
+    (layers, config)
+}
+
+async fn roundtrip(client: &Client, registry: &MemoryRegistry, auth: &RegistryAuth) {
+    let image = registry.reference("org/hello:v1");
+    let (layers, config) = image_layers();
+
+    client
+        .push(&image, &layers, config.clone(), auth, None)
+        .await
+        .expect("push image");
+    let pulled = client
+        .pull(&image, auth, vec![manifest::WASM_LAYER_MEDIA_TYPE])
+        .await
+        .expect("pull image");
+
+    assert_eq!(pulled.config.data, config.data);
+    assert_eq!(pulled.layers.len(), layers.len());
+    // The layers are pulled concurrently, in any order
+    for pushed in &layers {
+        assert!(pulled.layers.iter().any(|layer| layer.data == pushed.data));
+    }
+    assert_eq!(registry.tags("org/hello"), vec!["v1"]);
+}
+
+#[tokio::test]
+async fn test_roundtrip_chunked() {
+    let registry = MemoryRegistry::start(MemoryRegistryOptions::default())
+        .await
+        .unwrap();
+
+    roundtrip(&http_client(), &registry, &RegistryAuth::Anonymous).await;
+
+    let patches = registry
+        .requests()
+        .iter()
+        .filter(|request| request.starts_with("PATCH "))
+        .count();
+    assert!(patches >= 3, "{:?}", registry.requests());
+}
+
+#[tokio::test]
+async fn test_roundtrip_monolithic() {
+    let registry = MemoryRegistry::start(MemoryRegistryOptions::default())
+        .await
+        .unwrap();
+    let client = Client::new(ClientConfig {
+        protocol: ClientProtocol::Http,
+        use_monolithic_push: true,
+        ..Default::default()
+    });
+
+    roundtrip(&client, &registry, &RegistryAuth::Anonymous).await;
+
+    assert!(!registry
+        .requests()
+        .iter()
+        .any(|request| request.starts_with("PATCH ")));
+}
+
+#[tokio::test]
+async fn test_roundtrip_basic_auth() {
+    let registry = MemoryRegistry::start(MemoryRegistryOptions {
+        auth: MemoryRegistryAuth::Basic {
+            username: "user".to_string(),
+            password: "pass".to_string(),
+        },
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+
+    let wrong = RegistryAuth::Basic("user".to_string(), "wrong".to_string());
+    http_client()
+        .list_tags(&registry.reference("hello"), &wrong, None, None)
+        .await
+        .expect_err("wrong credentials");
+
+    let auth = RegistryAuth::Basic("user".to_string(), "pass".to_string());
+    roundtrip(&http_client(), &registry, &auth).await;
+}
+
+#[tokio::test]
+async fn test_roundtrip_bearer_auth() {
+    for oauth2_password_grant in [false, true] {
+        let registry = MemoryRegistry::start(MemoryRegistryOptions {
+            auth: MemoryRegistryAuth::Bearer {
+                username: "user".to_string(),
+                password: "pass".to_string(),
+            },
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let client = Client::new(ClientConfig {
+            protocol: ClientProtocol::Http,
+            allow_credentials_over_http: vec!["127.0.0.1".to_string()],
+            oauth2_password_grant,
+            ..Default::default()
+        });
+
+        let auth = RegistryAuth::Basic("user".to_string(), "pass".to_string());
+        roundtrip(&client, &registry, &auth).await;
+
+        let method = if oauth2_password_grant { "POST" } else { "GET" };
+        assert!(registry
+            .requests()
+            .iter()
+            .any(|request| request.starts_with(&format!("{method} /token"))));
+    }
+}
+
+#[tokio::test]
+async fn test_mount_blob() {
+    let registry = MemoryRegistry::start(MemoryRegistryOptions {
+        auth: MemoryRegistryAuth::Bearer {
+            username: "user".to_string(),
+            password: "pass".to_string(),
+        },
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    let client = http_client();
+    let auth = RegistryAuth::Basic("user".to_string(), "pass".to_string());
+    let source = registry.reference("source:v1");
+    let target = registry.reference("target:v1");
+    let data = b"shared layer".to_vec();
+    let digest = oci_client::client::ImageLayer::new(
+        data.clone(),
+        manifest::WASM_LAYER_MEDIA_TYPE.to_string(),
+        None,
+    )
+    .sha256_digest();
+
+    client
+        .auth(&source, &auth, RegistryOperation::Push)
+        .await
+        .unwrap();
+    client.push_blob(&source, data, &digest).await.unwrap();
+    assert!(!client.blob_exists(&target, &digest).await.unwrap());
+
+    client
+        .mount_blob(&target, &source, &digest)
+        .await
+        .expect("mount blob");
+
+    assert!(client.blob_exists(&target, &digest).await.unwrap());
+    assert!(registry
+        .requests()
+        .iter()
+        .any(|request| request.contains("mount=") && request.starts_with("POST /v2/target/")));
+}
+
+#[tokio::test]
+async fn test_list_tags_pagination() {
+    let registry = MemoryRegistry::start(MemoryRegistryOptions::default())
+        .await
+        .unwrap();
+    let client = http_client();
+    let (layers, config) = image_layers();
+    for tag in ["a", "b", "c"] {
+        client
+            .push(
+                &registry.reference(&format!("hello:{tag}")),
+                &layers[1..],
+                config.clone(),
+                &RegistryAuth::Anonymous,
+                None,
+            )
+            .await
+            .unwrap();
+    }
+
+    let image = registry.reference("hello");
+    let all = client
+        .list_tags(&image, &RegistryAuth::Anonymous, None, None)
+        .await
+        .unwrap();
+    assert_eq!(all.tags, vec!["a", "b", "c"]);
+    assert_eq!(all.next, None);
+    let page = client
+        .list_tags(&image, &RegistryAuth::Anonymous, Some(1), Some("a"))
+        .await
+        .unwrap();
+    assert_eq!(page.tags, vec!["b"]);
+    assert_eq!(page.next.as_deref(), Some("b"));
+    let last = client
+        .list_tags(&image, &RegistryAuth::Anonymous, Some(1), Some("b"))
+        .await
+        .unwrap();
+    assert_eq!(last.tags, vec!["c"]);
+    assert_eq!(last.next, None);
+}
+
+#[tokio::test]
+async fn test_delete_manifest() {
+    let registry = MemoryRegistry::start(MemoryRegistryOptions::default())
+        .await
+        .unwrap();
+    let client = http_client();
+    let (layers, config) = image_layers();
+    for tag in ["a", "b"] {
+        client
+            .push(
+                &registry.reference(&format!("hello:{tag}")),
+                &layers[1..],
+                config.clone(),
+                &RegistryAuth::Anonymous,
+                None,
+            )
+            .await
+            .unwrap();
+    }
+
+    // Deleting a tag keeps the manifest and its other tags
+    client
+        .delete_manifest(&registry.reference("hello:a"), &RegistryAuth::Anonymous)
+        .await
+        .unwrap();
+    assert_eq!(registry.tags("hello"), vec!["b"]);
+    let digest = client
+        .fetch_manifest_digest(&registry.reference("hello:b"), &RegistryAuth::Anonymous)
+        .await
+        .unwrap();
+
+    // Deleting the manifest drops all of its tags
+    let image = registry.reference(&format!("hello@{digest}"));
+    client
+        .delete_manifest(&image, &RegistryAuth::Anonymous)
+        .await
+        .unwrap();
+    assert!(registry.tags("hello").is_empty());
+    let error = client
+        .delete_manifest(&image, &RegistryAuth::Anonymous)
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(
+            error,
+            oci_client::errors::OciDistributionError::RegistryError { .. }
+        ),
+        "{error}"
+    );
+}
+
+#[tokio::test]
+async fn test_referrers() {
+    let registry = MemoryRegistry::start(MemoryRegistryOptions::default())
+        .await
+        .unwrap();
+    let client = http_client();
+    let image = registry.reference("hello:v1");
+    let (layers, config) = image_layers();
+    client
+        .push(
+            &image,
+            &layers[1..],
+            config.clone(),
+            &RegistryAuth::Anonymous,
+            None,
+        )
+        .await
+        .unwrap();
+    let (_, digest) = client
+        .pull_manifest(&image, &RegistryAuth::Anonymous)
+        .await
+        .unwrap();
+
+    for artifact_type in [
+        "application/vnd.example.sbom",
+        "application/vnd.example.sig",
+    ] {
+        let mut manifest = OciImageManifest::build(&layers[1..], &config, None);
+        manifest.artifact_type = Some(artifact_type.to_string());
+        manifest.subject = Some(OciDescriptor {
+            media_type: manifest::OCI_IMAGE_MEDIA_TYPE.to_string(),
+            digest: digest.clone(),
+            ..Default::default()
+        });
+        let referrer = registry.reference(&format!("hello:{}", artifact_type.len()));
+        client
+            .push_manifest(&referrer, &OciManifest::Image(manifest))
+            .await
+            .unwrap();
+    }
+
+    let image = registry.reference(&format!("hello@{digest}"));
+    let referrers = client.pull_referrers(&image, None).await.unwrap();
+    assert_eq!(referrers.manifests.len(), 2);
+    let referrers = client
+        .pull_referrers(&image, Some("application/vnd.example.sig"))
+        .await
+        .unwrap();
+    assert_eq!(referrers.manifests.len(), 1);
+}
+
+#[tokio::test]
+async fn test_pull_blob_partial() {
+    let registry = MemoryRegistry::start(MemoryRegistryOptions::default())
+        .await
+        .unwrap();
+    let client = http_client();
+    let image = registry.reference("hello:v1");
+    let layer = ImageLayer::new(
+        b"0123456789".to_vec(),
+        manifest::WASM_LAYER_MEDIA_TYPE.to_string(),
+        None,
+    );
+    let digest = layer.sha256_digest();
+    client
+        .push_blob(&image, layer.data.clone(), &digest)
+        .await
+        .unwrap();
+    let descriptor = OciDescriptor {
+        digest,
+        size: 10,
+        ..Default::default()
+    };
+
+    let BlobResponse::Partial(stream) = client
+        .pull_blob_stream_partial(&image, &descriptor, 2, Some(3))
+        .await
+        .unwrap()
+    else {
+        panic!("expected a partial response");
+    };
+    let data = stream
+        .stream
+        .map_ok(|bytes| bytes.to_vec())
+        .try_concat()
+        .await
+        .unwrap();
+    assert_eq!(data, b"234");
+}
+
+#[tokio::test]
+async fn test_conformance() {
+    let registry = MemoryRegistry::start(MemoryRegistryOptions {
+        auth: MemoryRegistryAuth::Bearer {
+            username: "user".to_string(),
+            password: "pass".to_string(),
+        },
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    let auth = RegistryAuth::Basic("user".to_string(), "pass".to_string());
+
+    let report =
+        oci_client::conformance::run(&http_client(), &registry.reference("conformance"), &auth)
+            .await;
+
+    assert!(report.all_passed(), "{report}");
+    assert_eq!(report.outcomes.len(), 10);
+}
+
+#[tokio::test]
+async fn test_requests_per_second() {
+    let registry = MemoryRegistry::start(MemoryRegistryOptions::default())
+        .await
+        .unwrap();
+    let client = Client::new(ClientConfig {
+        protocol: ClientProtocol::Http,
+        max_concurrent_requests_per_registry: Some(1),
+        max_requests_per_second_per_registry: Some(20.0),
+        ..Default::default()
+    });
+    let (layers, config) = image_layers();
+
+    let start = std::time::Instant::now();
+    client
+        .push(
+            &registry.reference("hello:v1"),
+            &layers[1..],
+            config,
+            &RegistryAuth::Anonymous,
+            None,
+        )
+        .await
+        .unwrap();
+
+    let requests = registry.requests().len() as u32;
+    assert!(requests > 2, "{:?}", registry.requests());
+    assert!(start.elapsed() >= std::time::Duration::from_millis(50) * (requests - 1));
+}
+
+#[tokio::test]
+async fn test_bandwidth_throttling() {
+    let registry = MemoryRegistry::start(MemoryRegistryOptions::default())
+        .await
+        .unwrap();
+    let client = Client::new(ClientConfig {
+        protocol: ClientProtocol::Http,
+        max_bytes_per_second_by_registry: [(registry.registry().to_string(), 400_000)].into(),
+        ..Default::default()
+    });
+    let image = registry.reference("hello:v1");
+    let layer = ImageLayer::new(
+        vec![7; 100_000],
+        manifest::WASM_LAYER_MEDIA_TYPE.to_string(),
+        None,
+    );
+    let digest = layer.sha256_digest();
+
+    let start = std::time::Instant::now();
+    client
+        .push_blob(&image, layer.data.clone(), &digest)
+        .await
+        .unwrap();
+    let pushed = start.elapsed();
+    let descriptor = OciDescriptor {
+        digest,
+        size: 100_000,
+        ..Default::default()
+    };
+    let data = client
+        .pull_blob_stream(&image, &descriptor)
+        .await
+        .unwrap()
+        .stream
+        .map_ok(|bytes| bytes.to_vec())
+        .try_concat()
+        .await
+        .unwrap();
+
+    assert_eq!(data, layer.data);
+    // The first slice of each transfer is not held back
+    assert!(
+        pushed >= std::time::Duration::from_millis(200),
+        "{pushed:?}"
+    );
+    // The pull waits for the bandwidth reserved by the push, and then for the one
+    // reserved by its own chunks but the last one, which may be the only one
+    assert!(
+        start.elapsed() >= std::time::Duration::from_millis(250),
+        "{:?}",
+        start.elapsed()
+    );
+}
+
+/// The default quirks, changed by `change`
+fn quirks(change: impl FnOnce(&mut RegistryQuirks)) -> RegistryQuirks {
+    let mut quirks = RegistryQuirks::default();
+    change(&mut quirks);
+    quirks
+}
+
+fn client_with_quirks(registry: &MemoryRegistry, quirks: RegistryQuirks) -> Client {
+    Client::new(ClientConfig {
+        protocol: ClientProtocol::Http,
+        registry_quirks: [(registry.registry(), quirks)].into(),
+        ..Default::default()
+    })
+}
+
+#[tokio::test]
+async fn test_quirks_missing_manifest_location() {
+    let registry = MemoryRegistry::start(MemoryRegistryOptions {
+        omit_manifest_location: true,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    let image = registry.reference("hello:v1");
+    let (layers, config) = image_layers();
+    let manifest = OciManifest::Image(OciImageManifest::build(&layers[1..], &config, None));
+
+    let err = client_with_quirks(&registry, RegistryQuirks::strict())
+        .push_manifest(&image, &manifest)
+        .await
+        .unwrap_err();
+    assert!(matches!(err, OciDistributionError::RegistryNoLocationError));
+
+    let url = http_client()
+        .push_manifest(&image, &manifest)
+        .await
+        .unwrap();
+    assert!(url.contains("/v2/hello/manifests/sha256:"), "{url}");
+}
+
+#[tokio::test]
+async fn test_quirks_min_chunk_size() {
+    let registry = MemoryRegistry::start(MemoryRegistryOptions {
+        min_chunk_size: Some(1024 * 1024),
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    let client = http_client();
+    let image = registry.reference("hello:v1");
+    let data = (0..5 * 1024 * 1024)
+        .map(|i| (i % 251) as u8)
+        .collect::<Vec<_>>();
+    let digest = ImageLayer::new(data.clone(), String::new(), None).sha256_digest();
+
+    // Pieces smaller than the minimum are gathered into full chunks
+    let pieces = data
+        .chunks(100 * 1024)
+        .map(|piece| Ok(bytes::Bytes::copy_from_slice(piece)))
+        .collect::<Vec<_>>();
+    client
+        .push_blob_stream(&image, futures_util::stream::iter(pieces), &digest)
+        .await
+        .expect("push blob stream");
+    assert_eq!(registry.blob(&digest).unwrap(), data);
+
+    // The minimum size configured for the registry takes precedence when larger
+    let registry = MemoryRegistry::start(MemoryRegistryOptions::default())
+        .await
+        .unwrap();
+    let client = client_with_quirks(
+        &registry,
+        quirks(|quirks| quirks.min_chunk_size = Some(5 * 1024 * 1024)),
+    );
+    client
+        .push_blob(&registry.reference("hello:v1"), data, &digest)
+        .await
+        .unwrap();
+    let patches = registry
+        .requests()
+        .iter()
+        .filter(|request| request.starts_with("PATCH "))
+        .count();
+    assert_eq!(patches, 1);
+}
+
+#[tokio::test]
+async fn test_quirks_monolithic_push() {
+    let registry = MemoryRegistry::start(MemoryRegistryOptions::default())
+        .await
+        .unwrap();
+    let client = client_with_quirks(&registry, quirks(|quirks| quirks.monolithic_push = true));
+
+    roundtrip(&client, &registry, &RegistryAuth::Anonymous).await;
+
+    assert!(!registry
+        .requests()
+        .iter()
+        .any(|request| request.starts_with("PATCH ")));
+}
+
+#[tokio::test]
+async fn test_quirks_no_referrers_api() {
+    let registry = MemoryRegistry::start(MemoryRegistryOptions {
+        no_referrers_api: true,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    let client = client_with_quirks(&registry, quirks(|quirks| quirks.no_referrers_api = true));
+    let image = registry.reference("hello:v1");
+    let (layers, config) = image_layers();
+    client
+        .push(
+            &image,
+            &layers[1..],
+            config.clone(),
+            &RegistryAuth::Anonymous,
+            None,
+        )
+        .await
+        .unwrap();
+    let (_, digest) = client
+        .pull_manifest(&image, &RegistryAuth::Anonymous)
+        .await
+        .unwrap();
+
+    // The index of the referrers tag schema is maintained by the pushers of referrers
+    let mut referrers = Vec::new();
+    for artifact_type in [
+        "application/vnd.example.sbom",
+        "application/vnd.example.sig",
+    ] {
+        let mut manifest = OciImageManifest::build(&layers[1..], &config, None);
+        manifest.artifact_type = Some(artifact_type.to_string());
+        manifest.subject = Some(OciDescriptor {
+            media_type: manifest::OCI_IMAGE_MEDIA_TYPE.to_string(),
+            digest: digest.clone(),
+            ..Default::default()
+        });
+        let referrer = registry.reference(&format!("hello:{}", artifact_type.len()));
+        client
+            .push_manifest(&referrer, &OciManifest::Image(manifest))
+            .await
+            .unwrap();
+        let (body, referrer_digest) = client
+            .pull_manifest_raw(
+                &referrer,
+                &RegistryAuth::Anonymous,
+                &[manifest::OCI_IMAGE_MEDIA_TYPE],
+            )
+            .await
+            .unwrap();
+        referrers.push(serde_json::json!({
+            "mediaType": manifest::OCI_IMAGE_MEDIA_TYPE,
+            "digest": referrer_digest,
+            "size": body.len(),
+            "artifactType": artifact_type,
+        }));
+    }
+    assert!(!registry.tags("hello").contains(&digest.replace(':', "-")));
+    let index = serde_json::json!({
+        "schemaVersion": 2,
+        "mediaType": manifest::OCI_IMAGE_INDEX_MEDIA_TYPE,
+        "manifests": referrers,
+    });
+    client
+        .push_manifest_raw(
+            &registry.reference(&format!("hello:{}", digest.replace(':', "-"))),
+            serde_json::to_vec(&index).unwrap(),
+            manifest::OCI_IMAGE_INDEX_MEDIA_TYPE.parse().unwrap(),
+        )
+        .await
+        .unwrap();
+
+    let image = registry.reference(&format!("hello@{digest}"));
+    http_client()
+        .pull_referrers(&image, None)
+        .await
+        .expect_err("the registry has no referrers API");
+    let referrers = client.pull_referrers(&image, None).await.unwrap();
+    assert_eq!(referrers.manifests.len(), 2);
+    let referrers = client
+        .pull_referrers(&image, Some("application/vnd.example.sig"))
+        .await
+        .unwrap();
+    assert_eq!(referrers.manifests.len(), 1);
+}
+
+#[tokio::test]
+async fn test_quirks_no_tag_delete() {
+    let registry = MemoryRegistry::start(MemoryRegistryOptions {
+        no_tag_delete: true,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    let image = registry.reference("hello:v1");
+    let (layers, config) = image_layers();
+    for tag in ["v1", "v2"] {
+        http_client()
+            .push(
+                &registry.reference(&format!("hello:{tag}")),
+                &layers[1..],
+                config.clone(),
+                &RegistryAuth::Anonymous,
+                None,
+            )
+            .await
+            .unwrap();
+    }
+
+    http_client()
+        .delete_manifest(&image, &RegistryAuth::Anonymous)
+        .await
+        .expect_err("the registry doesn't delete tags");
+    let client = client_with_quirks(&registry, quirks(|quirks| quirks.no_tag_delete = true));
+    let requests = registry.requests().len();
+    let error = client
+        .delete_manifest(&image, &RegistryAuth::Anonymous)
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(error, OciDistributionError::UnsupportedTagDeleteError(_)),
+        "{error}"
+    );
+    // The registry isn't asked, and the manifest keeps all its tags
+    assert_eq!(registry.requests().len(), requests);
+    assert_eq!(registry.tags("hello"), vec!["v1", "v2"]);
+
+    // Deleting the manifest by digest is up to the caller
+    let digest = client
+        .fetch_manifest_digest(&image, &RegistryAuth::Anonymous)
+        .await
+        .unwrap();
+    client
+        .delete_manifest(
+            &registry.reference(&format!("hello@{digest}")),
+            &RegistryAuth::Anonymous,
+        )
+        .await
+        .unwrap();
+    assert!(registry.tags("hello").is_empty());
+}
+
+#[tokio::test]
+async fn test_quirks_docker_media_types_only() {
+    let registry = MemoryRegistry::start(MemoryRegistryOptions::default())
+        .await
+        .unwrap();
+    let client = client_with_quirks(
+        &registry,
+        quirks(|quirks| quirks.docker_media_types_only = true),
+    );
+    let image = registry.reference("hello:v1");
+    let layers = vec![ImageLayer::new(
+        b"layer".to_vec(),
+        manifest::IMAGE_LAYER_GZIP_MEDIA_TYPE.to_string(),
+        None,
+    )];
+    let config = Config::new(
+        b"{}".to_vec(),
+        manifest::IMAGE_CONFIG_MEDIA_TYPE.to_string(),
+        None,
+    );
+    client
+        .push(&image, &layers, config, &RegistryAuth::Anonymous, None)
+        .await
+        .unwrap();
+
+    let (manifest, _) = client
+        .pull_manifest(&image, &RegistryAuth::Anonymous)
+        .await
+        .unwrap();
+    let OciManifest::Image(manifest) = manifest else {
+        panic!("expected an image manifest");
+    };
+    assert_eq!(
+        manifest.media_type.as_deref(),
+        Some(manifest::IMAGE_MANIFEST_MEDIA_TYPE)
+    );
+    assert_eq!(
+        manifest.config.media_type,
+        manifest::IMAGE_DOCKER_CONFIG_MEDIA_TYPE
+    );
+    assert_eq!(
+        manifest.layers[0].media_type,
+        manifest::IMAGE_DOCKER_LAYER_GZIP_MEDIA_TYPE
+    );
+}
+
+#[tokio::test]
+async fn test_probe() {
+    let registry = MemoryRegistry::start(MemoryRegistryOptions {
+        auth: MemoryRegistryAuth::Bearer {
+            username: "user".to_string(),
+            password: "pass".to_string(),
+        },
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    let client = http_client();
+
+    let capabilities = client.probe(&registry.registry()).await.unwrap();
+    assert_eq!(capabilities.api_version.as_deref(), Some("registry/2.0"));
+    assert_eq!(
+        capabilities.auth,
+        AuthScheme::Bearer {
+            realm: format!("http://{}/token", registry.registry()),
+            service: Some("memory-registry".to_string()),
+        }
+    );
+    // The registry doesn't list its extensions without authentication
+    assert!(capabilities.extensions.is_empty());
+    assert_eq!(
+        client.capabilities(&registry.registry()),
+        Some(capabilities)
+    );
+
+    let address = registry.registry();
+    drop(registry);
+    client
+        .probe(&address)
+        .await
+        .expect_err("the registry is stopped");
+}
+
+#[tokio::test]
+async fn test_probe_repository() {
+    let registry = MemoryRegistry::start(MemoryRegistryOptions::default())
+        .await
+        .unwrap();
+    let client = http_client();
+    let image = registry.reference("hello:v1");
+
+    let capabilities = client
+        .probe_repository(&image, &RegistryAuth::Anonymous)
+        .await
+        .unwrap();
+    assert_eq!(capabilities.api_version.as_deref(), Some("registry/2.0"));
+    assert_eq!(capabilities.auth, AuthScheme::None);
+    assert_eq!(capabilities.extensions.len(), 1);
+    assert_eq!(capabilities.extensions[0].name, "_oci");
+    assert_eq!(capabilities.referrers_api, Some(true));
+    assert_eq!(capabilities.uploads, Some(true));
+    assert_eq!(capabilities.chunk_min_length, None);
+    assert_eq!(capabilities.blob_mount, None);
+    assert_eq!(capabilities.tag_delete, Some(true));
+    // The upload session has been cancelled
+    assert!(registry
+        .requests()
+        .iter()
+        .any(|request| request.starts_with("DELETE /v2/hello/blobs/uploads/")));
+
+    let digest = "sha256:44136fa355b3678a1146ad16f7e8649e94fb4fc21fe77e8310c060f61caaff8a";
+    client
+        .push_blob(&image, b"{}".to_vec(), digest)
+        .await
+        .unwrap();
+    let capabilities = client
+        .probe_repository(&image, &RegistryAuth::Anonymous)
+        .await
+        .unwrap();
+    assert_eq!(capabilities.blob_mount, Some(true));
+    assert!(registry.tags("hello").is_empty());
+}
+
+#[tokio::test]
+async fn test_probe_repository_quirks() {
+    let registry = MemoryRegistry::start(MemoryRegistryOptions {
+        no_referrers_api: true,
+        no_tag_delete: true,
+        min_chunk_size: Some(1024 * 1024),
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    let client = http_client();
+    let image = registry.reference("hello:v1");
+
+    let capabilities = client
+        .probe_repository(&image, &RegistryAuth::Anonymous)
+        .await
+        .unwrap();
+    assert_eq!(capabilities.referrers_api, Some(false));
+    assert_eq!(capabilities.chunk_min_length, Some(1024 * 1024));
+    assert_eq!(capabilities.tag_delete, Some(false));
+
+    // The workarounds are applied without first trying
+    let quirks = client.registry_quirks(&registry.registry());
+    assert!(quirks.no_referrers_api);
+    assert_eq!(quirks.min_chunk_size, Some(1024 * 1024));
+    let (layers, config) = image_layers();
+    client
+        .push(&image, &layers, config, &RegistryAuth::Anonymous, None)
+        .await
+        .unwrap();
+
+    // The probe doesn't change what deleting a tag does
+    assert!(!quirks.no_tag_delete);
+    client
+        .delete_manifest(&image, &RegistryAuth::Anonymous)
+        .await
+        .expect_err("the registry doesn't delete tags");
+    assert_eq!(registry.tags("hello"), vec!["v1"]);
+}
+
+#[tokio::test]
+async fn test_response_diagnostics() {
+    let registry = MemoryRegistry::start(MemoryRegistryOptions {
+        response_headers: vec![
+            ("ratelimit-limit".to_string(), "100;w=21600".to_string()),
+            ("ratelimit-remaining".to_string(), "76;w=21600".to_string()),
+            ("x-request-id".to_string(), "request-1".to_string()),
+            (
+                "warning".to_string(),
+                r#"299 - "This registry is deprecated""#.to_string(),
+            ),
+        ],
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    let client = http_client();
+    let image = registry.reference("hello:v1");
+    let (layers, config) = image_layers();
+
+    let response = client
+        .push(&image, &layers[1..], config, &RegistryAuth::Anonymous, None)
+        .await
+        .unwrap();
+    let rate_limit = response.diagnostics.rate_limit.unwrap();
+    assert_eq!(rate_limit.limit, Some(100));
+    assert_eq!(rate_limit.remaining, Some(76));
+    assert_eq!(
+        rate_limit.window,
+        Some(std::time::Duration::from_secs(21600))
+    );
+    assert_eq!(
+        response.diagnostics.request_id.as_deref(),
+        Some("request-1")
+    );
+    assert_eq!(
+        response.diagnostics.warnings,
+        vec!["This registry is deprecated"]
+    );
+
+    let pulled = client
+        .pull(
+            &image,
+            &RegistryAuth::Anonymous,
+            vec![manifest::WASM_LAYER_MEDIA_TYPE],
+        )
+        .await
+        .unwrap();
+    assert_eq!(pulled.diagnostics, response.diagnostics);
+    assert_eq!(
+        client.diagnostics(&registry.registry()),
+        Some(response.diagnostics)
+    );
+
+    let error = client
+        .pull_manifest(&registry.reference("missing:v1"), &RegistryAuth::Anonymous)
+        .await
+        .unwrap_err();
+    let diagnostics = error.diagnostics().expect("diagnostics of the error");
+    assert_eq!(diagnostics.request_id.as_deref(), Some("request-1"));
+}
+
+#[tokio::test]
+async fn test_error_classification() {
+    let registry = MemoryRegistry::start(MemoryRegistryOptions {
+        auth: MemoryRegistryAuth::Basic {
+            username: "user".to_string(),
+            password: "pass".to_string(),
+        },
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    let client = http_client();
+    let auth = RegistryAuth::Basic("user".to_string(), "pass".to_string());
+    let image = registry.reference("hello:v1");
+
+    let error = client.pull_manifest(&image, &auth).await.unwrap_err();
+    assert!(error.is_not_found(), "{error}");
+    assert!(!error.is_auth());
+    assert!(!error.is_retryable());
+    let context = error.context().unwrap();
+    assert_eq!(context.method, Some(reqwest::Method::GET));
+    assert_eq!(context.reference.as_deref(), Some(image.whole().as_str()));
+
+    let digest = "sha256:44136fa355b3678a1146ad16f7e8649e94fb4fc21fe77e8310c060f61caaff8a";
+    let error = client
+        .pull_blob(&image, digest, Vec::new())
+        .await
+        .unwrap_err();
+    assert!(error.is_not_found(), "{error}");
+    assert_eq!(error.context().unwrap().digest.as_deref(), Some(digest));
+
+    let wrong = RegistryAuth::Basic("user".to_string(), "wrong".to_string());
+    let error = http_client()
+        .pull_manifest(&image, &wrong)
+        .await
+        .unwrap_err();
+    assert!(error.is_auth(), "{error}");
+    assert!(!error.is_not_found());
+}
+
+#[tokio::test]
+async fn test_resolve_blob_url() {
+    let registry = MemoryRegistry::start(MemoryRegistryOptions {
+        auth: MemoryRegistryAuth::Bearer {
+            username: "user".to_string(),
+            password: "pass".to_string(),
+        },
+        redirect_blobs: true,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    let client = http_client();
+    let auth = RegistryAuth::Basic("user".to_string(), "pass".to_string());
+    client
+        .store_auth_if_needed(&registry.registry(), &auth)
+        .await;
+    let image = registry.reference("hello:v1");
+    let data = b"0123456789".to_vec();
+    let digest = ImageLayer::new(
+        data.clone(),
+        manifest::WASM_LAYER_MEDIA_TYPE.to_string(),
+        None,
+    )
+    .sha256_digest();
+    client
+        .push_blob(&image, data.clone(), &digest)
+        .await
+        .unwrap();
+    let descriptor = OciDescriptor {
+        digest: digest.clone(),
+        size: data.len() as i64,
+        ..Default::default()
+    };
+
+    let resolved = client.resolve_blob_url(&image, &descriptor).await.unwrap();
+    assert!(resolved.redirected);
+    let port = registry.registry().rsplit_once(':').unwrap().1.to_string();
+    let storage = format!("http://localhost:{port}/_storage/");
+    assert!(resolved.url.starts_with(&storage), "{}", resolved.url);
+    let expires_in = resolved.expires_at.unwrap() - chrono::Utc::now();
+    assert!(expires_in > chrono::Duration::minutes(14), "{expires_in}");
+    assert!(expires_in <= chrono::Duration::minutes(15), "{expires_in}");
+    // The storage wasn't requested
+    assert!(!registry
+        .requests()
+        .iter()
+        .any(|request| request.contains("/_storage/")));
+
+    // The blob is downloaded by another client and verified afterwards
+    let downloaded = reqwest::get(&resolved.url)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap()
+        .bytes()
+        .await
+        .unwrap();
+    let mut digester = Digester::new(&digest).unwrap();
+    digester.update(&downloaded);
+    digester.verify(&digest).unwrap();
+
+    // The client follows the redirect itself when pulling, without the credentials
+    let mut pulled = Vec::new();
+    client
+        .pull_blob(&image, &descriptor, &mut pulled)
+        .await
+        .unwrap();
+    assert_eq!(pulled, data);
+
+    let missing = OciDescriptor {
+        digest: "sha256:44136fa355b3678a1146ad16f7e8649e94fb4fc21fe77e8310c060f61caaff8a"
+            .to_string(),
+        ..Default::default()
+    };
+    let error = client.resolve_blob_url(&image, &missing).await.unwrap_err();
+    assert!(error.is_not_found(), "{error}");
+}
+
+#[tokio::test]
+async fn test_resolve_blob_url_without_redirect() {
+    let registry = MemoryRegistry::start(MemoryRegistryOptions::default())
+        .await
+        .unwrap();
+    let client = http_client();
+    let image = registry.reference("hello:v1");
+    let data = b"hello".to_vec();
+    let digest = ImageLayer::new(
+        data.clone(),
+        manifest::WASM_LAYER_MEDIA_TYPE.to_string(),
+        None,
+    )
+    .sha256_digest();
+    client.push_blob(&image, data, &digest).await.unwrap();
+    let descriptor = OciDescriptor {
+        digest: digest.clone(),
+        ..Default::default()
+    };
+
+    let resolved = client.resolve_blob_url(&image, &descriptor).await.unwrap();
+    assert!(!resolved.redirected);
+    assert_eq!(
+        resolved.url,
+        format!("http://{}/v2/hello/blobs/{digest}", registry.registry())
+    );
+    assert_eq!(resolved.expires_at, None);
+}
+
+#[tokio::test]
+async fn test_credentials_over_http() {
+    for (auth, oauth2_password_grant) in [
+        (
+            MemoryRegistryAuth::Basic {
+                username: "user".to_string(),
+                password: "pass".to_string(),
+            },
+            false,
+        ),
+        (
+            MemoryRegistryAuth::Bearer {
+                username: "user".to_string(),
+                password: "pass".to_string(),
+            },
+            false,
+        ),
+        (
+            MemoryRegistryAuth::Bearer {
+                username: "user".to_string(),
+                password: "pass".to_string(),
+            },
+            true,
+        ),
+    ] {
+        let registry = MemoryRegistry::start(MemoryRegistryOptions {
+            auth,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let image = registry.reference("hello:v1");
+        let auth = RegistryAuth::Basic("user".to_string(), "pass".to_string());
+
+        let client = Client::new(ClientConfig {
+            protocol: ClientProtocol::Http,
+            oauth2_password_grant,
+            ..Default::default()
+        });
+        let error = client.pull_manifest(&image, &auth).await.unwrap_err();
+        assert!(
+            matches!(error, OciDistributionError::InsecureCredentialsError(ref host) if *host == registry.registry()),
+            "{error}"
+        );
+        // Only the anonymous requests reached the registry
+        assert!(registry
+            .requests()
+            .iter()
+            .all(|request| request == "GET /v2/" || request.starts_with("GET /v2/hello/")));
+
+        let client = Client::new(ClientConfig {
+            protocol: ClientProtocol::Http,
+            allow_credentials_over_http: vec![registry.registry()],
+            oauth2_password_grant,
+            ..Default::default()
+        });
+        let error = client.pull_manifest(&image, &auth).await.unwrap_err();
+        assert!(error.is_not_found(), "{error}");
+    }
+}
+
+#[tokio::test]
+async fn test_redirect_drops_credentials() {
+    let registry = MemoryRegistry::start(MemoryRegistryOptions {
+        auth: MemoryRegistryAuth::Basic {
+            username: "user".to_string(),
+            password: "pass".to_string(),
+        },
+        redirect_blobs: true,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    let client = http_client();
+    let auth = RegistryAuth::Basic("user".to_string(), "pass".to_string());
+    client
+        .store_auth_if_needed(&registry.registry(), &auth)
+        .await;
+    let image = registry.reference("hello:v1");
+    let data = b"hello".to_vec();
+    let digest = ImageLayer::new(
+        data.clone(),
+        manifest::WASM_LAYER_MEDIA_TYPE.to_string(),
+        None,
+    )
+    .sha256_digest();
+    client
+        .push_blob(&image, data.clone(), &digest)
+        .await
+        .unwrap();
+    let descriptor = OciDescriptor {
+        digest,
+        ..Default::default()
+    };
+
+    // The storage the downloads are redirected to rejects the requests carrying the
+    // credentials of the registry
+    let pulled = client
+        .pull_blob_stream(&image, &descriptor)
+        .await
+        .unwrap()
+        .stream
+        .map_ok(|bytes| bytes.to_vec())
+        .try_concat()
+        .await
+        .unwrap();
+    assert_eq!(pulled, data);
+    assert!(registry
+        .requests()
+        .iter()
+        .any(|request| request.starts_with("GET /_storage/")));
+}
+
+#[tokio::test]
+async fn test_cross_host_redirect_strips_authorization() {
+    let registry = MemoryRegistry::start(MemoryRegistryOptions {
+        auth: MemoryRegistryAuth::Bearer {
+            username: "user".to_string(),
+            password: "pass".to_string(),
+        },
+        redirect_blobs: true,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    // The downloads are redirected to `localhost`, another host than the registry
+    assert!(registry.registry().starts_with("127.0.0.1:"));
+    let client = http_client();
+    let auth = RegistryAuth::Basic("user".to_string(), "pass".to_string());
+    let image = registry.reference("hello:v1");
+    client
+        .auth(&image, &auth, RegistryOperation::Push)
+        .await
+        .unwrap();
+    let data = b"hello".to_vec();
+    let digest = ImageLayer::new(
+        data.clone(),
+        manifest::WASM_LAYER_MEDIA_TYPE.to_string(),
+        None,
+    )
+    .sha256_digest();
+    client
+        .push_blob(&image, data.clone(), &digest)
+        .await
+        .unwrap();
+
+    // The storage rejects the requests carrying an `Authorization` header, such as the
+    // bearer token of the registry
+    let mut pulled = Vec::new();
+    client
+        .pull_blob(&image, digest.as_str(), &mut pulled)
+        .await
+        .unwrap();
+    assert_eq!(pulled, data);
+    let storage_requests = registry
+        .requests()
+        .iter()
+        .filter(|request| request.starts_with("GET /_storage/"))
+        .count();
+    assert_eq!(storage_requests, 1);
+}